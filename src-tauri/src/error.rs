@@ -0,0 +1,306 @@
+use crate::redact::redact;
+use crate::router::client::gemini_block_reason;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use serde_json::Value;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+/// Every failure a command can report, grouped by what the caller can do
+/// about it. Serialized as `{ kind, message, retry_after_ms }` so the UI can
+/// branch on `kind` and still show `message` as-is.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SophiaError {
+    /// Missing or rejected API key, or a provider that isn't configured.
+    Auth(String),
+    /// Too many requests; worth retrying, after `retry_after` if the provider said so.
+    RateLimited { message: String, retry_after: Option<Duration> },
+    /// Credits or billing limit exhausted. Retrying won't help until topped up.
+    QuotaExceeded(String),
+    /// Connection refused or reset, DNS failure, timeout.
+    Network(String),
+    /// The provider is having an outage (5xx, overloaded).
+    ProviderUnavailable { message: String, retry_after: Option<Duration> },
+    /// The provider refused the request itself (bad request, unknown model).
+    ProviderRejected(String),
+    /// The provider answered, but not with anything we could parse.
+    ProviderResponseInvalid(String),
+    /// The provider blocked the prompt or the reply.
+    ContentFiltered(String),
+    /// Every candidate provider was skipped or failed over without an answer.
+    NoProviderAvailable,
+    /// Only remote providers could take the request, but local-only mode is
+    /// on or network egress consent was not given.
+    EgressBlocked(String),
+    RuntimePaused,
+    /// The request with this ID was cancelled by the user or by a pause.
+    Cancelled(String),
+    /// Invalid runtime state transition or a poisoned lock.
+    Runtime(String),
+    /// Database, preference or secret store failure.
+    Storage(String),
+    /// Bad arguments from the caller (unknown provider, empty model, ...).
+    InvalidInput(String),
+}
+
+/// Error codes for exhausted credits rather than a short-term limit
+/// (OpenAI's `insufficient_quota`, Anthropic's `billing_error`).
+const QUOTA_CODES: &[&str] = &["insufficient_quota", "billing_error", "billing_hard_limit_reached"];
+/// Error codes for a request a safety filter blocked.
+const CONTENT_FILTER_CODES: &[&str] = &["content_filter", "content_policy_violation"];
+/// Error codes for a rejected key. Gemini sends `API_KEY_INVALID` with a 400
+/// rather than a 401.
+const INVALID_KEY_CODES: &[&str] = &["api_key_invalid", "invalid_api_key"];
+/// Secret store messages for a wrong, missing or still-needed passphrase.
+const SECRET_AUTH_MARKERS: &[&str] = &["incorrect master passphrase", "incorrect recovery passphrase", "passphrase is needed", "secret store is locked"];
+/// Secret store messages for a file that isn't there or isn't a recovery
+/// file, or a key with no secret stored.
+const SECRET_INPUT_MARKERS: &[&str] = &["recovery file not found", "not a secrets recovery file", "no secret stored for key"];
+
+/// The machine-readable codes in a provider's error body, lowercased:
+/// `error.code` and `error.type` (OpenAI, Anthropic, OpenRouter), and
+/// `error.status` and `error.details[].reason` (Gemini). Free-text messages
+/// are left out, so a word like "billing" in an unrelated error can't match.
+fn error_codes(json: &Value) -> Vec<String> {
+    let error = &json["error"];
+    let reasons = error["details"].as_array().into_iter().flatten().map(|detail| &detail["reason"]);
+    [&error["code"], &error["type"], &error["status"]].into_iter()
+        .chain(reasons)
+        .filter_map(Value::as_str)
+        .map(str::to_lowercase)
+        .collect()
+}
+
+fn contains_any(body: &str, markers: &[&str]) -> bool {
+    let body = body.to_lowercase();
+    markers.iter().any(|m| body.contains(m))
+}
+
+impl SophiaError {
+    /// Stable name of the variant, as sent to the frontend.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SophiaError::Auth(_) => "Auth",
+            SophiaError::RateLimited { .. } => "RateLimited",
+            SophiaError::QuotaExceeded(_) => "QuotaExceeded",
+            SophiaError::Network(_) => "Network",
+            SophiaError::ProviderUnavailable { .. } => "ProviderUnavailable",
+            SophiaError::ProviderRejected(_) => "ProviderRejected",
+            SophiaError::ProviderResponseInvalid(_) => "ProviderResponseInvalid",
+            SophiaError::ContentFiltered(_) => "ContentFiltered",
+            SophiaError::NoProviderAvailable => "NoProviderAvailable",
+            SophiaError::EgressBlocked(_) => "EgressBlocked",
+            SophiaError::RuntimePaused => "RuntimePaused",
+            SophiaError::Cancelled(_) => "Cancelled",
+            SophiaError::Runtime(_) => "Runtime",
+            SophiaError::Storage(_) => "Storage",
+            SophiaError::InvalidInput(_) => "InvalidInput",
+        }
+    }
+
+    /// Classifies a non-success HTTP response from a provider.
+    pub(crate) fn from_status(provider: &str, status: u16, body: &str, retry_after: Option<Duration>) -> Self {
+        let message = format!("{} API Error {}: {}", provider, status, body);
+        let json: Value = serde_json::from_str(body).unwrap_or(Value::Null);
+        let codes = error_codes(&json);
+        let has_code = |wanted: &[&str]| codes.iter().any(|code| wanted.contains(&code.as_str()));
+        match status {
+            401 | 403 => SophiaError::Auth(message),
+            402 => SophiaError::QuotaExceeded(message),
+            429 if has_code(QUOTA_CODES) => SophiaError::QuotaExceeded(message),
+            429 => SophiaError::RateLimited { message, retry_after },
+            500..=599 => SophiaError::ProviderUnavailable { message, retry_after },
+            _ if has_code(INVALID_KEY_CODES) => SophiaError::Auth(message),
+            _ if has_code(QUOTA_CODES) => SophiaError::QuotaExceeded(message),
+            _ if has_code(CONTENT_FILTER_CODES) || gemini_block_reason(&json).is_some() => SophiaError::ContentFiltered(message),
+            _ => SophiaError::ProviderRejected(message),
+        }
+    }
+
+    /// Maps a reqwest failure: undecodable bodies are invalid responses, anything
+    /// else (connect, timeout, broken body) is a network failure.
+    pub(crate) fn from_reqwest(provider: &str, err: reqwest::Error) -> Self {
+        if err.is_decode() {
+            let error = SophiaError::ProviderResponseInvalid(format!("Invalid {} response format: {}", provider, err));
+            log::error!("{}", error);
+            return error;
+        }
+        let error = SophiaError::Network(format!("{} API request failed: {}", provider, err));
+        log::error!("{}", error);
+        error
+    }
+
+    /// Classifies a secret store failure: a rejected passphrase or a locked
+    /// store is `Auth`, a missing or malformed file or an unknown key is
+    /// `InvalidInput`, and anything else (I/O, encryption) is `Storage`.
+    pub(crate) fn from_secret_store(message: String) -> Self {
+        if contains_any(&message, SECRET_AUTH_MARKERS) {
+            SophiaError::Auth(message)
+        } else if contains_any(&message, SECRET_INPUT_MARKERS) {
+            SophiaError::InvalidInput(message)
+        } else {
+            SophiaError::Storage(message)
+        }
+    }
+
+    /// Network failures, rate limits and outages may clear up on their own, so
+    /// the same provider is worth retrying.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            SophiaError::Network(_) | SophiaError::RateLimited { .. } | SophiaError::ProviderUnavailable { .. }
+        )
+    }
+
+    /// Whether another provider might succeed where this one failed. Keys and
+    /// quotas are per provider; bad requests, filters and parse errors would
+    /// likely fail the same way elsewhere, so the user should see them.
+    pub fn should_fail_over(&self) -> bool {
+        self.is_retryable() || matches!(self, SophiaError::Auth(_) | SophiaError::QuotaExceeded(_))
+    }
+
+    /// The `Retry-After` the provider sent with this failure, if any.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            SophiaError::RateLimited { retry_after, .. } => *retry_after,
+            SophiaError::ProviderUnavailable { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+/// Messages are redacted, so a key echoed back in a provider's error body or
+/// a request URL never reaches the logs or the frontend.
+impl fmt::Display for SophiaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            SophiaError::Auth(msg)
+            | SophiaError::QuotaExceeded(msg)
+            | SophiaError::Network(msg)
+            | SophiaError::ProviderRejected(msg)
+            | SophiaError::ProviderResponseInvalid(msg)
+            | SophiaError::ContentFiltered(msg)
+            | SophiaError::EgressBlocked(msg)
+            | SophiaError::Runtime(msg)
+            | SophiaError::Storage(msg)
+            | SophiaError::InvalidInput(msg) => msg.clone(),
+            SophiaError::RateLimited { message, .. } | SophiaError::ProviderUnavailable { message, .. } => message.clone(),
+            SophiaError::NoProviderAvailable => "No providers available".to_string(),
+            SophiaError::RuntimePaused => "Runtime is PAUSED. Request rejected.".to_string(),
+            SophiaError::Cancelled(request_id) => format!("Request {} was cancelled", request_id),
+        };
+        f.write_str(&redact(&message))
+    }
+}
+
+impl Error for SophiaError {}
+
+impl Serialize for SophiaError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("SophiaError", 3)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.serialize_field("retry_after_ms", &self.retry_after().map(|d| d.as_millis() as u64))?;
+        state.end()
+    }
+}
+
+impl From<rusqlite::Error> for SophiaError {
+    fn from(err: rusqlite::Error) -> Self {
+        SophiaError::Storage(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for SophiaError {
+    fn from(err: serde_json::Error) -> Self {
+        SophiaError::Storage(format!("Failed to serialize value: {}", err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_classification() {
+        assert_eq!(SophiaError::from_status("OpenAI", 401, "", None).kind(), "Auth");
+        let gemini_bad_key = r#"{"error":{"code":400,"message":"API key not valid. Please pass a valid API key.","status":"INVALID_ARGUMENT","details":[{"@type":"type.googleapis.com/google.rpc.ErrorInfo","reason":"API_KEY_INVALID"}]}}"#;
+        assert_eq!(SophiaError::from_status("Gemini", 400, gemini_bad_key, None).kind(), "Auth");
+        assert_eq!(SophiaError::from_status("OpenAI", 429, "{\"error\":{\"code\":\"insufficient_quota\"}}", None).kind(), "QuotaExceeded");
+        assert_eq!(SophiaError::from_status("OpenRouter", 402, "", None).kind(), "QuotaExceeded");
+        assert_eq!(SophiaError::from_status("OpenAI", 400, "{\"error\":{\"code\":\"content_filter\"}}", None).kind(), "ContentFiltered");
+        assert_eq!(SophiaError::from_status("DeepSeek", 400, "bad request", None).kind(), "ProviderRejected");
+        let anthropic_billing = r#"{"type":"error","error":{"type":"billing_error","message":"Your credit balance is too low"}}"#;
+        assert_eq!(SophiaError::from_status("Anthropic", 400, anthropic_billing, None).kind(), "QuotaExceeded");
+        let gemini_blocked = r#"{"promptFeedback":{"blockReason":"SAFETY"}}"#;
+        assert_eq!(SophiaError::from_status("Gemini", 400, gemini_blocked, None).kind(), "ContentFiltered");
+
+        let limited = SophiaError::from_status("Anthropic", 429, "slow down", Some(Duration::from_secs(3)));
+        assert_eq!(limited.kind(), "RateLimited");
+        assert_eq!(limited.retry_after(), Some(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn test_free_text_does_not_classify() {
+        // A bad safetySettings value is a bad request, not a filtered prompt
+        let safety_settings = r#"{"error":{"code":400,"message":"Invalid value at 'safety_settings[0].threshold'","status":"INVALID_ARGUMENT"}}"#;
+        assert_eq!(SophiaError::from_status("Gemini", 400, safety_settings, None).kind(), "ProviderRejected");
+
+        // "billing" or "quota" in an unrelated message mustn't trigger fail-over
+        let billing_field = r#"{"error":{"message":"Unrecognized request argument supplied: billing_tag","type":"invalid_request_error","code":null}}"#;
+        let error = SophiaError::from_status("OpenAI", 400, billing_field, None);
+        assert_eq!(error.kind(), "ProviderRejected");
+        assert!(!error.should_fail_over());
+        assert_eq!(SophiaError::from_status("OpenAI", 400, "quota and billing and safety", None).kind(), "ProviderRejected");
+
+        let rate_limit = r#"{"error":{"message":"Rate limit reached for requests per min. Limit: 3; quota resets shortly","type":"requests","code":"rate_limit_exceeded"}}"#;
+        assert_eq!(SophiaError::from_status("OpenAI", 429, rate_limit, None).kind(), "RateLimited");
+    }
+
+    #[test]
+    fn test_secret_store_classification() {
+        assert_eq!(SophiaError::from_secret_store("Incorrect recovery passphrase".into()).kind(), "Auth");
+        assert_eq!(SophiaError::from_secret_store("Incorrect master passphrase".into()).kind(), "Auth");
+        assert_eq!(SophiaError::from_secret_store("Recovery file not found: \"/tmp/x\"".into()).kind(), "InvalidInput");
+        assert_eq!(SophiaError::from_secret_store("Not a secrets recovery file".into()).kind(), "InvalidInput");
+        assert_eq!(SophiaError::from_secret_store("Secret store is locked".into()).kind(), "Auth");
+        assert_eq!(SophiaError::from_secret_store("No secret stored for key: openai_api_key".into()).kind(), "InvalidInput");
+        assert_eq!(SophiaError::from_secret_store("Failed to create temp file: denied".into()).kind(), "Storage");
+    }
+
+    #[test]
+    fn test_retry_and_fail_over() {
+        let unavailable = SophiaError::ProviderUnavailable { message: String::new(), retry_after: None };
+        assert!(unavailable.is_retryable());
+        assert!(SophiaError::Network("timeout".into()).is_retryable());
+        assert!(!SophiaError::Auth("bad key".into()).is_retryable());
+        assert!(SophiaError::Auth("bad key".into()).should_fail_over());
+        assert!(SophiaError::QuotaExceeded("no credits".into()).should_fail_over());
+        assert!(!SophiaError::ProviderRejected("bad request".into()).should_fail_over());
+        assert!(!SophiaError::ContentFiltered("blocked".into()).should_fail_over());
+        assert!(!SophiaError::ProviderResponseInvalid("bad json".into()).should_fail_over());
+    }
+
+    #[test]
+    fn test_serializes_kind_and_message() {
+        let json = serde_json::to_value(SophiaError::RateLimited {
+            message: "OpenAI API Error 429: slow down".into(),
+            retry_after: Some(Duration::from_millis(1500)),
+        }).unwrap();
+        assert_eq!(json["kind"], "RateLimited");
+        assert_eq!(json["message"], "OpenAI API Error 429: slow down");
+        assert_eq!(json["retry_after_ms"], 1500);
+
+        let json = serde_json::to_value(SophiaError::RuntimePaused).unwrap();
+        assert_eq!(json["kind"], "RuntimePaused");
+        assert!(json["retry_after_ms"].is_null());
+    }
+
+    #[test]
+    fn test_messages_are_redacted() {
+        crate::redact::register_secret("sk-error-test-secret");
+        let err = SophiaError::from_status("OpenAI", 401, "Incorrect API key provided: sk-error-test-secret", None);
+        assert_eq!(err.to_string(), "OpenAI API Error 401: Incorrect API key provided: [REDACTED]");
+        assert!(!serde_json::to_string(&err).unwrap().contains("sk-error-test-secret"));
+    }
+}
@@ -1,18 +1,33 @@
+pub mod error;
 pub mod runtime;
 pub mod storage;
 pub mod onboarding;
 pub mod router;
 pub mod secret_store;
 pub mod providers;
+pub mod redact;
 
+use error::SophiaError;
 use runtime::{RuntimeManager, RuntimeState};
+use runtime::audit::AuditLogger;
 use storage::StorageManager;
 use onboarding::OnboardingManager;
-use router::ModelRouter;
-use secret_store::SecretStore;
-use providers::{ProviderRegistry, ProviderType, ProviderConfig};
+use router::client::OllamaClient;
+use router::{AuthScheme, ChatMessage, ChatRole, ClassificationRule, ClassifierKind, EgressPolicy, GenerationParams, LocalModel, ModelInfo, ModelRouter, RetryPolicy, RouteTarget, RoutingTable, StreamChunk, TaskType};
+use secret_store::{open_backend, BackendKind, SecretInfo, SecretStore, SecretStoreStatus};
+use providers::{ModelCatalog, ProviderHealth, ProviderRegistry, ProviderType, ProviderConfig};
+use std::collections::BTreeMap;
 use std::sync::Arc;
-use tauri::{State, Manager};
+use tauri::{AppHandle, Emitter, State, Manager};
+
+/// Parses a provider ID from the frontend.
+fn parse_provider(provider: &str) -> Result<ProviderType, SophiaError> {
+    ProviderType::from_str(provider).ok_or_else(|| SophiaError::InvalidInput(format!("Unknown provider: {}", provider)))
+}
+
+fn lock_registry(registry: &std::sync::Mutex<ProviderRegistry>) -> Result<std::sync::MutexGuard<'_, ProviderRegistry>, SophiaError> {
+    registry.lock().map_err(|_| SophiaError::Runtime("Registry lock error".to_string()))
+}
 
 // --- Commands ---
 
@@ -22,18 +37,18 @@ fn get_runtime_state(state: State<RuntimeManager>) -> RuntimeState {
 }
 
 #[tauri::command]
-fn pause_runtime(state: State<RuntimeManager>) -> Result<(), String> {
-    state.pause()
+fn pause_runtime(state: State<RuntimeManager>) -> Result<(), SophiaError> {
+    state.pause().map_err(SophiaError::Runtime)
 }
 
 #[tauri::command]
-fn resume_runtime(state: State<RuntimeManager>) -> Result<(), String> {
-    state.resume()
+fn resume_runtime(state: State<RuntimeManager>) -> Result<(), SophiaError> {
+    state.resume().map_err(SophiaError::Runtime)
 }
 
 #[tauri::command]
-fn start_runtime(state: State<RuntimeManager>) -> Result<(), String> {
-    state.start()
+fn start_runtime(state: State<RuntimeManager>) -> Result<(), SophiaError> {
+    state.start().map_err(SophiaError::Runtime)
 }
 
 #[tauri::command]
@@ -51,13 +66,13 @@ fn complete_onboarding(
     gemini_key_id: String,
     gemini_key_value: String,
     network_egress_consent: bool,
-) -> Result<(), String> {
+) -> Result<(), SophiaError> {
     log::info!("Starting onboarding completion with key_id: {}", gemini_key_id);
 
-    let mut registry = provider_registry.lock().map_err(|_| "Registry lock error".to_string())?;
+    let mut registry = lock_registry(&provider_registry)?;
     
     log::info!("Setting API key in keychain...");
-    registry.set_api_key(&gemini_key_id, &gemini_key_value)?;
+    registry.set_api_key(&gemini_key_id, gemini_key_value).map_err(SophiaError::Storage)?;
     log::info!("API key stored successfully");
     
     registry.set_provider_enabled(&ProviderType::Gemini, true);
@@ -65,8 +80,7 @@ fn complete_onboarding(
 
     if let Some(config) = registry.get_provider_config(&ProviderType::Gemini) {
         log::info!("Saving provider config with keychain_id: {}", config.api_key_keychain_id);
-        storage.set_preference(&ProviderType::Gemini.preference_key(), serde_json::to_value(config).map_err(|e| e.to_string())?)
-            .map_err(|e| e.to_string())?;
+        storage.set_preference(&ProviderType::Gemini.preference_key(), serde_json::to_value(config)?)?;
     }
 
     state.accept_contract(
@@ -74,32 +88,47 @@ fn complete_onboarding(
         &contract_hash,
         &gemini_key_id,
         network_egress_consent,
-    )
+    ).map_err(SophiaError::Storage)
 }
 
+/// Stores a provider's API key and enables the provider. With `verify` the
+/// key is checked first, and a key the provider rejects is neither stored nor
+/// enabled; one that can't be checked (provider unreachable or blocked) is.
 #[tauri::command]
-fn save_provider_key(
+async fn save_provider_key(
     provider_registry: State<'_, Arc<std::sync::Mutex<ProviderRegistry>>>,
     storage: State<'_, Arc<StorageManager>>,
+    router: State<'_, ModelRouter>,
     provider: String,
-    api_key: String
-) -> Result<(), String> {
-    let provider_type = ProviderType::from_str(&provider).ok_or("Unknown provider")?;
-    let mut registry = provider_registry.lock().map_err(|_| "Registry lock error".to_string())?;
+    api_key: String,
+    verify: Option<bool>
+) -> Result<(), SophiaError> {
+    let provider_type = parse_provider(&provider)?;
+
+    if verify.unwrap_or(false) {
+        let health = router.check_provider_health(&provider_type, Some(api_key.trim())).await?;
+        if health.reachable && !health.auth_ok {
+            return Err(SophiaError::Auth(format!(
+                "{} rejected the key: {}",
+                provider, health.error.unwrap_or_default()
+            )));
+        }
+    }
+
+    let mut registry = lock_registry(&provider_registry)?;
     
     // Get the keychain ID before modifying
     let keychain_id = registry.get_provider_config(&provider_type)
-        .ok_or("Provider not configured")?
+        .ok_or_else(|| SophiaError::InvalidInput(format!("Provider not configured: {}", provider)))?
         .api_key_keychain_id.clone();
 
     // Set the key and enable the provider
-    registry.set_api_key(&keychain_id, &api_key)?;
+    registry.set_api_key(&keychain_id, api_key).map_err(SophiaError::Storage)?;
     registry.set_provider_enabled(&provider_type, true);
     
     // Save the updated config to storage
     if let Some(config) = registry.get_provider_config(&provider_type) {
-        storage.set_preference(&provider_type.preference_key(), serde_json::to_value(config).map_err(|e| e.to_string())?)
-            .map_err(|e| e.to_string())?;
+        storage.set_preference(&provider_type.preference_key(), serde_json::to_value(config)?)?;
     }
 
     Ok(())
@@ -111,65 +140,645 @@ fn update_provider_model(
     storage: State<'_, Arc<StorageManager>>,
     provider: String,
     model: String
-) -> Result<(), String> {
-    let provider_type = ProviderType::from_str(&provider).ok_or("Unknown provider")?;
-    let mut registry = provider_registry.lock().map_err(|_| "Registry lock error".to_string())?;
-
-    if let Some(config) = registry.get_provider_config_mut(&provider_type) {
-        config.model = model.clone();
-        storage.set_preference(&provider_type.preference_key(), serde_json::to_value(config).map_err(|e| e.to_string())?)
-            .map_err(|e| e.to_string())?;
+) -> Result<(), SophiaError> {
+    let provider_type = parse_provider(&provider)?;
+    let model = model.trim().to_string();
+    if model.is_empty() {
+        return Err(SophiaError::InvalidInput("Model name cannot be empty".to_string()));
+    }
+
+    ModelCatalog::validate_model(&storage, &provider_type, &model)?;
+
+    let mut registry = lock_registry(&provider_registry)?;
+
+    let config = registry.get_provider_config_mut(&provider_type)
+        .ok_or_else(|| SophiaError::InvalidInput(format!("Unknown provider: {}", provider)))?;
+    config.model = model.clone();
+    storage.set_preference(&provider_type.preference_key(), serde_json::to_value(config)?)?;
+
+    Ok(())
+}
+
+#[tauri::command]
+fn update_provider_endpoint(
+    provider_registry: State<'_, Arc<std::sync::Mutex<ProviderRegistry>>>,
+    storage: State<'_, Arc<StorageManager>>,
+    provider: String,
+    endpoint: String
+) -> Result<(), SophiaError> {
+    let provider_type = parse_provider(&provider)?;
+    let endpoint = endpoint.trim().trim_end_matches('/').to_string();
+    reqwest::Url::parse(&endpoint)
+        .map_err(|e| SophiaError::InvalidInput(format!("Invalid endpoint '{}': {}", endpoint, e)))?;
+    let mut registry = lock_registry(&provider_registry)?;
+
+    let config = registry.get_provider_config_mut(&provider_type)
+        .ok_or_else(|| SophiaError::InvalidInput(format!("Unknown provider: {}", provider)))?;
+    log::info!("Using endpoint {} for {}", endpoint, provider);
+    config.endpoint = endpoint;
+    storage.set_preference(&provider_type.preference_key(), serde_json::to_value(config)?)?;
+
+    Ok(())
+}
+
+/// Models installed on the configured Ollama server, for the model picker.
+#[tauri::command]
+async fn list_local_models(
+    provider_registry: State<'_, Arc<std::sync::Mutex<ProviderRegistry>>>,
+) -> Result<Vec<LocalModel>, SophiaError> {
+    // Listing works even while Ollama is disabled, so it can be set up first
+    let client = {
+        let registry = lock_registry(&provider_registry)?;
+        let endpoint = registry.get_provider_config(&ProviderType::Ollama)
+            .map(|c| c.endpoint.clone())
+            .unwrap_or_else(|| ProviderConfig::default_ollama().endpoint);
+        OllamaClient::new(&endpoint)
+    };
+
+    client.list_local_models().await
+}
+
+/// The provider's model list, from the cache unless it has expired or `refresh` is set.
+#[tauri::command]
+async fn list_models(
+    router: State<'_, ModelRouter>,
+    provider: String,
+    refresh: Option<bool>
+) -> Result<Vec<ModelInfo>, SophiaError> {
+    router.list_models(&parse_provider(&provider)?, refresh.unwrap_or(false)).await
+}
+
+/// Makes a minimal authenticated call to the provider. With `api_key` the
+/// candidate key is checked before it is saved; otherwise the stored one is.
+#[tauri::command]
+async fn check_provider_health(
+    router: State<'_, ModelRouter>,
+    provider: String,
+    api_key: Option<String>
+) -> Result<ProviderHealth, SophiaError> {
+    router.check_provider_health(&parse_provider(&provider)?, api_key.as_deref().map(str::trim)).await
+}
+
+/// Preference listing the IDs of user-defined providers.
+const CUSTOM_PROVIDERS_KEY: &str = "custom_providers";
+
+fn save_custom_provider_ids(storage: &StorageManager, registry: &ProviderRegistry) -> Result<(), SophiaError> {
+    let ids: Vec<String> = registry.custom_providers().iter().map(|p| p.as_str().to_string()).collect();
+    storage.set_preference(CUSTOM_PROVIDERS_KEY, serde_json::to_value(ids)?)?;
+    Ok(())
+}
+
+/// Adds an OpenAI-compatible provider (LM Studio, vLLM, Groq, ...). Its ID is
+/// derived from `name` and is what the other provider commands take.
+#[tauri::command]
+fn add_custom_provider(
+    provider_registry: State<'_, Arc<std::sync::Mutex<ProviderRegistry>>>,
+    storage: State<'_, Arc<StorageManager>>,
+    name: String,
+    endpoint: String,
+    model: String,
+    auth_scheme: Option<AuthScheme>,
+    extra_headers: Option<BTreeMap<String, String>>,
+    api_key: Option<String>
+) -> Result<ProviderConfig, SophiaError> {
+    let id = ProviderType::custom_id(&name)
+        .ok_or_else(|| SophiaError::InvalidInput(format!("'{}' can't be used as a provider name", name)))?;
+    let endpoint = endpoint.trim().trim_end_matches('/').to_string();
+    reqwest::Url::parse(&endpoint)
+        .map_err(|e| SophiaError::InvalidInput(format!("Invalid endpoint '{}': {}", endpoint, e)))?;
+    let model = model.trim().to_string();
+    if model.is_empty() {
+        return Err(SophiaError::InvalidInput("Model name cannot be empty".to_string()));
+    }
+
+    let auth_scheme = auth_scheme.unwrap_or_default();
+    if let AuthScheme::Header(header) = &auth_scheme {
+        reqwest::header::HeaderName::from_bytes(header.as_bytes())
+            .map_err(|_| SophiaError::InvalidInput(format!("Invalid auth header name: {}", header)))?;
+    }
+    let extra_headers = extra_headers.unwrap_or_default();
+    for (header, value) in &extra_headers {
+        reqwest::header::HeaderName::from_bytes(header.as_bytes())
+            .map_err(|_| SophiaError::InvalidInput(format!("Invalid header name: {}", header)))?;
+        reqwest::header::HeaderValue::from_str(value)
+            .map_err(|_| SophiaError::InvalidInput(format!("Invalid value for header {}", header)))?;
+    }
+
+    let mut config = ProviderConfig::custom(&id, name.trim(), &endpoint, &model, auth_scheme, extra_headers);
+    let mut registry = lock_registry(&provider_registry)?;
+    if let Some(key) = api_key.as_deref().map(str::trim).filter(|k| !k.is_empty()) {
+        registry.set_api_key(&config.api_key_keychain_id, key).map_err(SophiaError::Storage)?;
+        config.enabled = true;
+    }
+    registry.add_custom_provider(config.clone()).map_err(SophiaError::InvalidInput)?;
+
+    storage.set_preference(&config.provider.preference_key(), serde_json::to_value(&config)?)?;
+    save_custom_provider_ids(&storage, &registry)?;
+    log::info!("Added custom provider {} at {}", id, endpoint);
+    Ok(config)
+}
+
+#[tauri::command]
+fn remove_custom_provider(
+    provider_registry: State<'_, Arc<std::sync::Mutex<ProviderRegistry>>>,
+    storage: State<'_, Arc<StorageManager>>,
+    provider: String
+) -> Result<(), SophiaError> {
+    let provider_type = parse_provider(&provider)?;
+    let mut registry = lock_registry(&provider_registry)?;
+
+    let config = registry.remove_custom_provider(&provider_type)
+        .ok_or_else(|| SophiaError::InvalidInput(format!("{} is not a custom provider", provider)))?;
+    if config.requires_key() {
+        if let Err(e) = registry.delete_api_key(&config.api_key_keychain_id) {
+            log::warn!("Failed to delete key for removed provider {}: {}", provider, e);
+        }
     }
+    storage.delete_preference(&provider_type.preference_key())?;
+    save_custom_provider_ids(&storage, &registry)?;
+    log::info!("Removed custom provider {}", provider);
+    Ok(())
+}
+
+#[tauri::command]
+fn list_custom_providers(
+    provider_registry: State<'_, Arc<std::sync::Mutex<ProviderRegistry>>>,
+) -> Result<Vec<ProviderConfig>, SophiaError> {
+    let registry = lock_registry(&provider_registry)?;
+    Ok(registry.custom_providers().iter()
+        .filter_map(|p| registry.get_provider_config(p).cloned())
+        .collect())
+}
+
+/// Sets the system prompt and sampling settings sent with every request to
+/// this provider. `submit_prompt` may override them per request.
+#[tauri::command]
+fn update_provider_generation(
+    provider_registry: State<'_, Arc<std::sync::Mutex<ProviderRegistry>>>,
+    storage: State<'_, Arc<StorageManager>>,
+    provider: String,
+    generation: GenerationParams
+) -> Result<(), SophiaError> {
+    let provider_type = parse_provider(&provider)?;
+    generation.validate().map_err(SophiaError::InvalidInput)?;
+    let mut registry = lock_registry(&provider_registry)?;
+
+    let config = registry.get_provider_config_mut(&provider_type)
+        .ok_or_else(|| SophiaError::InvalidInput(format!("Unknown provider: {}", provider)))?;
+    config.generation = generation;
+    storage.set_preference(&provider_type.preference_key(), serde_json::to_value(config)?)?;
+
+    Ok(())
+}
+
+/// Preference holding the user's provider priority order, as provider IDs.
+const PROVIDER_ORDER_KEY: &str = "provider_order";
+
+/// Every provider by priority, including disabled ones.
+#[tauri::command]
+fn get_provider_order(
+    provider_registry: State<'_, Arc<std::sync::Mutex<ProviderRegistry>>>,
+) -> Result<Vec<String>, SophiaError> {
+    let registry = lock_registry(&provider_registry)?;
+    Ok(registry.provider_order().iter().map(|p| p.as_str().to_string()).collect())
+}
 
+/// Sets which providers the router tries first. Providers left out keep their
+/// default order after the listed ones.
+#[tauri::command]
+fn set_provider_order(
+    provider_registry: State<'_, Arc<std::sync::Mutex<ProviderRegistry>>>,
+    storage: State<'_, Arc<StorageManager>>,
+    order: Vec<String>
+) -> Result<(), SophiaError> {
+    let order = order.iter()
+        .map(|p| parse_provider(p))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut registry = lock_registry(&provider_registry)?;
+
+    let ids: Vec<String> = order.iter().map(|p| p.as_str().to_string()).collect();
+    registry.set_provider_order(order).map_err(SophiaError::InvalidInput)?;
+    storage.set_preference(PROVIDER_ORDER_KEY, serde_json::to_value(&ids)?)?;
     Ok(())
 }
 
+#[tauri::command]
+fn update_provider_retry_policy(
+    provider_registry: State<'_, Arc<std::sync::Mutex<ProviderRegistry>>>,
+    storage: State<'_, Arc<StorageManager>>,
+    provider: String,
+    retry_policy: RetryPolicy
+) -> Result<(), SophiaError> {
+    let provider_type = parse_provider(&provider)?;
+    if retry_policy.max_attempts == 0 {
+        return Err(SophiaError::InvalidInput("max_attempts must be at least 1".to_string()));
+    }
+    let mut registry = lock_registry(&provider_registry)?;
+
+    let config = registry.get_provider_config_mut(&provider_type)
+        .ok_or_else(|| SophiaError::InvalidInput(format!("Unknown provider: {}", provider)))?;
+    config.retry_policy = retry_policy;
+    storage.set_preference(&provider_type.preference_key(), serde_json::to_value(config)?)?;
+
+    Ok(())
+}
+
+/// Builds the message list for a prompt: inside a conversation, the stored
+/// history followed by the prompt. Nothing is persisted until a reply arrives
+/// (see `save_exchange`), so a failed, cancelled or blocked prompt leaves no
+/// unanswered message behind.
+fn prompt_messages(storage: &StorageManager, conversation_id: Option<&str>, prompt: &str) -> Result<Vec<ChatMessage>, SophiaError> {
+    let conversation_id = match conversation_id {
+        Some(id) => id,
+        None => return Ok(vec![ChatMessage::user(prompt)]),
+    };
+
+    if storage.get_conversation(conversation_id)?.is_none() {
+        return Err(SophiaError::InvalidInput(format!("Conversation not found: {}", conversation_id)));
+    }
+
+    let mut history: Vec<ChatMessage> = storage.get_messages(conversation_id)?
+        .into_iter()
+        .filter_map(|m| ChatRole::from_str(&m.role).map(|role| ChatMessage { role, content: m.content }))
+        .collect();
+    history.push(ChatMessage::user(prompt));
+
+    Ok(history)
+}
+
+/// Persists a prompt and its reply to the conversation, if there is one.
+fn save_exchange(storage: &StorageManager, conversation_id: Option<&str>, prompt: &str, response: &str) {
+    if let Some(id) = conversation_id {
+        let saved = storage.add_message(id, ChatRole::User.as_str(), prompt)
+            .and_then(|_| storage.add_message(id, ChatRole::Assistant.as_str(), response));
+        if let Err(e) = saved {
+            log::warn!("Failed to save exchange to conversation {}: {}", id, e);
+        }
+    }
+}
+
 #[tauri::command]
 async fn submit_prompt(
     prompt: String,
+    request_id: Option<String>,
+    conversation_id: Option<String>,
+    params: Option<GenerationParams>,
     router: State<'_, ModelRouter>,
-    runtime: State<'_, RuntimeManager>
-) -> Result<String, String> {
+    runtime: State<'_, RuntimeManager>,
+    storage: State<'_, Arc<StorageManager>>,
+) -> Result<String, SophiaError> {
     // 1. Guardrail: Check Pause State
     if runtime.get_state() == RuntimeState::Paused {
-        return Err("Runtime is PAUSED. Request rejected.".to_string());
+        return Err(SophiaError::RuntimePaused);
     }
+    let params = params.unwrap_or_default();
+    params.validate().map_err(SophiaError::InvalidInput)?;
 
-    log::info!("Submitting prompt: {}", &prompt[..prompt.len().min(50)]);
+    // The frontend may supply its own ID so it can cancel the request
+    let request_id = request_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    log::info!("Submitting prompt {}: {}", request_id, prompt.chars().take(50).collect::<String>());
+    let messages = prompt_messages(&storage, conversation_id.as_deref(), &prompt)?;
     
-    // 2. Route & Execute (Blocking call wrapped in async)
-    // In a real app, use tokio::spawn_blocking
-    router.route_and_execute(&prompt)
+    // 2. Route & Execute; cancel_prompt or a pause drops this mid-flight
+    let response = runtime.run_request(&request_id, router.route_and_execute_chat(&messages, &params)).await??.text;
+
+    save_exchange(&storage, conversation_id.as_deref(), &prompt, &response);
+    Ok(response)
+}
+
+/// Event carrying incremental output for `submit_prompt_stream`.
+const PROMPT_STREAM_EVENT: &str = "prompt-stream";
+
+#[tauri::command]
+async fn submit_prompt_stream(
+    app: AppHandle,
+    prompt: String,
+    request_id: Option<String>,
+    conversation_id: Option<String>,
+    params: Option<GenerationParams>,
+    router: State<'_, ModelRouter>,
+    runtime: State<'_, RuntimeManager>,
+    storage: State<'_, Arc<StorageManager>>,
+) -> Result<String, SophiaError> {
+    // 1. Guardrail: Check Pause State
+    if runtime.get_state() == RuntimeState::Paused {
+        return Err(SophiaError::RuntimePaused);
+    }
+    let params = params.unwrap_or_default();
+    params.validate().map_err(SophiaError::InvalidInput)?;
+
+    // The frontend may supply its own ID so it can subscribe before invoking
+    let request_id = request_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    log::info!("Submitting streamed prompt {}: {}", request_id, prompt.chars().take(50).collect::<String>());
+    let messages = prompt_messages(&storage, conversation_id.as_deref(), &prompt)?;
+
+    // 2. Route & Execute, emitting each delta as it arrives
+    let mut emit_delta = |delta: &str| {
+        let _ = app.emit(PROMPT_STREAM_EVENT, StreamChunk {
+            request_id: request_id.clone(),
+            delta: delta.to_string(),
+            done: false,
+            finish_reason: None,
+            truncated: false,
+        });
+    };
+    let result = runtime.run_request(&request_id, router.route_and_execute_chat_stream(&messages, &params, &mut emit_delta))
+        .await
+        .and_then(|result| result);
+
+    // 3. Always close the stream so the UI stops waiting, even on error
+    let completion = result.as_ref().ok();
+    let _ = app.emit(PROMPT_STREAM_EVENT, StreamChunk {
+        request_id,
+        delta: String::new(),
+        done: true,
+        finish_reason: completion.and_then(|c| c.finish_reason.clone()),
+        truncated: completion.is_some_and(|c| c.is_truncated()),
+    });
+
+    let response = result?.text;
+    save_exchange(&storage, conversation_id.as_deref(), &prompt, &response);
+    Ok(response)
+}
+
+/// Aborts a prompt started with `submit_prompt` or `submit_prompt_stream`.
+/// Returns false if no request with that ID is running.
+#[tauri::command]
+fn cancel_prompt(runtime: State<'_, RuntimeManager>, request_id: String) -> bool {
+    log::info!("Cancelling prompt {}", request_id);
+    runtime.cancel_request(&request_id)
+}
+
+#[tauri::command]
+fn get_routing_table(router: State<'_, ModelRouter>) -> RoutingTable {
+    router.get_routing_table()
+}
+
+#[tauri::command]
+fn set_task_route(
+    router: State<'_, ModelRouter>,
+    task_type: TaskType,
+    provider: String,
+    model: String,
+) -> Result<RoutingTable, SophiaError> {
+    let provider = parse_provider(&provider)?;
+    log::info!("Routing {:?} to {} ({})", task_type, provider.as_str(), model);
+    router.set_task_route(task_type, RouteTarget { provider, model })
+}
+
+#[tauri::command]
+fn clear_task_route(
+    router: State<'_, ModelRouter>,
+    task_type: TaskType,
+) -> Result<RoutingTable, SophiaError> {
+    router.clear_task_route(&task_type)
+}
+
+#[tauri::command]
+fn get_task_classifier(router: State<'_, ModelRouter>) -> ClassifierKind {
+    router.get_classifier_kind()
+}
+
+#[tauri::command]
+fn set_task_classifier(router: State<'_, ModelRouter>, kind: String) -> Result<(), SophiaError> {
+    let kind = ClassifierKind::from_str(&kind)
+        .ok_or_else(|| SophiaError::InvalidInput(format!("Unknown classifier: {}", kind)))?;
+    log::info!("Using {:?} task classifier", kind);
+    router.set_classifier_kind(kind)
+}
+
+#[tauri::command]
+fn get_classifier_rules(router: State<'_, ModelRouter>) -> Vec<ClassificationRule> {
+    router.get_classifier_rules()
+}
+
+#[tauri::command]
+fn set_classifier_rules(
+    router: State<'_, ModelRouter>,
+    rules: Vec<ClassificationRule>,
+) -> Result<(), SophiaError> {
+    router.set_classifier_rules(rules)
+}
+
+#[tauri::command]
+fn get_egress_policy(router: State<'_, ModelRouter>) -> EgressPolicy {
+    router.egress_policy()
+}
+
+#[tauri::command]
+fn set_local_only(router: State<'_, ModelRouter>, enabled: bool) -> Result<EgressPolicy, SophiaError> {
+    log::info!("Local-only mode {}", if enabled { "on" } else { "off" });
+    router.set_local_only(enabled)
+}
+
+#[tauri::command]
+fn set_network_egress_consent(router: State<'_, ModelRouter>, consent: bool) -> Result<EgressPolicy, SophiaError> {
+    log::info!("Network egress consent {}", if consent { "given" } else { "withdrawn" });
+    router.set_network_egress_consent(consent)
+}
+
+#[tauri::command]
+fn create_conversation(
+    storage: State<'_, Arc<StorageManager>>,
+    title: Option<String>,
+) -> Result<storage::Conversation, SophiaError> {
+    storage.create_conversation(title.as_deref())
+        .map_err(SophiaError::from)
+}
+
+#[tauri::command]
+fn list_conversations(
+    storage: State<'_, Arc<StorageManager>>,
+) -> Result<Vec<storage::Conversation>, SophiaError> {
+    storage.list_conversations()
+        .map_err(SophiaError::from)
+}
+
+#[tauri::command]
+fn get_conversation_messages(
+    storage: State<'_, Arc<StorageManager>>,
+    conversation_id: String,
+) -> Result<Vec<storage::StoredMessage>, SophiaError> {
+    storage.get_messages(&conversation_id)
+        .map_err(SophiaError::from)
+}
+
+#[tauri::command]
+fn rename_conversation(
+    storage: State<'_, Arc<StorageManager>>,
+    conversation_id: String,
+    title: String,
+) -> Result<(), SophiaError> {
+    match storage.rename_conversation(&conversation_id, &title) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(SophiaError::InvalidInput(format!("Conversation not found: {}", conversation_id))),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[tauri::command]
+fn delete_conversation(
+    storage: State<'_, Arc<StorageManager>>,
+    conversation_id: String,
+) -> Result<(), SophiaError> {
+    match storage.delete_conversation(&conversation_id) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(SophiaError::InvalidInput(format!("Conversation not found: {}", conversation_id))),
+        Err(e) => Err(e.into()),
+    }
 }
 
 #[tauri::command]
 fn test_keychain(
     provider_registry: State<'_, Arc<std::sync::Mutex<ProviderRegistry>>>,
-) -> Result<String, String> {
-    let registry = provider_registry.lock().map_err(|_| "Registry lock error".to_string())?;
+) -> Result<String, SophiaError> {
+    let registry = lock_registry(&provider_registry)?;
     
     // First try to set a test key
     log::info!("Testing keychain write...");
     match registry.set_api_key("test_key", "test_value") {
         Ok(_) => log::info!("Test key written successfully"),
-        Err(e) => return Err(format!("Failed to write test key: {}", e))
+        Err(e) => return Err(SophiaError::Storage(format!("Failed to write test key: {}", e)))
     }
     
     // Try to read it back
     log::info!("Testing keychain read...");
     match registry.get_api_key("test_key") {
-        Ok(Some(val)) => log::info!("Test key read successfully: {}", val),
-        Ok(None) => return Err("Test key not found after writing".to_string()),
-        Err(e) => return Err(format!("Failed to read test key: {}", e))
+        Ok(Some(_)) => log::info!("Test key read successfully"),
+        Ok(None) => return Err(SophiaError::Storage("Test key not found after writing".to_string())),
+        Err(e) => return Err(SophiaError::Storage(format!("Failed to read test key: {}", e)))
     }
     
     // Now check for the actual Gemini key
     log::info!("Checking for Gemini key...");
     match registry.get_api_key("gemini_api_key") {
-        Ok(Some(key)) => Ok(format!("Gemini key found: {}...", &key[..key.len().min(10)])),
+        Ok(Some(_)) => Ok("Gemini key found in keychain. Use the health check to verify it.".to_string()),
         Ok(None) => Ok("Gemini key not found in keychain (but test key works)".to_string()),
-        Err(e) => Err(format!("Keychain error reading Gemini key: {}", e))
+        Err(e) => Err(SophiaError::Storage(format!("Keychain error reading Gemini key: {}", e)))
+    }
+}
+
+/// Keyring service name secrets are stored under.
+const SECRET_SERVICE: &str = "sophia";
+/// Preference naming the preferred secret backend (`BackendKind`).
+const SECRET_BACKEND_KEY: &str = "secret_backend";
+
+/// Preference holding the secret store's auto-lock timeout in minutes; null disables it.
+const SECRET_AUTO_LOCK_KEY: &str = "secret_auto_lock_minutes";
+/// Longest auto-lock timeout accepted, in minutes (one day).
+const MAX_AUTO_LOCK_MINUTES: u64 = 24 * 60;
+/// Shortest master passphrase accepted.
+const MIN_PASSPHRASE_LEN: usize = 8;
+
+#[tauri::command]
+fn get_secret_store_status(secret_store: State<'_, Arc<SecretStore>>) -> SecretStoreStatus {
+    secret_store.status()
+}
+
+#[tauri::command]
+fn unlock_secret_store(secret_store: State<'_, Arc<SecretStore>>, passphrase: String) -> Result<SecretStoreStatus, SophiaError> {
+    secret_store.unlock(&passphrase).map_err(SophiaError::from_secret_store)?;
+    Ok(secret_store.status())
+}
+
+#[tauri::command]
+fn lock_secret_store(secret_store: State<'_, Arc<SecretStore>>) -> Result<SecretStoreStatus, SophiaError> {
+    secret_store.lock().map_err(SophiaError::Storage)?;
+    Ok(secret_store.status())
+}
+
+/// Sets the master passphrase, or changes it given the current one. Secrets
+/// encrypted with the machine key are re-encrypted with the new key.
+#[tauri::command]
+fn set_master_passphrase(
+    secret_store: State<'_, Arc<SecretStore>>,
+    current: Option<String>,
+    passphrase: String,
+) -> Result<SecretStoreStatus, SophiaError> {
+    if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+        return Err(SophiaError::InvalidInput(format!("Master passphrase must be at least {} characters", MIN_PASSPHRASE_LEN)));
     }
+    secret_store.set_passphrase(current.as_deref(), &passphrase).map_err(SophiaError::from_secret_store)?;
+    Ok(secret_store.status())
+}
+
+#[tauri::command]
+fn remove_master_passphrase(secret_store: State<'_, Arc<SecretStore>>, current: String) -> Result<SecretStoreStatus, SophiaError> {
+    secret_store.remove_passphrase(&current).map_err(SophiaError::from_secret_store)?;
+    Ok(secret_store.status())
+}
+
+#[tauri::command]
+fn set_auto_lock_timeout(
+    secret_store: State<'_, Arc<SecretStore>>,
+    storage: State<'_, Arc<StorageManager>>,
+    minutes: Option<u64>,
+) -> Result<SecretStoreStatus, SophiaError> {
+    if minutes.is_some_and(|m| m == 0 || m > MAX_AUTO_LOCK_MINUTES) {
+        return Err(SophiaError::InvalidInput(format!(
+            "Auto-lock timeout must be between 1 and {} minutes", MAX_AUTO_LOCK_MINUTES
+        )));
+    }
+    storage.set_preference(SECRET_AUTO_LOCK_KEY, serde_json::json!(minutes))?;
+    secret_store.set_auto_lock_after(minutes.map(|m| std::time::Duration::from_secs(m * 60)));
+    Ok(secret_store.status())
+}
+
+/// Re-encrypts the secrets file under a new master key.
+#[tauri::command]
+fn rotate_master_key(secret_store: State<'_, Arc<SecretStore>>, passphrase: Option<String>) -> Result<SecretStoreStatus, SophiaError> {
+    secret_store.rotate_master_key(passphrase.as_deref()).map_err(SophiaError::from_secret_store)?;
+    Ok(secret_store.status())
+}
+
+/// Writes every secret to a passphrase-encrypted recovery file that can be
+/// imported on another machine. Returns how many secrets were exported.
+#[tauri::command]
+fn export_secrets_recovery(secret_store: State<'_, Arc<SecretStore>>, path: String, passphrase: String) -> Result<usize, SophiaError> {
+    if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+        return Err(SophiaError::InvalidInput(format!("Recovery passphrase must be at least {} characters", MIN_PASSPHRASE_LEN)));
+    }
+    secret_store.export_recovery(std::path::Path::new(&path), &passphrase).map_err(SophiaError::from_secret_store)
+}
+
+/// Imports the secrets from a recovery file. Returns how many were imported.
+#[tauri::command]
+fn import_secrets_recovery(secret_store: State<'_, Arc<SecretStore>>, path: String, passphrase: String) -> Result<usize, SophiaError> {
+    secret_store.import_recovery(std::path::Path::new(&path), &passphrase).map_err(SophiaError::from_secret_store)
+}
+
+/// Metadata for every stored secret, so stale or unused keys can be spotted.
+#[tauri::command]
+fn list_secrets(secret_store: State<'_, Arc<SecretStore>>) -> Result<Vec<SecretInfo>, SophiaError> {
+    secret_store.list_secrets().map_err(SophiaError::from_secret_store)
+}
+
+/// Sets or clears when a stored secret expires.
+#[tauri::command]
+fn set_secret_expiry(
+    secret_store: State<'_, Arc<SecretStore>>,
+    key: String,
+    expires_at: Option<chrono::DateTime<chrono::Utc>>,
+) -> Result<Vec<SecretInfo>, SophiaError> {
+    secret_store.set_expiry(&key, expires_at).map_err(SophiaError::from_secret_store)?;
+    secret_store.list_secrets().map_err(SophiaError::from_secret_store)
+}
+
+/// Moves every stored secret to another backend and makes it the preferred one.
+#[tauri::command]
+fn migrate_secrets(
+    secret_store: State<'_, Arc<SecretStore>>,
+    storage: State<'_, Arc<StorageManager>>,
+    backend: String,
+) -> Result<SecretStoreStatus, SophiaError> {
+    let kind = BackendKind::from_str(&backend)
+        .ok_or_else(|| SophiaError::InvalidInput(format!("Unknown secret backend: {}", backend)))?;
+    let target = open_backend(kind, SECRET_SERVICE).map_err(SophiaError::Storage)?;
+    let moved = secret_store.migrate_to(target).map_err(SophiaError::from_secret_store)?;
+    storage.set_preference(SECRET_BACKEND_KEY, serde_json::to_value(kind)?)?;
+
+    log::info!("Moved {} secrets to the {} backend", moved, kind.as_str());
+    Ok(secret_store.status())
 }
 
 #[tauri::command]
@@ -177,26 +786,20 @@ fn reset_provider_config(
     provider_registry: State<'_, Arc<std::sync::Mutex<ProviderRegistry>>>,
     storage: State<'_, Arc<StorageManager>>,
     provider: String,
-) -> Result<String, String> {
-    let provider_type = ProviderType::from_str(&provider).ok_or("Unknown provider")?;
-    let mut registry = provider_registry.lock().map_err(|_| "Registry lock error".to_string())?;
+) -> Result<String, SophiaError> {
+    let provider_type = parse_provider(&provider)?;
+    let mut registry = lock_registry(&provider_registry)?;
     
     // Reset to default config
-    let default_config = match provider_type {
-        ProviderType::Gemini => ProviderConfig::default_gemini(),
-        ProviderType::OpenAI => ProviderConfig::default_openai(),
-        ProviderType::Anthropic => ProviderConfig::default_anthropic(),
-        ProviderType::DeepSeek => ProviderConfig::default_deepseek(),
-        ProviderType::OpenRouter => ProviderConfig::default_openrouter(),
-        ProviderType::Ollama => ProviderConfig::default_ollama(),
-    };
+    let default_config = registry.default_config(&provider_type).ok_or_else(|| SophiaError::InvalidInput(format!(
+        "{} is a custom provider with no defaults; remove and re-add it instead", provider
+    )))?;
     
     log::info!("Resetting {} to default config: model={}, endpoint={}", 
         provider, default_config.model, default_config.endpoint);
     
     registry.set_provider_config(default_config.clone());
-    storage.set_preference(&provider_type.preference_key(), serde_json::to_value(&default_config).map_err(|e| e.to_string())?)
-        .map_err(|e| e.to_string())?;
+    storage.set_preference(&provider_type.preference_key(), serde_json::to_value(&default_config)?)?;
     
     Ok(format!("Reset {} to model: {}", provider, default_config.model))
 }
@@ -205,18 +808,18 @@ fn reset_provider_config(
 fn get_usage_stats(
     storage: State<'_, Arc<StorageManager>>,
     days: i64,
-) -> Result<Vec<storage::UsageStats>, String> {
+) -> Result<Vec<storage::UsageStats>, SophiaError> {
     storage.get_all_usage_stats(days)
-        .map_err(|e| e.to_string())
+        .map_err(SophiaError::from)
 }
 
 #[tauri::command]
 fn get_total_cost(
     storage: State<'_, Arc<StorageManager>>,
     days: i64,
-) -> Result<f64, String> {
+) -> Result<f64, SophiaError> {
     storage.get_total_cost(days)
-        .map_err(|e| e.to_string())
+        .map_err(SophiaError::from)
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -227,18 +830,32 @@ pub fn run() {
             let runtime_manager = RuntimeManager::new(app.handle());
             let storage_manager = Arc::new(StorageManager::new(app.handle()));
             let onboarding_manager = OnboardingManager::new(storage_manager.clone());
-            let secret_store = Arc::new(SecretStore::new("sophia"));
-            let provider_registry = Arc::new(std::sync::Mutex::new(ProviderRegistry::new(secret_store)));
+            let preferred_backend = storage_manager.get_preference(SECRET_BACKEND_KEY).ok().flatten()
+                .and_then(|val| serde_json::from_value(val).ok())
+                .unwrap_or(BackendKind::File);
+            let audit = Arc::new(AuditLogger::from_app(app.handle()));
+            let secret_store = Arc::new(SecretStore::open(SECRET_SERVICE, preferred_backend).with_audit_logger(audit.clone()));
+            let provider_registry = Arc::new(std::sync::Mutex::new(ProviderRegistry::new(secret_store.clone())));
+
+            if let Ok(Some(val)) = storage_manager.get_preference(SECRET_AUTO_LOCK_KEY) {
+                let minutes: Option<u64> = serde_json::from_value(val).unwrap_or(None);
+                // A stored value is only trusted within the range the command accepts
+                let minutes = minutes.filter(|m| (1..=MAX_AUTO_LOCK_MINUTES).contains(m));
+                secret_store.set_auto_lock_after(minutes.map(|m| std::time::Duration::from_secs(m * 60)));
+            }
+
+            // Lock an idle passphrase-protected store even when nothing touches it
+            let idle_store = secret_store.clone();
+            std::thread::spawn(move || loop {
+                std::thread::sleep(std::time::Duration::from_secs(30));
+                idle_store.lock_if_idle();
+            });
 
             // Load provider config preferences if present
-            for provider in [
-                ProviderType::Gemini,
-                ProviderType::DeepSeek,
-                ProviderType::OpenAI,
-                ProviderType::Anthropic,
-                ProviderType::OpenRouter,
-                ProviderType::Ollama,
-            ] {
+            let built_in: Vec<ProviderType> = provider_registry.lock().unwrap().descriptors().iter()
+                .map(|d| d.provider.clone())
+                .collect();
+            for provider in built_in {
                 if let Ok(Some(val)) = storage_manager.get_preference(&provider.preference_key()) {
                     if let Ok(config) = serde_json::from_value(val) {
                         provider_registry.lock().unwrap().load_provider_config(config);
@@ -246,21 +863,45 @@ pub fn run() {
                 }
             }
 
-            let model_router = ModelRouter::new(storage_manager.clone(), provider_registry.clone());
+            // Then the user-defined providers, listed by ID
+            let custom_ids: Vec<String> = storage_manager.get_preference(CUSTOM_PROVIDERS_KEY).ok().flatten()
+                .and_then(|val| serde_json::from_value(val).ok())
+                .unwrap_or_default();
+            for id in custom_ids {
+                let key = ProviderType::Custom(id).preference_key();
+                if let Ok(Some(val)) = storage_manager.get_preference(&key) {
+                    if let Ok(config) = serde_json::from_value(val) {
+                        provider_registry.lock().unwrap().load_provider_config(config);
+                    }
+                }
+            }
+
+            // And the priority order, once every provider it may name exists
+            let order: Vec<String> = storage_manager.get_preference(PROVIDER_ORDER_KEY).ok().flatten()
+                .and_then(|val| serde_json::from_value(val).ok())
+                .unwrap_or_default();
+            provider_registry.lock().unwrap()
+                .load_provider_order(order.iter().filter_map(|p| ProviderType::from_str(p)).collect());
+
+            let model_router = ModelRouter::new(storage_manager.clone(), provider_registry.clone())
+                .with_audit_logger(audit);
 
             // Manage State
             app.manage(runtime_manager);
             app.manage(storage_manager.clone()); 
             app.manage(onboarding_manager);
             app.manage(provider_registry.clone());
+            app.manage(secret_store);
             app.manage(model_router);
             
             if cfg!(debug_assertions) {
-                app.handle().plugin(
-                    tauri_plugin_log::Builder::default()
-                        .level(log::LevelFilter::Info)
-                        .build(),
-                )?;
+                // Register the plugin's logger behind the redacting layer, not directly
+                let (plugin, max_level, logger) = tauri_plugin_log::Builder::default()
+                    .level(log::LevelFilter::Info)
+                    .split(app.handle())?;
+                log::set_boxed_logger(Box::new(redact::RedactingLogger::new(logger)))?;
+                log::set_max_level(max_level);
+                app.handle().plugin(plugin)?;
             }
             Ok(())
         })
@@ -273,11 +914,51 @@ pub fn run() {
             complete_onboarding,
             save_provider_key,
             update_provider_model,
+            update_provider_endpoint,
+            update_provider_retry_policy,
+            update_provider_generation,
+            list_local_models,
+            list_models,
+            check_provider_health,
+            add_custom_provider,
+            remove_custom_provider,
+            list_custom_providers,
+            get_provider_order,
+            set_provider_order,
             submit_prompt,
+            submit_prompt_stream,
+            cancel_prompt,
             test_keychain,
+            get_secret_store_status,
+            unlock_secret_store,
+            lock_secret_store,
+            set_master_passphrase,
+            remove_master_passphrase,
+            set_auto_lock_timeout,
+            migrate_secrets,
+            rotate_master_key,
+            export_secrets_recovery,
+            import_secrets_recovery,
+            list_secrets,
+            set_secret_expiry,
             reset_provider_config,
             get_usage_stats,
-            get_total_cost
+            get_total_cost,
+            create_conversation,
+            list_conversations,
+            get_conversation_messages,
+            rename_conversation,
+            delete_conversation,
+            get_routing_table,
+            set_task_route,
+            clear_task_route,
+            get_task_classifier,
+            set_task_classifier,
+            get_egress_policy,
+            set_local_only,
+            set_network_egress_consent,
+            get_classifier_rules,
+            set_classifier_rules
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
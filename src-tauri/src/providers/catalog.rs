@@ -0,0 +1,95 @@
+use crate::error::SophiaError;
+use crate::providers::types::ProviderType;
+use crate::router::types::ModelInfo;
+use crate::storage::StorageManager;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// How long a fetched model list is trusted before it is fetched again.
+pub const CATALOG_TTL_HOURS: i64 = 24;
+
+/// A provider's model list as last fetched, stored under `model_catalog_<provider>`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelCatalog {
+    pub fetched_at: DateTime<Utc>,
+    pub models: Vec<ModelInfo>,
+}
+
+impl ModelCatalog {
+    pub fn new(models: Vec<ModelInfo>) -> Self {
+        ModelCatalog { fetched_at: Utc::now(), models }
+    }
+
+    pub fn is_fresh(&self) -> bool {
+        Utc::now() - self.fetched_at < Duration::hours(CATALOG_TTL_HOURS)
+    }
+
+    pub fn contains(&self, model: &str) -> bool {
+        self.models.iter().any(|m| m.id == model)
+    }
+
+    fn preference_key(provider: &ProviderType) -> String {
+        format!("model_catalog_{}", provider.as_str())
+    }
+
+    /// The cached catalog, fresh or not. A cache that no longer parses is treated as missing.
+    pub fn load(storage: &StorageManager, provider: &ProviderType) -> Result<Option<Self>, SophiaError> {
+        Ok(storage.get_preference(&Self::preference_key(provider))?
+            .and_then(|value| serde_json::from_value(value).ok()))
+    }
+
+    /// Checks `model` against the last fetched model list, stale or not;
+    /// without one there is nothing to check against yet.
+    pub fn validate_model(storage: &StorageManager, provider: &ProviderType, model: &str) -> Result<(), SophiaError> {
+        match Self::load(storage, provider)? {
+            Some(catalog) if !catalog.contains(model) => Err(SophiaError::InvalidInput(format!(
+                "{} does not offer model '{}'. Refresh the model list if it was added recently.",
+                provider.as_str(), model
+            ))),
+            Some(_) => Ok(()),
+            None => {
+                log::warn!("No model list cached for {}; accepting '{}' unchecked", provider.as_str(), model);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn save(&self, storage: &StorageManager, provider: &ProviderType) -> Result<(), SophiaError> {
+        storage.set_preference(&Self::preference_key(provider), serde_json::to_value(self)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn model(id: &str) -> ModelInfo {
+        ModelInfo { id: id.to_string(), display_name: None, context_length: None }
+    }
+
+    #[test]
+    fn test_catalog_round_trip_and_expiry() {
+        let dir = tempdir().unwrap();
+        let storage = StorageManager::new_with_path(dir.path().join("test.db"));
+
+        assert!(ModelCatalog::load(&storage, &ProviderType::OpenAI).unwrap().is_none());
+
+        let catalog = ModelCatalog::new(vec![model("gpt-4o"), model("gpt-4o-mini")]);
+        catalog.save(&storage, &ProviderType::OpenAI).unwrap();
+
+        let loaded = ModelCatalog::load(&storage, &ProviderType::OpenAI).unwrap().unwrap();
+        assert!(loaded.is_fresh());
+        assert!(loaded.contains("gpt-4o-mini"));
+        assert!(!loaded.contains("gpt-5-turbo"));
+        assert!(ModelCatalog::load(&storage, &ProviderType::Gemini).unwrap().is_none());
+
+        let stale = ModelCatalog { fetched_at: Utc::now() - Duration::hours(CATALOG_TTL_HOURS + 1), ..loaded };
+        assert!(!stale.is_fresh());
+
+        assert!(ModelCatalog::validate_model(&storage, &ProviderType::OpenAI, "gpt-4o").is_ok());
+        assert_eq!(ModelCatalog::validate_model(&storage, &ProviderType::OpenAI, "gpt-5-turbo").unwrap_err().kind(), "InvalidInput");
+        assert!(ModelCatalog::validate_model(&storage, &ProviderType::Gemini, "anything").is_ok());
+    }
+}
@@ -0,0 +1,115 @@
+use crate::providers::types::{ProviderConfig, ProviderType};
+use crate::router::client::{
+    AnthropicClient, GeminiClient, LLMClient, OllamaClient, OpenAICompatibleClient,
+};
+use crate::secret_store::SecretString;
+use serde::Serialize;
+
+/// What callers may rely on a provider for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ProviderCapabilities {
+    /// Streams replies natively rather than in one final chunk.
+    pub streaming: bool,
+    /// Has a model-list endpoint (`LLMClient::list_models`).
+    pub model_listing: bool,
+    /// Runs on this machine: free, and no network egress.
+    pub local: bool,
+}
+
+/// Everything the registry needs to know about a built-in provider.
+pub struct ProviderDescriptor {
+    pub provider: ProviderType,
+    pub defaults: fn() -> ProviderConfig,
+    /// Builds a client from the provider's config and API key.
+    pub factory: fn(&ProviderConfig, SecretString) -> Box<dyn LLMClient>,
+    pub capabilities: ProviderCapabilities,
+}
+
+const REMOTE: ProviderCapabilities = ProviderCapabilities { streaming: true, model_listing: true, local: false };
+const LOCAL: ProviderCapabilities = ProviderCapabilities { streaming: true, model_listing: true, local: true };
+
+/// The built-in providers, in their default priority order (Gemini first,
+/// local Ollama last).
+pub fn built_in_descriptors() -> Vec<ProviderDescriptor> {
+    vec![
+        ProviderDescriptor {
+            provider: ProviderType::Gemini,
+            defaults: ProviderConfig::default_gemini,
+            factory: |config, key| Box::new(GeminiClient::new(&config.endpoint, key)),
+            capabilities: REMOTE,
+        },
+        ProviderDescriptor {
+            provider: ProviderType::DeepSeek,
+            defaults: ProviderConfig::default_deepseek,
+            factory: |config, key| Box::new(OpenAICompatibleClient::new("DeepSeek", &config.endpoint, key)),
+            capabilities: REMOTE,
+        },
+        ProviderDescriptor {
+            provider: ProviderType::OpenAI,
+            defaults: ProviderConfig::default_openai,
+            factory: |config, key| Box::new(OpenAICompatibleClient::new("OpenAI", &config.endpoint, key)),
+            capabilities: REMOTE,
+        },
+        ProviderDescriptor {
+            provider: ProviderType::Anthropic,
+            defaults: ProviderConfig::default_anthropic,
+            factory: |config, key| Box::new(AnthropicClient::new(&config.endpoint, key)),
+            capabilities: REMOTE,
+        },
+        ProviderDescriptor {
+            provider: ProviderType::OpenRouter,
+            defaults: ProviderConfig::default_openrouter,
+            factory: |config, key| Box::new(
+                OpenAICompatibleClient::new("OpenRouter", &config.endpoint, key)
+                    .with_header("HTTP-Referer", "http://localhost")
+                    .with_header("X-Title", "Sophia Desktop"),
+            ),
+            capabilities: REMOTE,
+        },
+        ProviderDescriptor {
+            provider: ProviderType::Ollama,
+            defaults: ProviderConfig::default_ollama,
+            factory: |config, _| Box::new(OllamaClient::new(&config.endpoint)),
+            capabilities: LOCAL,
+        },
+    ]
+}
+
+/// Client for a user-defined OpenAI-compatible provider, which has no descriptor.
+pub fn custom_client(config: &ProviderConfig, key: SecretString) -> Box<dyn LLMClient> {
+    Box::new(config.extra_headers.iter().fold(
+        OpenAICompatibleClient::new(config.label(), &config.endpoint, key).with_auth(config.auth_scheme.clone()),
+        |client, (name, value)| client.with_header(name, value),
+    ))
+}
+
+/// Custom providers count as local when their endpoint is on this machine,
+/// e.g. LM Studio or a llama.cpp server on localhost.
+pub fn custom_capabilities(config: &ProviderConfig) -> ProviderCapabilities {
+    let local = reqwest::Url::parse(&config.endpoint)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| matches!(host, "localhost" | "127.0.0.1" | "[::1]")))
+        .unwrap_or(false);
+    ProviderCapabilities { local, ..REMOTE }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::AuthScheme;
+
+    #[test]
+    fn test_descriptors_match_their_defaults() {
+        for descriptor in built_in_descriptors() {
+            assert_eq!((descriptor.defaults)().provider, descriptor.provider);
+        }
+    }
+
+    #[test]
+    fn test_custom_capabilities() {
+        let custom = |endpoint| ProviderConfig::custom("x", "X", endpoint, "m", AuthScheme::None, Default::default());
+        assert!(custom_capabilities(&custom("http://localhost:1234/v1")).local);
+        assert!(custom_capabilities(&custom("http://127.0.0.1:8080/v1")).local);
+        assert!(!custom_capabilities(&custom("https://api.groq.com/openai/v1")).local);
+    }
+}
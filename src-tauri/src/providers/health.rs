@@ -0,0 +1,112 @@
+use crate::error::SophiaError;
+use crate::router::client::LLMClient;
+use crate::router::types::ModelInfo;
+use serde::Serialize;
+use std::time::Instant;
+
+/// Result of a minimal authenticated call against a provider.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProviderHealth {
+    pub provider: String,
+    /// The provider answered at all, even if only to reject the key.
+    pub reachable: bool,
+    /// The key was accepted.
+    pub auth_ok: bool,
+    /// Round trip of the check call, when the provider answered.
+    pub latency_ms: Option<u64>,
+    pub model: String,
+    /// Whether the configured model is in the provider's model list.
+    /// None if the list couldn't be fetched or came back empty.
+    pub model_available: Option<bool>,
+    /// `SophiaError::kind` of the failure, if the check failed.
+    pub error_kind: Option<String>,
+    pub error: Option<String>,
+}
+
+impl ProviderHealth {
+    /// Health of a provider that couldn't even be called, e.g. no key stored.
+    pub fn unavailable(provider: &str, model: &str, err: &SophiaError) -> Self {
+        ProviderHealth {
+            provider: provider.to_string(),
+            reachable: false,
+            auth_ok: false,
+            latency_ms: None,
+            model: model.to_string(),
+            model_available: None,
+            error_kind: Some(err.kind().to_string()),
+            error: Some(err.to_string()),
+        }
+    }
+
+    /// Builds the status from the outcome of a model-list call.
+    fn from_listing(provider: &str, model: &str, latency_ms: u64, result: &Result<Vec<ModelInfo>, SophiaError>) -> Self {
+        match result {
+            Ok(models) => ProviderHealth {
+                provider: provider.to_string(),
+                reachable: true,
+                auth_ok: true,
+                latency_ms: Some(latency_ms),
+                model: model.to_string(),
+                model_available: (!models.is_empty()).then(|| models.iter().any(|m| m.id == model)),
+                error_kind: None,
+                error: None,
+            },
+            Err(err) => {
+                let reachable = !matches!(err, SophiaError::Network(_));
+                ProviderHealth {
+                    latency_ms: reachable.then_some(latency_ms),
+                    reachable,
+                    // Limits and quotas are only enforced on an accepted key
+                    auth_ok: matches!(err, SophiaError::RateLimited { .. } | SophiaError::QuotaExceeded(_)),
+                    ..Self::unavailable(provider, model, err)
+                }
+            }
+        }
+    }
+}
+
+/// Checks a provider by listing its models: cheap, authenticated, and it
+/// tells whether `model` exists. Also returns the list so it can be cached.
+pub async fn check_health(provider: &str, model: &str, client: &dyn LLMClient) -> (ProviderHealth, Option<Vec<ModelInfo>>) {
+    let started = Instant::now();
+    let result = client.list_models().await;
+    let health = ProviderHealth::from_listing(provider, model, started.elapsed().as_millis() as u64, &result);
+
+    log::info!(
+        "Health check for {}: reachable={} auth_ok={} model_available={:?}",
+        provider, health.reachable, health.auth_ok, health.model_available
+    );
+    (health, result.ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(id: &str) -> ModelInfo {
+        ModelInfo { id: id.to_string(), display_name: None, context_length: None }
+    }
+
+    #[test]
+    fn test_health_from_listing() {
+        let ok = ProviderHealth::from_listing("openai", "gpt-4o", 120, &Ok(vec![model("gpt-4o")]));
+        assert!(ok.reachable && ok.auth_ok);
+        assert_eq!(ok.model_available, Some(true));
+        assert_eq!(ok.latency_ms, Some(120));
+
+        let missing = ProviderHealth::from_listing("openai", "gpt-9", 120, &Ok(vec![model("gpt-4o")]));
+        assert_eq!(missing.model_available, Some(false));
+
+        let rejected = ProviderHealth::from_listing("openai", "gpt-4o", 80, &Err(SophiaError::Auth("bad key".into())));
+        assert!(rejected.reachable);
+        assert!(!rejected.auth_ok);
+        assert_eq!(rejected.error_kind.as_deref(), Some("Auth"));
+
+        let offline = ProviderHealth::from_listing("openai", "gpt-4o", 5000, &Err(SophiaError::Network("timeout".into())));
+        assert!(!offline.reachable);
+        assert_eq!(offline.latency_ms, None);
+
+        let limited = SophiaError::RateLimited { message: "slow down".into(), retry_after: None };
+        assert!(ProviderHealth::from_listing("openai", "gpt-4o", 80, &Err(limited)).auth_ok);
+    }
+}
@@ -1,5 +1,11 @@
+pub mod catalog;
+pub mod descriptor;
+pub mod health;
 pub mod registry;
 pub mod types;
 
-pub use registry::ProviderRegistry;
+pub use catalog::ModelCatalog;
+pub use descriptor::{ProviderCapabilities, ProviderDescriptor};
+pub use health::ProviderHealth;
+pub use registry::{ProviderRegistry, ProviderUnavailable, UnavailableReason};
 pub use types::{ProviderType, ProviderConfig};
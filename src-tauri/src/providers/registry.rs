@@ -1,34 +1,144 @@
+use crate::error::SophiaError;
 use crate::providers::types::{ProviderConfig, ProviderType};
-use crate::router::client::{
-    LLMClient, 
-    OllamaClient, 
-    GeminiClient, 
-    OpenAIClient, 
-    AnthropicClient, 
-    DeepSeekClient, 
-    OpenRouterClient, 
-    MockClient
-};
-use crate::secret_store::SecretStore;
+use crate::providers::descriptor::{built_in_descriptors, custom_capabilities, custom_client, ProviderCapabilities, ProviderDescriptor};
+use crate::router::client::LLMClient;
+use crate::secret_store::{SecretStore, SecretString};
 use std::collections::HashMap;
+use std::fmt;
 use std::sync::Arc;
 
+/// Why the registry won't hand out a client for a provider.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnavailableReason {
+    NotConfigured,
+    Disabled,
+    MissingKey,
+    KeychainError(String),
+}
+
+impl fmt::Display for UnavailableReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnavailableReason::NotConfigured => write!(f, "Provider not configured"),
+            UnavailableReason::Disabled => write!(f, "Provider is disabled"),
+            UnavailableReason::MissingKey => write!(f, "API key not found in keychain"),
+            UnavailableReason::KeychainError(e) => write!(f, "Keychain error: {}", e),
+        }
+    }
+}
+
+/// A provider that can't be called right now, and why. The router skips it
+/// rather than sending a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderUnavailable {
+    pub provider: ProviderType,
+    pub reason: UnavailableReason,
+}
+
+impl fmt::Display for ProviderUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.provider.as_str(), self.reason)
+    }
+}
+
+/// Keychain failures are storage problems; everything else means the user has
+/// to configure, enable or add a key for the provider.
+impl From<ProviderUnavailable> for SophiaError {
+    fn from(err: ProviderUnavailable) -> Self {
+        match err.reason {
+            UnavailableReason::KeychainError(_) => SophiaError::Storage(err.to_string()),
+            _ => SophiaError::Auth(err.to_string()),
+        }
+    }
+}
+
 pub struct ProviderRegistry {
+    descriptors: Vec<ProviderDescriptor>,
     providers: HashMap<ProviderType, ProviderConfig>,
+    /// User-defined priority order. Providers missing from it follow in the
+    /// default order.
+    order: Vec<ProviderType>,
     secret_store: Arc<SecretStore>,
 }
 
 impl ProviderRegistry {
     pub fn new(secret_store: Arc<SecretStore>) -> Self {
-        let mut providers = HashMap::new();
-        providers.insert(ProviderType::Gemini, ProviderConfig::default_gemini());
-        providers.insert(ProviderType::OpenAI, ProviderConfig::default_openai());
-        providers.insert(ProviderType::Anthropic, ProviderConfig::default_anthropic());
-        providers.insert(ProviderType::DeepSeek, ProviderConfig::default_deepseek());
-        providers.insert(ProviderType::OpenRouter, ProviderConfig::default_openrouter());
-        providers.insert(ProviderType::Ollama, ProviderConfig::default_ollama());
+        let descriptors = built_in_descriptors();
+        let providers = descriptors.iter()
+            .map(|d| (d.provider.clone(), (d.defaults)()))
+            .collect();
+
+        ProviderRegistry { descriptors, providers, order: Vec::new(), secret_store }
+    }
+
+    /// The built-in providers, in default priority order.
+    pub fn descriptors(&self) -> &[ProviderDescriptor] {
+        &self.descriptors
+    }
+
+    fn descriptor(&self, provider: &ProviderType) -> Option<&ProviderDescriptor> {
+        self.descriptors.iter().find(|d| d.provider == *provider)
+    }
+
+    /// Swaps the factory a built-in provider's clients are built with, so
+    /// tests can stand in a mock for a real endpoint.
+    #[cfg(test)]
+    pub(crate) fn set_client_factory(&mut self, provider: &ProviderType, factory: fn(&ProviderConfig, SecretString) -> Box<dyn LLMClient>) {
+        if let Some(descriptor) = self.descriptors.iter_mut().find(|d| d.provider == *provider) {
+            descriptor.factory = factory;
+        }
+    }
+
+    /// The config a built-in provider starts out with; None for custom providers.
+    pub fn default_config(&self, provider: &ProviderType) -> Option<ProviderConfig> {
+        self.descriptor(provider).map(|d| (d.defaults)())
+    }
+
+    pub fn capabilities(&self, provider: &ProviderType) -> Option<ProviderCapabilities> {
+        match self.descriptor(provider) {
+            Some(descriptor) => Some(descriptor.capabilities),
+            None => self.providers.get(provider).map(custom_capabilities),
+        }
+    }
+
+    /// Every provider by priority: the user's order first, then the rest by
+    /// default (remote built-ins, custom providers, then local built-ins).
+    pub fn provider_order(&self) -> Vec<ProviderType> {
+        let (local, remote): (Vec<_>, Vec<_>) = self.descriptors.iter().partition(|d| d.capabilities.local);
+        let defaults = remote.into_iter().map(|d| d.provider.clone())
+            .chain(self.custom_providers())
+            .chain(local.into_iter().map(|d| d.provider.clone()));
+
+        let mut order: Vec<ProviderType> = self.order.iter()
+            .filter(|p| self.providers.contains_key(p))
+            .cloned()
+            .collect();
+        for provider in defaults {
+            if !order.contains(&provider) {
+                order.push(provider);
+            }
+        }
+        order
+    }
+
+    /// Sets the priority order. It may name a subset of the providers; the
+    /// rest keep their default order after it.
+    pub fn set_provider_order(&mut self, order: Vec<ProviderType>) -> Result<(), String> {
+        for (i, provider) in order.iter().enumerate() {
+            if !self.providers.contains_key(provider) {
+                return Err(format!("Unknown provider: {}", provider.as_str()));
+            }
+            if order[..i].contains(provider) {
+                return Err(format!("{} is listed twice", provider.as_str()));
+            }
+        }
+        self.order = order;
+        Ok(())
+    }
 
-        ProviderRegistry { providers, secret_store }
+    /// Restores a saved order, dropping providers that no longer exist.
+    pub fn load_provider_order(&mut self, order: Vec<ProviderType>) {
+        self.order = order;
     }
 
     pub fn get_provider_config(&self, provider: &ProviderType) -> Option<&ProviderConfig> {
@@ -47,11 +157,46 @@ impl ProviderRegistry {
         self.providers.insert(config.provider.clone(), config);
     }
 
-    pub fn set_api_key(&self, key_id: &str, value: &str) -> Result<(), String> {
-        self.secret_store.set_secret(key_id, value)
+    /// Adds a user-defined provider. Fails if its ID is already taken.
+    pub fn add_custom_provider(&mut self, config: ProviderConfig) -> Result<(), String> {
+        if self.providers.contains_key(&config.provider) {
+            return Err(format!("A provider with ID '{}' already exists", config.provider.as_str()));
+        }
+        self.providers.insert(config.provider.clone(), config);
+        Ok(())
+    }
+
+    /// Removes a user-defined provider, returning its config. Built-ins can't be removed.
+    pub fn remove_custom_provider(&mut self, provider: &ProviderType) -> Option<ProviderConfig> {
+        match provider {
+            ProviderType::Custom(_) => self.providers.remove(provider),
+            _ => None,
+        }
+    }
+
+    /// User-defined providers, sorted by ID.
+    pub fn custom_providers(&self) -> Vec<ProviderType> {
+        let mut custom = self.providers.keys()
+            .filter(|p| matches!(p, ProviderType::Custom(_)))
+            .cloned()
+            .collect::<Vec<_>>();
+        custom.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        custom
+    }
+
+    /// Stores an API key, tagged with the provider whose config names `key_id`.
+    pub fn set_api_key(&self, key_id: &str, value: impl Into<SecretString>) -> Result<(), String> {
+        let provider = self.providers.values()
+            .find(|config| config.api_key_keychain_id == key_id)
+            .map(|config| config.provider.as_str());
+        self.secret_store.set_secret_for(key_id, value.into(), provider)
     }
 
-    pub fn get_api_key(&self, key_id: &str) -> Result<Option<String>, String> {
+    pub fn delete_api_key(&self, key_id: &str) -> Result<(), String> {
+        self.secret_store.delete_secret(key_id)
+    }
+
+    pub fn get_api_key(&self, key_id: &str) -> Result<Option<SecretString>, String> {
         self.secret_store.get_secret(key_id)
     }
 
@@ -62,17 +207,7 @@ impl ProviderRegistry {
     }
 
     pub fn get_active_provider_order(&self) -> Vec<ProviderType> {
-        // Gemini-first priority order
-        let order = vec![
-            ProviderType::Gemini,
-            ProviderType::DeepSeek,
-            ProviderType::OpenAI,
-            ProviderType::Anthropic,
-            ProviderType::OpenRouter,
-            ProviderType::Ollama,
-        ];
-
-        let mut active = order.into_iter()
+        let mut active = self.provider_order().into_iter()
             .filter(|p| self.providers.get(p).map(|c| c.enabled).unwrap_or(false))
             .collect::<Vec<_>>();
 
@@ -83,40 +218,148 @@ impl ProviderRegistry {
         active
     }
 
-    pub fn get_client(&self, provider: &ProviderType) -> Box<dyn LLMClient + Send + Sync> {
-        match provider {
-            ProviderType::Ollama => Box::new(OllamaClient::new("http://localhost:11434")),
-            ProviderType::Gemini => self.get_keyed_client(provider, |c, k| GeminiClient::new(&c.endpoint, &k), "Missing Gemini API key"),
-            ProviderType::OpenAI => self.get_keyed_client(provider, |c, k| OpenAIClient::new(&c.endpoint, &k), "Missing OpenAI API key"),
-            ProviderType::Anthropic => self.get_keyed_client(provider, |c, k| AnthropicClient::new(&c.endpoint, &k), "Missing Anthropic API key"),
-            ProviderType::DeepSeek => self.get_keyed_client(provider, |c, k| DeepSeekClient::new(&c.endpoint, &k), "Missing DeepSeek API key"),
-            ProviderType::OpenRouter => self.get_keyed_client(provider, |c, k| OpenRouterClient::new(&c.endpoint, &k), "Missing OpenRouter API key"),
+    /// Explains why a provider can't be called right now (e.g. its API key is
+    /// missing), or returns None if it is ready.
+    pub fn unavailable_reason(&self, provider: &ProviderType) -> Option<UnavailableReason> {
+        self.resolve(provider).err().map(|e| e.reason)
+    }
+
+    /// The config and API key for a provider, or why it can't be used.
+    fn resolve(&self, provider: &ProviderType) -> Result<(&ProviderConfig, SecretString), ProviderUnavailable> {
+        let unavailable = |reason| ProviderUnavailable { provider: provider.clone(), reason };
+
+        let config = self.providers.get(provider)
+            .ok_or_else(|| unavailable(UnavailableReason::NotConfigured))?;
+        if !config.enabled {
+            return Err(unavailable(UnavailableReason::Disabled));
+        }
+        if !config.requires_key() {
+            return Ok((config, SecretString::default())); // Local, no key required
+        }
+
+        match self.get_api_key(&config.api_key_keychain_id) {
+            Ok(Some(key)) => Ok((config, key)),
+            Ok(None) => Err(unavailable(UnavailableReason::MissingKey)),
+            Err(e) => Err(unavailable(UnavailableReason::KeychainError(e))),
         }
     }
 
-    fn get_keyed_client<F, C>(&self, provider: &ProviderType, factory: F, missing_msg: &str) -> Box<dyn LLMClient + Send + Sync>
-    where
-        F: Fn(&ProviderConfig, String) -> C,
-        C: LLMClient + Send + Sync + 'static,
-    {
-        if let Some(config) = self.providers.get(provider) {
-            match self.get_api_key(&config.api_key_keychain_id) {
-                Ok(Some(key)) => {
-                    log::info!("Retrieved API key for provider {:?}", provider);
-                    Box::new(factory(config, key))
-                },
-                Ok(None) => {
-                    log::warn!("No API key found for provider {:?} (keychain_id: {})", provider, config.api_key_keychain_id);
-                    Box::new(MockClient { response: format!("{} (key not found in keychain)", missing_msg) })
-                },
-                Err(e) => {
-                    log::error!("Error retrieving API key for provider {:?}: {}", provider, e);
-                    Box::new(MockClient { response: format!("{} (keychain error: {})", missing_msg, e) })
-                }
-            }
-        } else {
-            log::error!("Provider {:?} not configured in registry", provider);
-            Box::new(MockClient { response: "Provider not configured".to_string() })
+    /// Builds a client for an enabled provider whose API key is available.
+    pub fn get_client(&self, provider: &ProviderType) -> Result<Box<dyn LLMClient>, ProviderUnavailable> {
+        let (config, key) = self.resolve(provider).inspect_err(|e| log::warn!("{}", e))?;
+        if config.requires_key() {
+            log::info!("Retrieved API key for provider {}", provider.as_str());
         }
+
+        Ok(self.build_client(config, key))
+    }
+
+    fn build_client(&self, config: &ProviderConfig, key: SecretString) -> Box<dyn LLMClient> {
+        match self.descriptor(&config.provider) {
+            Some(descriptor) => (descriptor.factory)(config, key),
+            None => custom_client(config, key),
+        }
+    }
+
+    /// Builds a client for a health check, whether or not the provider is
+    /// enabled: with `api_key` if given (a key not saved yet), otherwise with
+    /// the stored key. Neither redaction of a candidate key nor the egress
+    /// policy is handled here; see `ModelRouter::check_provider_health`.
+    pub fn get_check_client(&self, provider: &ProviderType, api_key: Option<&str>) -> Result<Box<dyn LLMClient>, ProviderUnavailable> {
+        let unavailable = |reason| ProviderUnavailable { provider: provider.clone(), reason };
+        let config = self.providers.get(provider)
+            .ok_or_else(|| unavailable(UnavailableReason::NotConfigured))?;
+
+        let key = match api_key {
+            Some(key) => SecretString::from(key),
+            None if !config.requires_key() => SecretString::default(),
+            None => match self.get_api_key(&config.api_key_keychain_id) {
+                Ok(Some(key)) => key,
+                Ok(None) => return Err(unavailable(UnavailableReason::MissingKey)),
+                Err(e) => return Err(unavailable(UnavailableReason::KeychainError(e))),
+            },
+        };
+
+        Ok(self.build_client(config, key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::AuthScheme;
+
+    #[test]
+    fn test_unavailable_providers_get_no_client() {
+        let mut registry = ProviderRegistry::new(Arc::new(SecretStore::in_memory("test")));
+
+        // Disabled by default
+        let err = registry.get_client(&ProviderType::Anthropic).err().unwrap();
+        assert_eq!(err.reason, UnavailableReason::Disabled);
+
+        // Enabled, but no key is stored under this ID
+        registry.set_provider_enabled(&ProviderType::Anthropic, true);
+        assert_eq!(registry.unavailable_reason(&ProviderType::Anthropic), Some(UnavailableReason::MissingKey));
+        assert_eq!(SophiaError::from(registry.get_client(&ProviderType::Anthropic).err().unwrap()).kind(), "Auth");
+
+        // Ollama needs no key
+        assert!(registry.unavailable_reason(&ProviderType::Ollama).is_none());
+        assert!(registry.get_client(&ProviderType::Ollama).is_ok());
+    }
+
+    #[test]
+    fn test_custom_providers() {
+        let mut registry = ProviderRegistry::new(Arc::new(SecretStore::in_memory("test")));
+        let local = ProviderConfig::custom("lm-studio", "LM Studio", "http://localhost:1234/v1", "qwen2.5-7b", AuthScheme::None, Default::default());
+        let groq = ProviderConfig::custom("groq", "Groq", "https://api.groq.com/openai/v1", "llama-3.3-70b", AuthScheme::Bearer, Default::default());
+        registry.add_custom_provider(local.clone()).unwrap();
+        registry.add_custom_provider(groq).unwrap();
+        assert!(registry.add_custom_provider(local).is_err());
+
+        let lm_studio = ProviderType::Custom("lm-studio".to_string());
+        let groq = ProviderType::Custom("groq".to_string());
+        // Keyless endpoints are usable straight away; the rest wait for a key
+        assert!(registry.get_client(&lm_studio).is_ok());
+        assert_eq!(registry.unavailable_reason(&groq), Some(UnavailableReason::Disabled));
+        assert_eq!(registry.get_active_provider_order(), vec![lm_studio.clone(), ProviderType::Ollama]);
+
+        assert!(registry.remove_custom_provider(&ProviderType::Gemini).is_none());
+        assert!(registry.remove_custom_provider(&lm_studio).is_some());
+        assert_eq!(registry.custom_providers(), vec![groq]);
+    }
+
+    #[test]
+    fn test_provider_order() {
+        let mut registry = ProviderRegistry::new(Arc::new(SecretStore::in_memory("test")));
+        let groq = ProviderType::Custom("groq".to_string());
+        registry.add_custom_provider(ProviderConfig::custom("groq", "Groq", "https://api.groq.com/openai/v1", "llama-3.3-70b", AuthScheme::Bearer, Default::default())).unwrap();
+
+        let order = registry.provider_order();
+        assert_eq!(order.first(), Some(&ProviderType::Gemini));
+        assert_eq!(&order[order.len() - 2..], &[groq.clone(), ProviderType::Ollama]);
+
+        registry.set_provider_order(vec![ProviderType::Ollama, ProviderType::Anthropic]).unwrap();
+        let order = registry.provider_order();
+        assert_eq!(&order[..3], &[ProviderType::Ollama, ProviderType::Anthropic, ProviderType::Gemini]);
+        assert_eq!(order.len(), 7);
+
+        assert!(registry.set_provider_order(vec![ProviderType::Ollama, ProviderType::Ollama]).is_err());
+        assert!(registry.set_provider_order(vec![ProviderType::Custom("nope".to_string())]).is_err());
+
+        // Removed providers drop out of a saved order
+        registry.set_provider_order(vec![groq.clone(), ProviderType::Gemini]).unwrap();
+        registry.remove_custom_provider(&groq);
+        assert_eq!(registry.provider_order()[0], ProviderType::Gemini);
+        assert_eq!(registry.capabilities(&ProviderType::Ollama).map(|c| c.local), Some(true));
+    }
+
+    #[test]
+    fn test_check_client_ignores_enabled_flag() {
+        let registry = ProviderRegistry::new(Arc::new(SecretStore::in_memory("test")));
+
+        // A candidate key can be checked before it is saved or the provider enabled
+        assert!(registry.get_check_client(&ProviderType::OpenAI, Some("sk-candidate")).is_ok());
+        let err = registry.get_check_client(&ProviderType::OpenAI, None).err().unwrap();
+        assert_eq!(err.reason, UnavailableReason::MissingKey);
     }
 }
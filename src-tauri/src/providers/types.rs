@@ -1,4 +1,6 @@
+use crate::router::{AuthScheme, GenerationParams, RetryPolicy};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum ProviderType {
@@ -8,10 +10,15 @@ pub enum ProviderType {
     Anthropic,
     Ollama,
     OpenRouter,
+    /// A user-defined OpenAI-compatible endpoint, identified by its slug.
+    Custom(String),
 }
 
+/// Names of the built-in providers; custom provider IDs may not reuse them.
+const BUILT_IN_NAMES: &[&str] = &["gemini", "deepseek", "openai", "anthropic", "ollama", "openrouter"];
+
 impl ProviderType {
-    pub fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &str {
         match self {
             ProviderType::Gemini => "gemini",
             ProviderType::DeepSeek => "deepseek",
@@ -19,6 +26,7 @@ impl ProviderType {
             ProviderType::Anthropic => "anthropic",
             ProviderType::Ollama => "ollama",
             ProviderType::OpenRouter => "openrouter",
+            ProviderType::Custom(id) => id,
         }
     }
 
@@ -34,9 +42,27 @@ impl ProviderType {
             "anthropic" => Some(ProviderType::Anthropic),
             "ollama" => Some(ProviderType::Ollama),
             "openrouter" => Some(ProviderType::OpenRouter),
+            id if is_custom_id(id) => Some(ProviderType::Custom(id.to_string())),
             _ => None,
         }
     }
+
+    /// The ID a custom provider named `name` gets, e.g. "Together AI" -> "together-ai".
+    /// None if nothing usable is left or it would shadow a built-in provider.
+    pub fn custom_id(name: &str) -> Option<String> {
+        let slug = name.to_lowercase()
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("-");
+        is_custom_id(&slug).then_some(slug)
+    }
+}
+
+fn is_custom_id(id: &str) -> bool {
+    !id.is_empty()
+        && !BUILT_IN_NAMES.contains(&id)
+        && id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -46,6 +72,21 @@ pub struct ProviderConfig {
     pub endpoint: String,
     pub model: String,
     pub enabled: bool,
+    /// Configs saved before retries existed get the default policy.
+    #[serde(default)]
+    pub retry_policy: RetryPolicy,
+    /// System prompt and sampling settings sent with every request.
+    #[serde(default)]
+    pub generation: GenerationParams,
+    /// Name shown for custom providers; built-ins leave it unset.
+    #[serde(default)]
+    pub display_name: Option<String>,
+    /// How the key is sent. Only custom providers honor anything but the default.
+    #[serde(default)]
+    pub auth_scheme: AuthScheme,
+    /// Headers added to every request of a custom provider.
+    #[serde(default)]
+    pub extra_headers: BTreeMap<String, String>,
 }
 
 impl ProviderConfig {
@@ -56,6 +97,8 @@ impl ProviderConfig {
             endpoint: "https://generativelanguage.googleapis.com/v1beta".to_string(),
             model: "gemini-2.5-flash-lite".to_string(),
             enabled: false,
+            retry_policy: RetryPolicy::default(),
+            ..Self::base()
         }
     }
 
@@ -66,6 +109,9 @@ impl ProviderConfig {
             endpoint: "https://api.openai.com/v1".to_string(),
             model: "gpt-4o-mini".to_string(), // Cost-effective model
             enabled: false,
+            retry_policy: RetryPolicy::default(),
+            generation: openai_style_generation(),
+            ..Self::base()
         }
     }
 
@@ -76,6 +122,8 @@ impl ProviderConfig {
             endpoint: "https://api.anthropic.com/v1".to_string(),
             model: "claude-3-5-haiku-20241022".to_string(), // Fast, affordable model
             enabled: false,
+            retry_policy: RetryPolicy::default(),
+            ..Self::base()
         }
     }
 
@@ -86,6 +134,9 @@ impl ProviderConfig {
             endpoint: "https://api.deepseek.com/v1".to_string(),
             model: "deepseek-chat".to_string(),
             enabled: false,
+            retry_policy: RetryPolicy::default(),
+            generation: openai_style_generation(),
+            ..Self::base()
         }
     }
 
@@ -96,6 +147,9 @@ impl ProviderConfig {
             endpoint: "https://openrouter.ai/api/v1".to_string(),
             model: "openai/gpt-4o".to_string(),
             enabled: false,
+            retry_policy: RetryPolicy::default(),
+            generation: openai_style_generation(),
+            ..Self::base()
         }
     }
 
@@ -106,6 +160,75 @@ impl ProviderConfig {
             endpoint: "http://localhost:11434".to_string(),
             model: "llama3.2:3b".to_string(),
             enabled: true,
+            // A local server is either up or not; fail over straight away
+            retry_policy: RetryPolicy::none(),
+            ..Self::base()
+        }
+    }
+
+    /// A user-defined OpenAI-compatible provider. It starts out enabled only
+    /// if it needs no key; saving a key enables the others.
+    pub fn custom(id: &str, name: &str, endpoint: &str, model: &str, auth_scheme: AuthScheme, extra_headers: BTreeMap<String, String>) -> Self {
+        ProviderConfig {
+            provider: ProviderType::Custom(id.to_string()),
+            api_key_keychain_id: format!("custom_{}_api_key", id),
+            endpoint: endpoint.to_string(),
+            model: model.to_string(),
+            enabled: auth_scheme == AuthScheme::None,
+            retry_policy: RetryPolicy::default(),
+            generation: openai_style_generation(),
+            display_name: Some(name.to_string()),
+            auth_scheme,
+            extra_headers,
+        }
+    }
+
+    /// Field values shared by every built-in provider.
+    fn base() -> Self {
+        ProviderConfig {
+            provider: ProviderType::Ollama,
+            api_key_keychain_id: String::new(),
+            endpoint: String::new(),
+            model: String::new(),
+            enabled: false,
+            retry_policy: RetryPolicy::default(),
+            generation: GenerationParams::default(),
+            display_name: None,
+            auth_scheme: AuthScheme::Bearer,
+            extra_headers: BTreeMap::new(),
         }
     }
+
+    /// Local Ollama and keyless custom endpoints are called without an API key.
+    pub fn requires_key(&self) -> bool {
+        self.provider != ProviderType::Ollama && self.auth_scheme != AuthScheme::None
+    }
+
+    /// Name for logs and messages: the display name if set, else the ID.
+    pub fn label(&self) -> &str {
+        self.display_name.as_deref().unwrap_or(self.provider.as_str())
+    }
+}
+
+/// The temperature OpenAI-style providers were always called with.
+fn openai_style_generation() -> GenerationParams {
+    GenerationParams { temperature: Some(0.7), ..GenerationParams::default() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_custom_provider_ids() {
+        assert_eq!(ProviderType::custom_id("Together AI").as_deref(), Some("together-ai"));
+        assert_eq!(ProviderType::custom_id("  LM Studio (local) ").as_deref(), Some("lm-studio-local"));
+        assert_eq!(ProviderType::custom_id("OpenAI"), None);
+        assert_eq!(ProviderType::custom_id("!!"), None);
+
+        assert_eq!(ProviderType::from_str("OpenAI"), Some(ProviderType::OpenAI));
+        assert_eq!(ProviderType::from_str("groq"), Some(ProviderType::Custom("groq".to_string())));
+        assert_eq!(ProviderType::from_str("not a slug"), None);
+        assert_eq!(ProviderType::Custom("groq".to_string()).preference_key(), "provider_config_groq");
+    }
 }
@@ -0,0 +1,275 @@
+use crate::error::SophiaError;
+use crate::providers::ProviderType;
+use crate::router::client::LLMClient;
+use crate::router::types::{ChatMessage, ChatRole, GenerationParams, TaskType};
+use crate::storage::StorageManager;
+use async_trait::async_trait;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Outcome of classifying a prompt, kept in the decision log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Classification {
+    pub task_type: TaskType,
+    /// 0.0 – 1.0
+    pub confidence: f64,
+    pub classifier: String,
+}
+
+#[async_trait]
+pub trait TaskClassifier: Send + Sync {
+    fn name(&self) -> &'static str;
+    async fn classify(&self, input: &str) -> Result<Classification, SophiaError>;
+}
+
+/// Which classifier the router uses, stored as the `task_classifier` preference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClassifierKind {
+    #[default]
+    Heuristic,
+    Rules,
+    Llm,
+}
+
+impl ClassifierKind {
+    pub fn from_str(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "heuristic" => Some(ClassifierKind::Heuristic),
+            "rules" => Some(ClassifierKind::Rules),
+            "llm" => Some(ClassifierKind::Llm),
+            _ => None,
+        }
+    }
+}
+
+/// The original v1 substring checks. Cheap, but "decode" counts as code.
+pub struct HeuristicClassifier;
+
+#[async_trait]
+impl TaskClassifier for HeuristicClassifier {
+    fn name(&self) -> &'static str {
+        "heuristic"
+    }
+
+    async fn classify(&self, input: &str) -> Result<Classification, SophiaError> {
+        let input = input.to_lowercase();
+        let (task_type, confidence) = if input.contains("code") || input.contains("function") {
+            (TaskType::CodeAnalysis, 0.5)
+        } else if input.contains("plan") || input.contains("todo") {
+            (TaskType::Planning, 0.5)
+        } else if input.contains("json") || input.contains("data") {
+            (TaskType::DataProcessing, 0.5)
+        } else {
+            (TaskType::GeneralChat, 0.3)
+        };
+
+        Ok(Classification {
+            task_type,
+            confidence,
+            classifier: self.name().to_string(),
+        })
+    }
+}
+
+/// One weighted pattern; a match adds `weight` to `task_type`'s score.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClassificationRule {
+    pub task_type: TaskType,
+    /// Regular expression, matched case-insensitively.
+    pub pattern: String,
+    pub weight: f64,
+}
+
+impl ClassificationRule {
+    fn new(task_type: TaskType, pattern: &str, weight: f64) -> Self {
+        ClassificationRule {
+            task_type,
+            pattern: pattern.to_string(),
+            weight,
+        }
+    }
+
+    pub fn compile(&self) -> Result<Regex, String> {
+        Regex::new(&format!("(?i){}", self.pattern))
+            .map_err(|e| format!("Invalid pattern '{}': {}", self.pattern, e))
+    }
+
+    /// Word-bounded defaults, so "decode" no longer reads as code.
+    pub fn defaults() -> Vec<Self> {
+        vec![
+            Self::new(TaskType::CodeAnalysis, r"\b(code|function|method|class|compile[sd]?|refactor|debug|stack ?trace|bug)\b", 1.0),
+            Self::new(TaskType::CodeAnalysis, r"\b(rust|python|javascript|typescript|java|sql|regex)\b", 0.5),
+            Self::new(TaskType::CodeAnalysis, r"```", 1.5),
+            Self::new(TaskType::Planning, r"\b(plan|planning|todo|roadmap|schedule|milestones?|prioriti[sz]e)\b", 1.0),
+            Self::new(TaskType::Planning, r"\b(steps|next week|by friday|deadline)\b", 0.5),
+            Self::new(TaskType::DataProcessing, r"\b(json|csv|yaml|xml|dataset|data|table|spreadsheet)\b", 1.0),
+            Self::new(TaskType::DataProcessing, r"\b(parse|extract|convert|transform|aggregate)\b", 0.5),
+        ]
+    }
+}
+
+/// Weighted keyword/regex rules. The task type with the highest total score
+/// wins; confidence is its share of all matched weight.
+pub struct RuleClassifier {
+    rules: Vec<(TaskType, Regex, f64)>,
+}
+
+impl RuleClassifier {
+    /// Compiles `rules`, skipping (and logging) any with an invalid pattern.
+    pub fn new(rules: &[ClassificationRule]) -> Self {
+        let rules = rules.iter()
+            .filter_map(|rule| match rule.compile() {
+                Ok(regex) => Some((rule.task_type.clone(), regex, rule.weight)),
+                Err(e) => {
+                    log::warn!("Skipping classification rule: {}", e);
+                    None
+                }
+            })
+            .collect();
+
+        RuleClassifier { rules }
+    }
+}
+
+#[async_trait]
+impl TaskClassifier for RuleClassifier {
+    fn name(&self) -> &'static str {
+        "rules"
+    }
+
+    async fn classify(&self, input: &str) -> Result<Classification, SophiaError> {
+        let mut scores: HashMap<TaskType, f64> = HashMap::new();
+        for (task_type, regex, weight) in &self.rules {
+            if regex.is_match(input) {
+                *scores.entry(task_type.clone()).or_default() += weight;
+            }
+        }
+
+        let total: f64 = scores.values().sum();
+        let best = scores.into_iter()
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+
+        let (task_type, confidence) = match best {
+            Some((task_type, score)) if total > 0.0 => (task_type, score / total),
+            _ => (TaskType::GeneralChat, 0.3),
+        };
+
+        Ok(Classification {
+            task_type,
+            confidence,
+            classifier: self.name().to_string(),
+        })
+    }
+}
+
+const LLM_CLASSIFIER_PROMPT: &str = "Classify the user's request into exactly one task type: \
+GeneralChat, CodeAnalysis, Planning, DataProcessing. \
+Reply with JSON only, for example {\"task_type\": \"Planning\", \"confidence\": 0.8}.";
+
+#[derive(Deserialize)]
+struct LlmLabel {
+    task_type: TaskType,
+    confidence: f64,
+}
+
+/// Asks a (cheap) model for a JSON label. Its usage is billed like any other call.
+pub struct LlmClassifier {
+    client: Box<dyn LLMClient>,
+    provider: ProviderType,
+    model: String,
+    storage: Arc<StorageManager>,
+}
+
+impl LlmClassifier {
+    pub fn new(client: Box<dyn LLMClient>, provider: ProviderType, model: String, storage: Arc<StorageManager>) -> Self {
+        LlmClassifier { client, provider, model, storage }
+    }
+
+    /// Pulls the first `{...}` object out of the reply, tolerating code fences
+    /// and chatter around it.
+    fn parse_label(text: &str) -> Result<LlmLabel, String> {
+        let start = text.find('{').ok_or("Classifier reply contained no JSON")?;
+        let end = text.rfind('}').ok_or("Classifier reply contained no JSON")?;
+        if end < start {
+            return Err("Classifier reply contained no JSON".to_string());
+        }
+        serde_json::from_str(&text[start..=end])
+            .map_err(|e| format!("Unreadable classifier reply: {}", e))
+    }
+}
+
+#[async_trait]
+impl TaskClassifier for LlmClassifier {
+    fn name(&self) -> &'static str {
+        "llm"
+    }
+
+    async fn classify(&self, input: &str) -> Result<Classification, SophiaError> {
+        let messages = [
+            ChatMessage::new(ChatRole::System, LLM_CLASSIFIER_PROMPT),
+            ChatMessage::user(input),
+        ];
+        // The provider's own system prompt and sampling settings are meant for
+        // conversations, not for picking a label
+        let params = GenerationParams { temperature: Some(0.0), ..GenerationParams::default() };
+        let completion = self.client.chat(&self.model, &messages, &params).await?;
+
+        let estimated_prompt_tokens = messages.iter()
+            .map(|m| crate::storage::estimate_tokens(&m.content))
+            .sum();
+        crate::router::core::record_usage(&self.storage, &self.provider, &self.model, estimated_prompt_tokens, &completion);
+
+        let label = Self::parse_label(&completion.text)
+            .map_err(SophiaError::ProviderResponseInvalid)?;
+        Ok(Classification {
+            task_type: label.task_type,
+            confidence: label.confidence.clamp(0.0, 1.0),
+            classifier: format!("{}:{}/{}", self.name(), self.provider.as_str(), self.model),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rules_use_word_boundaries() {
+        let classifier = RuleClassifier::new(&ClassificationRule::defaults());
+
+        let result = classifier.classify("Decode this base64 string for me").await.unwrap();
+        assert_ne!(result.task_type, TaskType::CodeAnalysis);
+
+        let result = classifier.classify("Why does this Function panic?").await.unwrap();
+        assert_eq!(result.task_type, TaskType::CodeAnalysis);
+        assert_eq!(result.classifier, "rules");
+
+        let result = classifier.classify("hello there").await.unwrap();
+        assert_eq!(result.task_type, TaskType::GeneralChat);
+    }
+
+    #[tokio::test]
+    async fn test_rule_confidence_is_share_of_weight() {
+        let rules = vec![
+            ClassificationRule::new(TaskType::Planning, r"\bplan\b", 3.0),
+            ClassificationRule::new(TaskType::DataProcessing, r"\bcsv\b", 1.0),
+            ClassificationRule::new(TaskType::CodeAnalysis, r"(unclosed", 5.0),
+        ];
+        let classifier = RuleClassifier::new(&rules);
+
+        let result = classifier.classify("plan the csv import").await.unwrap();
+        assert_eq!(result.task_type, TaskType::Planning);
+        assert!((result.confidence - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_llm_label_parsing() {
+        let label = LlmClassifier::parse_label("```json\n{\"task_type\": \"Planning\", \"confidence\": 0.9}\n```").unwrap();
+        assert_eq!(label.task_type, TaskType::Planning);
+        assert!(LlmClassifier::parse_label("I think it's planning").is_err());
+        assert!(LlmClassifier::parse_label("{\"task_type\": \"Cooking\", \"confidence\": 1}").is_err());
+    }
+}
@@ -1,324 +1,1077 @@
+use crate::error::SophiaError;
+use crate::redact::redact;
+use crate::router::retry::parse_retry_after;
+use crate::router::types::{AuthScheme, ChatMessage, ChatRole, Completion, GenerationParams, LocalModel, ModelInfo, TokenUsage};
+use crate::secret_store::SecretString;
+use async_trait::async_trait;
+use serde::Serialize;
 use serde_json::{json, Value};
-use std::error::Error;
+use std::borrow::Cow;
+use std::time::Duration;
 
-pub trait LLMClient {
-    fn complete(&self, model: &str, prompt: &str) -> Result<String, Box<dyn Error>>;
+// Single-shot requests must answer within this budget.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+// Streams stay open for as long as the model keeps generating, so they get a
+// longer budget than the 30s used for single-shot requests.
+const STREAM_TIMEOUT: Duration = Duration::from_secs(300);
+// Each of the concurrent `/api/show` lookups behind the local model picker
+// gets this long, so many models or a slow remote server can't stall it.
+const OLLAMA_SHOW_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Receives each text fragment of a streamed reply.
+pub type DeltaFn<'a> = dyn FnMut(&str) + Send + 'a;
+
+/// A chat-capable provider. Calls are async so that dropping the returned
+/// future (on cancel or pause) aborts the underlying HTTP request.
+#[async_trait]
+pub trait LLMClient: Send + Sync {
+    /// Sends a full conversation and returns the assistant's reply.
+    async fn chat(&self, model: &str, messages: &[ChatMessage], params: &GenerationParams) -> Result<Completion, SophiaError>;
+
+    /// Streams the reply to a conversation, calling `on_delta` with each text
+    /// fragment as it arrives. Returns the full text once the stream has finished.
+    ///
+    /// Clients without native streaming fall back to a single delta.
+    async fn chat_stream(
+        &self,
+        model: &str,
+        messages: &[ChatMessage],
+        params: &GenerationParams,
+        on_delta: &mut DeltaFn<'_>,
+    ) -> Result<Completion, SophiaError> {
+        let completion = self.chat(model, messages, params).await?;
+        on_delta(&completion.text);
+        Ok(completion)
+    }
+
+    /// The models this provider offers, from its model-list endpoint.
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, SophiaError>;
+
+    /// Single-turn convenience wrapper around `chat`.
+    async fn complete(&self, model: &str, prompt: &str) -> Result<Completion, SophiaError> {
+        self.chat(model, &[ChatMessage::user(prompt)], &GenerationParams::default()).await
+    }
+
+    /// Single-turn convenience wrapper around `chat_stream`.
+    async fn complete_stream(
+        &self,
+        model: &str,
+        prompt: &str,
+        on_delta: &mut DeltaFn<'_>,
+    ) -> Result<Completion, SophiaError> {
+        self.chat_stream(model, &[ChatMessage::user(prompt)], &GenerationParams::default(), on_delta).await
+    }
+}
+
+/// Anthropic requires an output budget; used when the config sets none.
+const ANTHROPIC_DEFAULT_MAX_TOKENS: u32 = 4096;
+
+/// Prepends the configured system prompt as a system turn, which each body
+/// builder then maps to its provider's native field.
+fn with_system_prompt<'a>(messages: &'a [ChatMessage], params: &GenerationParams) -> Cow<'a, [ChatMessage]> {
+    match params.system_prompt.as_deref().filter(|prompt| !prompt.trim().is_empty()) {
+        Some(prompt) => {
+            let mut all = Vec::with_capacity(messages.len() + 1);
+            all.push(ChatMessage::new(ChatRole::System, prompt));
+            all.extend_from_slice(messages);
+            Cow::Owned(all)
+        },
+        None => Cow::Borrowed(messages),
+    }
+}
+
+/// Sets `key` only if there is a value, so the provider default applies otherwise.
+fn set_some<T: Serialize>(body: &mut Value, key: &str, value: Option<T>) {
+    if let Some(value) = value {
+        body[key] = json!(value);
+    }
+}
+
+/// Chat completions body shared by every OpenAI-compatible provider.
+fn openai_body(model: &str, messages: &[ChatMessage], params: &GenerationParams) -> Value {
+    let mut body = json!({
+        "model": model,
+        "messages": openai_messages(&with_system_prompt(messages, params))
+    });
+    set_some(&mut body, "temperature", params.temperature);
+    set_some(&mut body, "top_p", params.top_p);
+    set_some(&mut body, "max_tokens", params.max_output_tokens);
+    if !params.stop_sequences.is_empty() {
+        body["stop"] = json!(params.stop_sequences);
+    }
+    set_some(&mut body, "seed", params.seed);
+    body
+}
+
+/// Ollama takes the OpenAI message format, with sampling settings under `options`.
+fn ollama_body(model: &str, messages: &[ChatMessage], params: &GenerationParams) -> Value {
+    let mut options = json!({});
+    set_some(&mut options, "temperature", params.temperature);
+    set_some(&mut options, "top_p", params.top_p);
+    set_some(&mut options, "num_predict", params.max_output_tokens);
+    if !params.stop_sequences.is_empty() {
+        options["stop"] = json!(params.stop_sequences);
+    }
+    set_some(&mut options, "seed", params.seed);
+
+    json!({
+        "model": model,
+        "messages": openai_messages(&with_system_prompt(messages, params)),
+        "options": options
+    })
+}
+
+/// Anthropic has no seed parameter, so a configured seed is not sent.
+fn anthropic_body(model: &str, messages: &[ChatMessage], params: &GenerationParams) -> Value {
+    let (system, turns) = anthropic_messages(&with_system_prompt(messages, params));
+    let mut body = json!({
+        "model": model,
+        "messages": turns,
+        "max_tokens": params.max_output_tokens.unwrap_or(ANTHROPIC_DEFAULT_MAX_TOKENS)
+    });
+    set_some(&mut body, "system", system);
+    set_some(&mut body, "temperature", params.temperature);
+    set_some(&mut body, "top_p", params.top_p);
+    if !params.stop_sequences.is_empty() {
+        body["stop_sequences"] = json!(params.stop_sequences);
+    }
+    body
+}
+
+/// Maps a conversation to the OpenAI-style `messages` array (also used by Ollama).
+fn openai_messages(messages: &[ChatMessage]) -> Value {
+    Value::Array(
+        messages.iter()
+            .map(|m| json!({"role": m.role.as_str(), "content": m.content}))
+            .collect()
+    )
+}
+
+/// Maps a conversation to Anthropic's format: system turns are lifted into the
+/// top-level `system` field and consecutive turns from the same role are merged,
+/// since the Messages API requires strictly alternating user/assistant turns.
+fn anthropic_messages(messages: &[ChatMessage]) -> (Option<String>, Value) {
+    let system = messages.iter()
+        .filter(|m| m.role == ChatRole::System)
+        .map(|m| m.content.as_str())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    let mut turns: Vec<(ChatRole, String)> = Vec::new();
+    for m in messages.iter().filter(|m| m.role != ChatRole::System) {
+        match turns.last_mut() {
+            Some((role, content)) if *role == m.role => {
+                content.push_str("\n\n");
+                content.push_str(&m.content);
+            },
+            _ => turns.push((m.role, m.content.clone())),
+        }
+    }
+
+    let turns = turns.into_iter()
+        .map(|(role, content)| json!({"role": role.as_str(), "content": content}))
+        .collect();
+
+    ((!system.is_empty()).then_some(system), Value::Array(turns))
+}
+
+/// Builds a Gemini request body: `user`/`model` roles in `contents`, with system
+/// turns sent as `systemInstruction` and sampling settings as `generationConfig`.
+fn gemini_body(messages: &[ChatMessage], params: &GenerationParams) -> Value {
+    let messages = with_system_prompt(messages, params);
+    let contents: Vec<Value> = messages.iter()
+        .filter(|m| m.role != ChatRole::System)
+        .map(|m| {
+            let role = if m.role == ChatRole::Assistant { "model" } else { "user" };
+            json!({"role": role, "parts": [{ "text": m.content }]})
+        })
+        .collect();
+
+    let mut body = json!({ "contents": contents });
+
+    let system: Vec<Value> = messages.iter()
+        .filter(|m| m.role == ChatRole::System)
+        .map(|m| json!({ "text": m.content }))
+        .collect();
+    if !system.is_empty() {
+        body["systemInstruction"] = json!({ "parts": system });
+    }
+
+    let mut config = json!({});
+    set_some(&mut config, "temperature", params.temperature);
+    set_some(&mut config, "topP", params.top_p);
+    set_some(&mut config, "maxOutputTokens", params.max_output_tokens);
+    if !params.stop_sequences.is_empty() {
+        config["stopSequences"] = json!(params.stop_sequences);
+    }
+    set_some(&mut config, "seed", params.seed);
+    if config.as_object().is_some_and(|c| !c.is_empty()) {
+        body["generationConfig"] = config;
+    }
+
+    body
+}
+
+/// Sends a JSON request body, see `dispatch`.
+async fn send(provider: &str, request: reqwest::RequestBuilder, body: &Value) -> Result<reqwest::Response, SophiaError> {
+    dispatch(provider, request.json(body)).await
+}
+
+/// Sends a request, turning transport failures and non-success statuses into
+/// categorized `SophiaError`s.
+async fn dispatch(provider: &str, request: reqwest::RequestBuilder) -> Result<reqwest::Response, SophiaError> {
+    log::info!("Sending request to {} API...", provider);
+    let response = request
+        .send()
+        .await
+        .map_err(|e| SophiaError::from_reqwest(provider, e))?;
+
+    let status = response.status();
+    log::info!("Received response with status: {}", status);
+
+    if !status.is_success() {
+        let retry_after = response.headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_retry_after);
+        let body = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+        log::error!("{} API error response {}: {}", provider, status.as_u16(), redact(&body));
+        return Err(SophiaError::from_status(provider, status.as_u16(), &body, retry_after));
+    }
+
+    Ok(response)
+}
+
+/// Sends a single-shot request and returns the decoded JSON body.
+async fn send_json(provider: &str, request: reqwest::RequestBuilder, body: &Value) -> Result<Value, SophiaError> {
+    send(provider, request.timeout(REQUEST_TIMEOUT), body).await?
+        .json()
+        .await
+        .map_err(|e| SophiaError::from_reqwest(provider, e))
+}
+
+/// Sends a bodiless request (e.g. a GET) and returns the decoded JSON body.
+async fn fetch_json(provider: &str, request: reqwest::RequestBuilder) -> Result<Value, SophiaError> {
+    dispatch(provider, request.timeout(REQUEST_TIMEOUT)).await?
+        .json()
+        .await
+        .map_err(|e| SophiaError::from_reqwest(provider, e))
+}
+
+/// Splits a chunked body into lines. Chunks may end mid-line (or mid-character),
+/// so bytes are held back until their line is complete.
+#[derive(Default)]
+struct LineBuffer {
+    pending: Vec<u8>,
+}
+
+impl LineBuffer {
+    fn push(&mut self, chunk: &[u8]) {
+        self.pending.extend_from_slice(chunk);
+    }
+
+    fn next_line(&mut self) -> Option<String> {
+        let end = self.pending.iter().position(|b| *b == b'\n')?;
+        let line: Vec<u8> = self.pending.drain(..=end).collect();
+        Some(String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    /// Whatever is left once the body has ended without a trailing newline.
+    fn finish(self) -> Option<String> {
+        (!self.pending.is_empty()).then(|| String::from_utf8_lossy(&self.pending).trim_end().to_string())
+    }
+}
+
+/// Incremental parser for one provider's streaming format, fed line by line.
+trait StreamParser {
+    /// Handles one line of the body. Returns false once the stream is complete.
+    fn line(&mut self, line: &str, on_delta: &mut DeltaFn<'_>) -> Result<bool, SophiaError>;
+
+    fn finish(self) -> Completion;
+}
+
+/// Sends a streaming request and feeds the body to `parser` as lines arrive.
+async fn read_stream<P: StreamParser + Send>(
+    provider: &str,
+    request: reqwest::RequestBuilder,
+    body: &Value,
+    mut parser: P,
+    on_delta: &mut DeltaFn<'_>,
+) -> Result<Completion, SophiaError> {
+    log::info!("Opening {} stream...", provider);
+    let mut response = send(provider, request.timeout(STREAM_TIMEOUT), body).await?;
+    let mut lines = LineBuffer::default();
+
+    while let Some(chunk) = response.chunk().await.map_err(|e| SophiaError::from_reqwest(provider, e))? {
+        lines.push(&chunk);
+        while let Some(line) = lines.next_line() {
+            if !parser.line(&line, on_delta)? {
+                return Ok(parser.finish());
+            }
+        }
+    }
+
+    if let Some(line) = lines.finish() {
+        parser.line(&line, on_delta)?;
+    }
+    Ok(parser.finish())
+}
+
+/// One line of a `text/event-stream` body.
+enum SseLine<'a> {
+    Data(&'a str),
+    /// The OpenAI-style `[DONE]` sentinel.
+    Done,
+    /// Event names, comments, keep-alives and blank separators.
+    Skip,
+}
+
+fn sse_line(line: &str) -> SseLine<'_> {
+    match line.strip_prefix("data:").map(str::trim) {
+        Some("[DONE]") => SseLine::Done,
+        Some(data) if !data.is_empty() => SseLine::Data(data),
+        _ => SseLine::Skip,
+    }
+}
+
+/// `usage` block of an OpenAI-compatible response (OpenAI, DeepSeek, OpenRouter).
+fn openai_usage(json: &Value) -> Option<TokenUsage> {
+    let usage = &json["usage"];
+    Some(TokenUsage::new(
+        usage["prompt_tokens"].as_i64()?,
+        usage["completion_tokens"].as_i64()?,
+        usage["total_tokens"].as_i64(),
+    ))
+}
+
+/// `usage` block of an Anthropic message (or of a streamed message event).
+fn anthropic_usage(usage: &Value) -> Option<TokenUsage> {
+    Some(TokenUsage::new(
+        usage["input_tokens"].as_i64()?,
+        usage["output_tokens"].as_i64()?,
+        None,
+    ))
+}
+
+/// `usageMetadata` of a Gemini response. `candidatesTokenCount` is omitted when
+/// nothing was generated.
+fn gemini_usage(json: &Value) -> Option<TokenUsage> {
+    let usage = &json["usageMetadata"];
+    Some(TokenUsage::new(
+        usage["promptTokenCount"].as_i64()?,
+        usage["candidatesTokenCount"].as_i64().unwrap_or(0),
+        usage["totalTokenCount"].as_i64(),
+    ))
+}
+
+/// Token counters on Ollama's final response object.
+fn ollama_usage(json: &Value) -> Option<TokenUsage> {
+    Some(TokenUsage::new(
+        json["prompt_eval_count"].as_i64()?,
+        json["eval_count"].as_i64()?,
+        None,
+    ))
+}
+
+fn json_string(value: &Value) -> Option<String> {
+    value.as_str().map(|s| s.to_string())
+}
+
+fn invalid_response(provider: &str) -> SophiaError {
+    SophiaError::ProviderResponseInvalid(format!("Invalid {} response format", provider))
+}
+
+/// Decodes one line of a streamed body.
+fn parse_chunk(data: &str) -> Result<Value, SophiaError> {
+    serde_json::from_str(data)
+        .map_err(|e| SophiaError::ProviderResponseInvalid(format!("Unreadable stream chunk: {}", e)))
+}
+
+/// An error event sent in the middle of a stream. These are overloads and rate
+/// limits in practice, since bad requests are rejected before streaming starts.
+fn stream_error(label: &str, err: &Value) -> SophiaError {
+    let message = format!("{}: {}", label, err);
+    if err.to_string().to_lowercase().contains("rate_limit") {
+        SophiaError::RateLimited { message, retry_after: None }
+    } else {
+        SophiaError::ProviderUnavailable { message, retry_after: None }
+    }
+}
+
+pub(crate) fn parse_openai_response(provider: &str, json: &Value) -> Result<Completion, SophiaError> {
+    if json["choices"][0]["finish_reason"] == "content_filter" && json["choices"][0]["message"]["content"].is_null() {
+        return Err(SophiaError::ContentFiltered(format!("{} blocked the reply (content_filter)", provider)));
+    }
+
+    let text = json["choices"][0]["message"]["content"]
+        .as_str()
+        .ok_or_else(|| invalid_response(provider))?
+        .to_string();
+
+    Ok(Completion {
+        text,
+        usage: openai_usage(json),
+        finish_reason: json_string(&json["choices"][0]["finish_reason"]),
+        request_id: json_string(&json["id"]),
+    })
+}
+
+/// Joins every text block; other blocks (tool use, thinking) carry no reply text.
+pub(crate) fn parse_anthropic_response(json: &Value) -> Result<Completion, SophiaError> {
+    let text = json["content"]
+        .as_array()
+        .ok_or_else(|| invalid_response("Anthropic"))?
+        .iter()
+        .filter(|block| block["type"] == "text")
+        .filter_map(|block| block["text"].as_str())
+        .collect::<String>();
+
+    Ok(Completion {
+        text,
+        usage: anthropic_usage(&json["usage"]),
+        finish_reason: json_string(&json["stop_reason"]),
+        request_id: json_string(&json["id"]),
+    })
+}
+
+pub(crate) fn parse_gemini_response(json: &Value) -> Result<Completion, SophiaError> {
+    if let Some(reason) = gemini_block_reason(json) {
+        return Err(SophiaError::ContentFiltered(format!("Gemini blocked the request ({})", reason)));
+    }
+
+    let text = json["candidates"][0]["content"]["parts"][0]["text"]
+        .as_str()
+        .ok_or_else(|| invalid_response("Gemini"))?
+        .to_string();
+
+    Ok(Completion {
+        text,
+        usage: gemini_usage(json),
+        finish_reason: json_string(&json["candidates"][0]["finishReason"]),
+        request_id: json_string(&json["responseId"]),
+    })
+}
+
+/// Why Gemini withheld a reply: a blocked prompt, or a candidate stopped by a
+/// safety filter before producing any text.
+pub(crate) fn gemini_block_reason(json: &Value) -> Option<String> {
+    if let Some(reason) = json_string(&json["promptFeedback"]["blockReason"]) {
+        return Some(reason);
+    }
+    let candidate = &json["candidates"][0];
+    let reason = candidate["finishReason"].as_str()?;
+    let blocked = matches!(reason, "SAFETY" | "PROHIBITED_CONTENT" | "BLOCKLIST" | "SPII");
+    (blocked && candidate["content"]["parts"][0]["text"].is_null()).then(|| reason.to_string())
+}
+
+pub(crate) fn parse_ollama_response(json: &Value) -> Result<Completion, SophiaError> {
+    let text = json["message"]["content"]
+        .as_str()
+        .ok_or_else(|| invalid_response("Ollama"))?
+        .to_string();
+
+    Ok(Completion {
+        text,
+        usage: ollama_usage(json),
+        finish_reason: json_string(&json["done_reason"]),
+        request_id: None,
+    })
+}
+
+/// Parses an OpenAI-compatible chat completion stream (OpenAI, DeepSeek, OpenRouter).
+/// Usage only arrives in the final chunk when `stream_options.include_usage` is set.
+#[derive(Default)]
+pub(crate) struct OpenAIStream {
+    completion: Completion,
+}
+
+impl StreamParser for OpenAIStream {
+    fn line(&mut self, line: &str, on_delta: &mut DeltaFn<'_>) -> Result<bool, SophiaError> {
+        let data = match sse_line(line) {
+            SseLine::Data(data) => data,
+            SseLine::Done => return Ok(false),
+            SseLine::Skip => return Ok(true),
+        };
+
+        let json = parse_chunk(data)?;
+        if let Some(err) = json.get("error") {
+            return Err(stream_error("Stream error", err));
+        }
+        let completion = &mut self.completion;
+        if completion.request_id.is_none() {
+            completion.request_id = json_string(&json["id"]);
+        }
+        if let Some(delta) = json["choices"][0]["delta"]["content"].as_str() {
+            if !delta.is_empty() {
+                completion.text.push_str(delta);
+                on_delta(delta);
+            }
+        }
+        if let Some(reason) = json_string(&json["choices"][0]["finish_reason"]) {
+            completion.finish_reason = Some(reason);
+        }
+        if let Some(usage) = openai_usage(&json) {
+            completion.usage = Some(usage);
+        }
+        Ok(true)
+    }
+
+    fn finish(self) -> Completion {
+        self.completion
+    }
+}
+
+/// Parses an Anthropic Messages API stream. Input tokens and the message ID come
+/// with `message_start`; output tokens and the stop reason with `message_delta`.
+#[derive(Default)]
+pub(crate) struct AnthropicStream {
+    completion: Completion,
+    input_tokens: Option<i64>,
+    output_tokens: Option<i64>,
+}
+
+impl StreamParser for AnthropicStream {
+    fn line(&mut self, line: &str, on_delta: &mut DeltaFn<'_>) -> Result<bool, SophiaError> {
+        let data = match sse_line(line) {
+            SseLine::Data(data) => data,
+            SseLine::Done => return Ok(false),
+            SseLine::Skip => return Ok(true),
+        };
+
+        let json = parse_chunk(data)?;
+        match json["type"].as_str() {
+            Some("message_start") => {
+                self.completion.request_id = json_string(&json["message"]["id"]);
+                self.input_tokens = json["message"]["usage"]["input_tokens"].as_i64();
+                Ok(true)
+            },
+            Some("content_block_delta") => {
+                if let Some(delta) = json["delta"]["text"].as_str() {
+                    self.completion.text.push_str(delta);
+                    on_delta(delta);
+                }
+                Ok(true)
+            },
+            Some("message_delta") => {
+                self.completion.finish_reason = json_string(&json["delta"]["stop_reason"]);
+                self.output_tokens = json["usage"]["output_tokens"].as_i64();
+                Ok(true)
+            },
+            Some("message_stop") => Ok(false),
+            Some("error") => Err(stream_error("Stream error", &json["error"])),
+            _ => Ok(true),
+        }
+    }
+
+    fn finish(mut self) -> Completion {
+        if let (Some(input), Some(output)) = (self.input_tokens, self.output_tokens) {
+            self.completion.usage = Some(TokenUsage::new(input, output, None));
+        }
+        self.completion
+    }
+}
+
+/// Parses a Gemini `streamGenerateContent?alt=sse` stream. Every chunk carries
+/// cumulative `usageMetadata`, so the last one wins.
+#[derive(Default)]
+pub(crate) struct GeminiStream {
+    completion: Completion,
+}
+
+impl StreamParser for GeminiStream {
+    fn line(&mut self, line: &str, on_delta: &mut DeltaFn<'_>) -> Result<bool, SophiaError> {
+        let data = match sse_line(line) {
+            SseLine::Data(data) => data,
+            SseLine::Done => return Ok(false),
+            SseLine::Skip => return Ok(true),
+        };
+
+        let json = parse_chunk(data)?;
+        if let Some(err) = json.get("error") {
+            return Err(stream_error("Stream error", err));
+        }
+        let completion = &mut self.completion;
+        if let Some(parts) = json["candidates"][0]["content"]["parts"].as_array() {
+            for delta in parts.iter().filter_map(|p| p["text"].as_str()) {
+                completion.text.push_str(delta);
+                on_delta(delta);
+            }
+        }
+        if let Some(reason) = json_string(&json["candidates"][0]["finishReason"]) {
+            completion.finish_reason = Some(reason);
+        }
+        if let Some(id) = json_string(&json["responseId"]) {
+            completion.request_id = Some(id);
+        }
+        if let Some(usage) = gemini_usage(&json) {
+            completion.usage = Some(usage);
+        }
+        Ok(true)
+    }
+
+    fn finish(self) -> Completion {
+        self.completion
+    }
+}
+
+/// Parses Ollama's newline-delimited `/api/chat` stream. Counters and the done
+/// reason are only on the final (`"done": true`) object.
+#[derive(Default)]
+pub(crate) struct OllamaStream {
+    completion: Completion,
+}
+
+impl StreamParser for OllamaStream {
+    fn line(&mut self, line: &str, on_delta: &mut DeltaFn<'_>) -> Result<bool, SophiaError> {
+        if line.trim().is_empty() {
+            return Ok(true);
+        }
+
+        let json = parse_chunk(line)?;
+        if json["error"].is_string() {
+            return Err(stream_error("Ollama stream error", &json["error"]));
+        }
+        let completion = &mut self.completion;
+        if let Some(delta) = json["message"]["content"].as_str() {
+            if !delta.is_empty() {
+                completion.text.push_str(delta);
+                on_delta(delta);
+            }
+        }
+        if json["done"].as_bool().unwrap_or(false) {
+            completion.usage = ollama_usage(&json);
+            completion.finish_reason = json_string(&json["done_reason"]);
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn finish(self) -> Completion {
+        self.completion
+    }
 }
 
 pub struct OllamaClient {
     endpoint: String,
-    client: reqwest::blocking::Client,
+    client: reqwest::Client,
 }
 
 impl OllamaClient {
     pub fn new(endpoint: &str) -> Self {
         OllamaClient {
-            endpoint: endpoint.to_string(),
-            client: reqwest::blocking::Client::new(),
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Models installed on the server (`/api/tags`), with context length read
+    /// from each model's metadata (`/api/show`). The metadata calls run
+    /// concurrently, each with `OLLAMA_SHOW_TIMEOUT`; a model whose metadata
+    /// can't be read in time is still listed, just without a context length.
+    pub async fn list_local_models(&self) -> Result<Vec<LocalModel>, SophiaError> {
+        let url = format!("{}/api/tags", self.endpoint);
+        let tags = fetch_json("Ollama", self.client.get(&url)).await?;
+        let mut models = parse_ollama_tags(&tags)?;
+
+        // Dropping the set (e.g. on cancel) aborts any lookups still running
+        let url = format!("{}/api/show", self.endpoint);
+        let mut lookups = tokio::task::JoinSet::new();
+        for (index, model) in models.iter().enumerate() {
+            let request = self.client.post(&url);
+            let body = json!({ "model": model.name });
+            lookups.spawn(async move {
+                (index, tokio::time::timeout(OLLAMA_SHOW_TIMEOUT, send_json("Ollama", request, &body)).await)
+            });
+        }
+
+        while let Some(lookup) = lookups.join_next().await {
+            let Ok((index, result)) = lookup else { continue };
+            let model = &mut models[index];
+            match result {
+                Ok(Ok(info)) => model.context_length = ollama_context_length(&info),
+                Ok(Err(e)) => log::warn!("Could not read metadata for Ollama model {}: {}", model.name, e),
+                Err(_) => log::warn!("Timed out reading metadata for Ollama model {}", model.name),
+            }
         }
+
+        Ok(models)
     }
 }
 
+/// `data` array of an OpenAI-style `/models` listing (OpenAI, DeepSeek,
+/// OpenRouter). OpenRouter adds a display name and context length.
+pub(crate) fn parse_openai_models(provider: &str, json: &Value) -> Result<Vec<ModelInfo>, SophiaError> {
+    let data = json["data"].as_array().ok_or_else(|| invalid_response(provider))?;
+
+    Ok(data.iter()
+        .filter_map(|m| Some(ModelInfo {
+            id: json_string(&m["id"])?,
+            display_name: json_string(&m["name"]),
+            context_length: m["context_length"].as_u64(),
+        }))
+        .collect())
+}
+
+pub(crate) fn parse_anthropic_models(json: &Value) -> Result<Vec<ModelInfo>, SophiaError> {
+    let data = json["data"].as_array().ok_or_else(|| invalid_response("Anthropic"))?;
+
+    Ok(data.iter()
+        .filter_map(|m| Some(ModelInfo {
+            id: json_string(&m["id"])?,
+            display_name: json_string(&m["display_name"]),
+            context_length: None,
+        }))
+        .collect())
+}
+
+/// Gemini lists embedding and other models too; only those that can
+/// `generateContent` are usable for chat. Names come as `models/<id>`.
+pub(crate) fn parse_gemini_models(json: &Value) -> Result<Vec<ModelInfo>, SophiaError> {
+    let models = match json.get("models") {
+        Some(models) => models.as_array().ok_or_else(|| invalid_response("Gemini"))?,
+        // An empty page omits the field entirely
+        None => return Ok(Vec::new()),
+    };
+
+    Ok(models.iter()
+        .filter(|m| m["supportedGenerationMethods"].as_array()
+            .is_some_and(|methods| methods.iter().any(|x| x == "generateContent")))
+        .filter_map(|m| Some(ModelInfo {
+            id: m["name"].as_str()?.trim_start_matches("models/").to_string(),
+            display_name: json_string(&m["displayName"]),
+            context_length: m["inputTokenLimit"].as_u64(),
+        }))
+        .collect())
+}
+
+pub(crate) fn parse_ollama_tags(json: &Value) -> Result<Vec<LocalModel>, SophiaError> {
+    let models = json["models"].as_array().ok_or_else(|| invalid_response("Ollama"))?;
+
+    Ok(models.iter()
+        .filter_map(|m| {
+            let details = &m["details"];
+            Some(LocalModel {
+                name: json_string(&m["name"])?,
+                size_bytes: m["size"].as_u64().unwrap_or(0),
+                parameter_size: json_string(&details["parameter_size"]),
+                quantization: json_string(&details["quantization_level"]),
+                family: json_string(&details["family"]),
+                context_length: None,
+            })
+        })
+        .collect())
+}
+
+/// `model_info` keys are prefixed with the architecture, e.g. `llama.context_length`.
+fn ollama_context_length(json: &Value) -> Option<u64> {
+    json["model_info"].as_object()?
+        .iter()
+        .find(|(key, _)| key.ends_with(".context_length"))
+        .and_then(|(_, value)| value.as_u64())
+}
+
+#[async_trait]
 impl LLMClient for OllamaClient {
-    fn complete(&self, model: &str, prompt: &str) -> Result<String, Box<dyn Error>> {
-        let url = format!("{}/api/generate", self.endpoint);
-        let body = json!({
-            "model": model,
-            "prompt": prompt,
-            "stream": false
-        });
+    async fn chat(&self, model: &str, messages: &[ChatMessage], params: &GenerationParams) -> Result<Completion, SophiaError> {
+        let url = format!("{}/api/chat", self.endpoint);
+        let mut body = ollama_body(model, messages, params);
+        body["stream"] = json!(false);
 
-        let res = self.client.post(&url)
-            .json(&body)
-            .send()?;
+        let json = send_json("Ollama", self.client.post(&url), &body).await?;
+        parse_ollama_response(&json)
+    }
 
-        if !res.status().is_success() {
-            return Err(format!("Ollama API Error: {}", res.status()).into());
-        }
+    async fn chat_stream(&self, model: &str, messages: &[ChatMessage], params: &GenerationParams, on_delta: &mut DeltaFn<'_>) -> Result<Completion, SophiaError> {
+        let url = format!("{}/api/chat", self.endpoint);
+        let mut body = ollama_body(model, messages, params);
+        body["stream"] = json!(true);
 
-        let json: Value = res.json()?;
-        let response = json["response"].as_str()
-            .ok_or("Invalid response format")?
-            .to_string();
+        read_stream("Ollama", self.client.post(&url), &body, OllamaStream::default(), on_delta).await
+    }
 
-        Ok(response)
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, SophiaError> {
+        let url = format!("{}/api/tags", self.endpoint);
+        let tags = fetch_json("Ollama", self.client.get(&url)).await?;
+
+        Ok(parse_ollama_tags(&tags)?
+            .into_iter()
+            .map(|m| ModelInfo { id: m.name, display_name: None, context_length: None })
+            .collect())
     }
 }
 
 pub struct GeminiClient {
     endpoint: String,
-    api_key: String,
+    api_key: SecretString,
+    client: reqwest::Client,
 }
 
 impl GeminiClient {
-    pub fn new(endpoint: &str, api_key: &str) -> Self {
+    pub fn new(endpoint: &str, api_key: impl Into<SecretString>) -> Self {
         GeminiClient {
             endpoint: endpoint.to_string(),
-            api_key: api_key.to_string(),
+            api_key: api_key.into(),
+            client: reqwest::Client::new(),
         }
     }
+
+    /// Sends the key in `x-goog-api-key` rather than the `key=` query
+    /// parameter, so it never shows up in URLs logged with request errors.
+    fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        request.header("x-goog-api-key", self.api_key.expose_secret())
+    }
 }
 
+#[async_trait]
 impl LLMClient for GeminiClient {
-    fn complete(&self, model: &str, prompt: &str) -> Result<String, Box<dyn Error>> {
+    async fn chat(&self, model: &str, messages: &[ChatMessage], params: &GenerationParams) -> Result<Completion, SophiaError> {
         log::info!("Gemini API call starting for model: {}", model);
         // Gemini API URL format: endpoint already includes /v1 or /v1beta
-        let url = format!("{}/models/{}:generateContent?key={}", self.endpoint, model, self.api_key);
-        let body = json!({
-            "contents": [{
-                "parts": [{ "text": prompt }]
-            }]
-        });
-
-        log::info!("Sending request to Gemini API...");
-        
-        // Use ureq instead of reqwest to avoid async issues
-        let response = ureq::post(&url)
-            .timeout(std::time::Duration::from_secs(30))
-            .send_json(&body)
-            .map_err(|e| {
-                log::error!("Gemini API request failed: {}", e);
-                format!("Gemini API request failed: {}", e)
-            })?;
-
-        let status = response.status();
-        log::info!("Received response with status: {}", status);
-        
-        if status != 200 {
-            let error_text = response.into_string().unwrap_or_else(|_| "Unknown error".to_string());
-            log::error!("Gemini API error response: {}", error_text);
-            return Err(format!("Gemini API Error {}: {}", status, error_text).into());
-        }
-
-        let json: Value = response.into_json()?;
+        let url = format!("{}/models/{}:generateContent", self.endpoint, model);
+        let body = gemini_body(messages, params);
+
+        let json = send_json("Gemini", self.authorize(self.client.post(&url)), &body).await?;
         log::info!("Parsing Gemini response...");
-        
-        let response_text = json["candidates"][0]["content"]["parts"][0]["text"]
-            .as_str()
-            .ok_or("Invalid Gemini response format")?
-            .to_string();
+        let completion = parse_gemini_response(&json)?;
 
         log::info!("Gemini response received successfully");
-        Ok(response_text)
+        Ok(completion)
+    }
+
+    async fn chat_stream(&self, model: &str, messages: &[ChatMessage], params: &GenerationParams, on_delta: &mut DeltaFn<'_>) -> Result<Completion, SophiaError> {
+        log::info!("Gemini streaming call starting for model: {}", model);
+        let url = format!("{}/models/{}:streamGenerateContent?alt=sse", self.endpoint, model);
+        let body = gemini_body(messages, params);
+
+        let completion = read_stream("Gemini", self.authorize(self.client.post(&url)), &body, GeminiStream::default(), on_delta).await?;
+
+        log::info!("Gemini stream finished");
+        Ok(completion)
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, SophiaError> {
+        let url = format!("{}/models", self.endpoint);
+        let mut models = Vec::new();
+        let mut page_token: Option<String> = None;
+
+        loop {
+            let mut request = self.authorize(self.client.get(&url)).query(&[("pageSize", "1000")]);
+            if let Some(token) = &page_token {
+                request = request.query(&[("pageToken", token)]);
+            }
+
+            let json = fetch_json("Gemini", request).await?;
+            models.extend(parse_gemini_models(&json)?);
+            page_token = json_string(&json["nextPageToken"]);
+            if page_token.is_none() {
+                return Ok(models);
+            }
+        }
     }
 }
 
-pub struct OpenAIClient {
+/// Client for any endpoint that speaks the OpenAI chat completions API:
+/// OpenAI, DeepSeek and OpenRouter, and user-defined providers such as
+/// LM Studio, vLLM, llama.cpp server, Groq, Mistral or Together.
+pub struct OpenAICompatibleClient {
+    /// Provider name used in logs and error messages.
+    label: String,
     endpoint: String,
-    api_key: String,
+    api_key: SecretString,
+    auth: AuthScheme,
+    headers: Vec<(String, String)>,
+    client: reqwest::Client,
 }
 
-impl OpenAIClient {
-    pub fn new(endpoint: &str, api_key: &str) -> Self {
-        OpenAIClient {
-            endpoint: endpoint.to_string(),
-            api_key: api_key.to_string(),
+impl OpenAICompatibleClient {
+    /// A client sending the key as a bearer token and no extra headers.
+    pub fn new(label: &str, endpoint: &str, api_key: impl Into<SecretString>) -> Self {
+        OpenAICompatibleClient {
+            label: label.to_string(),
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            api_key: api_key.into(),
+            auth: AuthScheme::Bearer,
+            headers: Vec::new(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn with_auth(mut self, auth: AuthScheme) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Adds a header sent with every request, e.g. OpenRouter's `X-Title`.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Applies the auth scheme and extra headers to a request.
+    fn authorize(&self, mut request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        request = match &self.auth {
+            AuthScheme::Bearer => request.bearer_auth(self.api_key.expose_secret()),
+            AuthScheme::Header(name) => request.header(name.as_str(), self.api_key.expose_secret()),
+            AuthScheme::None => request,
+        };
+        for (name, value) in &self.headers {
+            request = request.header(name.as_str(), value.as_str());
         }
+        request
     }
 }
 
-impl LLMClient for OpenAIClient {
-    fn complete(&self, model: &str, prompt: &str) -> Result<String, Box<dyn Error>> {
-        log::info!("OpenAI API call starting for model: {}", model);
+#[async_trait]
+impl LLMClient for OpenAICompatibleClient {
+    async fn chat(&self, model: &str, messages: &[ChatMessage], params: &GenerationParams) -> Result<Completion, SophiaError> {
+        log::info!("{} API call starting for model: {}", self.label, model);
         let url = format!("{}/chat/completions", self.endpoint);
-        let body = json!({
-            "model": model,
-            "messages": [{"role": "user", "content": prompt}],
-            "temperature": 0.7
-        });
-
-        log::info!("Sending request to OpenAI API...");
-        let response = ureq::post(&url)
-            .set("Authorization", &format!("Bearer {}", self.api_key))
-            .timeout(std::time::Duration::from_secs(30))
-            .send_json(&body)
-            .map_err(|e| {
-                log::error!("OpenAI API request failed: {}", e);
-                format!("OpenAI API request failed: {}", e)
-            })?;
-
-        let status = response.status();
-        log::info!("Received response with status: {}", status);
-        
-        if status != 200 {
-            let error_text = response.into_string().unwrap_or_else(|_| "Unknown error".to_string());
-            log::error!("OpenAI API error response: {}", error_text);
-            return Err(format!("OpenAI API Error {}: {}", status, error_text).into());
-        }
-
-        let json: Value = response.into_json()?;
-        let response_text = json["choices"][0]["message"]["content"]
-            .as_str()
-            .ok_or("Invalid OpenAI response format")?
-            .to_string();
-
-        log::info!("OpenAI response received successfully");
-        Ok(response_text)
+        let body = openai_body(model, messages, params);
+
+        let request = self.authorize(self.client.post(&url));
+        let json = send_json(&self.label, request, &body).await?;
+        let completion = parse_openai_response(&self.label, &json)?;
+
+        log::info!("{} response received successfully", self.label);
+        Ok(completion)
+    }
+
+    async fn chat_stream(&self, model: &str, messages: &[ChatMessage], params: &GenerationParams, on_delta: &mut DeltaFn<'_>) -> Result<Completion, SophiaError> {
+        log::info!("{} streaming call starting for model: {}", self.label, model);
+        let url = format!("{}/chat/completions", self.endpoint);
+        let mut body = openai_body(model, messages, params);
+        body["stream"] = json!(true);
+        body["stream_options"] = json!({"include_usage": true});
+
+        let request = self.authorize(self.client.post(&url));
+        let completion = read_stream(&self.label, request, &body, OpenAIStream::default(), on_delta).await?;
+
+        log::info!("{} stream finished", self.label);
+        Ok(completion)
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, SophiaError> {
+        let url = format!("{}/models", self.endpoint);
+        let json = fetch_json(&self.label, self.authorize(self.client.get(&url))).await?;
+        parse_openai_models(&self.label, &json)
     }
 }
 
 pub struct AnthropicClient {
     endpoint: String,
-    api_key: String,
+    api_key: SecretString,
+    client: reqwest::Client,
 }
 
 impl AnthropicClient {
-    pub fn new(endpoint: &str, api_key: &str) -> Self {
+    pub fn new(endpoint: &str, api_key: impl Into<SecretString>) -> Self {
         AnthropicClient {
             endpoint: endpoint.to_string(),
-            api_key: api_key.to_string(),
+            api_key: api_key.into(),
+            client: reqwest::Client::new(),
         }
     }
+
+    fn post(&self, url: &str) -> reqwest::RequestBuilder {
+        self.client.post(url)
+            .header("x-api-key", self.api_key.expose_secret())
+            .header("anthropic-version", "2023-06-01")
+    }
+}
+
+/// The conversation to send when continuing `partial`: the reply so far as a
+/// trailing assistant turn, which Anthropic resumes from. Trailing whitespace
+/// is trimmed from `partial` since the API rejects it in that position.
+/// None if nothing was generated that could be resumed.
+fn anthropic_continuation(messages: &[ChatMessage], partial: &mut Completion) -> Option<Vec<ChatMessage>> {
+    partial.text.truncate(partial.text.trim_end().len());
+    if partial.text.is_empty() {
+        return None;
+    }
+
+    let mut continued = messages.to_vec();
+    continued.push(ChatMessage::new(ChatRole::Assistant, &partial.text));
+    Some(continued)
 }
 
+#[async_trait]
 impl LLMClient for AnthropicClient {
-    fn complete(&self, model: &str, prompt: &str) -> Result<String, Box<dyn Error>> {
+    async fn chat(&self, model: &str, messages: &[ChatMessage], params: &GenerationParams) -> Result<Completion, SophiaError> {
         log::info!("Anthropic API call starting for model: {}", model);
         let url = format!("{}/messages", self.endpoint);
-        let body = json!({
-            "model": model,
-            "messages": [{"role": "user", "content": prompt}],
-            "max_tokens": 1024
-        });
-
-        log::info!("Sending request to Anthropic API...");
-        let response = ureq::post(&url)
-            .set("x-api-key", &self.api_key)
-            .set("anthropic-version", "2023-06-01")
-            .timeout(std::time::Duration::from_secs(30))
-            .send_json(&body)
-            .map_err(|e| {
-                log::error!("Anthropic API request failed: {}", e);
-                format!("Anthropic API request failed: {}", e)
-            })?;
-
-        let status = response.status();
-        log::info!("Received response with status: {}", status);
-        
-        if status != 200 {
-            let error_text = response.into_string().unwrap_or_else(|_| "Unknown error".to_string());
-            log::error!("Anthropic API error response: {}", error_text);
-            return Err(format!("Anthropic API Error {}: {}", status, error_text).into());
-        }
-
-        let json: Value = response.into_json()?;
-        let response_text = json["content"][0]["text"]
-            .as_str()
-            .ok_or("Invalid Anthropic response format")?
-            .to_string();
+        let body = anthropic_body(model, messages, params);
 
-        log::info!("Anthropic response received successfully");
-        Ok(response_text)
-    }
-}
+        let json = send_json("Anthropic", self.post(&url), &body).await?;
+        let mut completion = parse_anthropic_response(&json)?;
 
-pub struct DeepSeekClient {
-    endpoint: String,
-    api_key: String,
-}
+        for continuation in 1..=params.max_continuations.unwrap_or(0) {
+            if !completion.is_truncated() {
+                break;
+            }
+            let Some(continued) = anthropic_continuation(messages, &mut completion) else { break };
+            log::info!("Anthropic reply hit max_tokens, continuing ({})", continuation);
 
-impl DeepSeekClient {
-    pub fn new(endpoint: &str, api_key: &str) -> Self {
-        DeepSeekClient {
-            endpoint: endpoint.to_string(),
-            api_key: api_key.to_string(),
+            let body = anthropic_body(model, &continued, params);
+            let json = send_json("Anthropic", self.post(&url), &body).await?;
+            completion.extend(parse_anthropic_response(&json)?);
+        }
+
+        if completion.is_truncated() {
+            log::warn!("Anthropic reply truncated at the max_tokens budget");
         }
+        log::info!("Anthropic response received successfully");
+        Ok(completion)
     }
-}
 
-impl LLMClient for DeepSeekClient {
-    fn complete(&self, model: &str, prompt: &str) -> Result<String, Box<dyn Error>> {
-        log::info!("DeepSeek API call starting for model: {}", model);
-        let url = format!("{}/chat/completions", self.endpoint);
-        let body = json!({
-            "model": model,
-            "messages": [{"role": "user", "content": prompt}],
-            "temperature": 0.7
-        });
-
-        log::info!("Sending request to DeepSeek API...");
-        let response = ureq::post(&url)
-            .set("Authorization", &format!("Bearer {}", self.api_key))
-            .timeout(std::time::Duration::from_secs(30))
-            .send_json(&body)
-            .map_err(|e| {
-                log::error!("DeepSeek API request failed: {}", e);
-                format!("DeepSeek API request failed: {}", e)
-            })?;
-
-        let status = response.status();
-        log::info!("Received response with status: {}", status);
-        
-        if status != 200 {
-            let error_text = response.into_string().unwrap_or_else(|_| "Unknown error".to_string());
-            log::error!("DeepSeek API error response: {}", error_text);
-            return Err(format!("DeepSeek API Error {}: {}", status, error_text).into());
-        }
-
-        let json: Value = response.into_json()?;
-        let response_text = json["choices"][0]["message"]["content"]
-            .as_str()
-            .ok_or("Invalid DeepSeek response format")?
-            .to_string();
-
-        log::info!("DeepSeek response received successfully");
-        Ok(response_text)
-    }
-}
-
-pub struct OpenRouterClient {
-    endpoint: String,
-    api_key: String,
-}
+    async fn chat_stream(&self, model: &str, messages: &[ChatMessage], params: &GenerationParams, on_delta: &mut DeltaFn<'_>) -> Result<Completion, SophiaError> {
+        log::info!("Anthropic streaming call starting for model: {}", model);
+        let url = format!("{}/messages", self.endpoint);
+        let mut body = anthropic_body(model, messages, params);
+        body["stream"] = json!(true);
 
-impl OpenRouterClient {
-    pub fn new(endpoint: &str, api_key: &str) -> Self {
-        OpenRouterClient {
-            endpoint: endpoint.to_string(),
-            api_key: api_key.to_string(),
+        let mut completion = read_stream("Anthropic", self.post(&url), &body, AnthropicStream::default(), on_delta).await?;
+
+        // Continuations stream into the same deltas, so the UI sees one reply
+        for continuation in 1..=params.max_continuations.unwrap_or(0) {
+            if !completion.is_truncated() {
+                break;
+            }
+            let Some(continued) = anthropic_continuation(messages, &mut completion) else { break };
+            log::info!("Anthropic stream hit max_tokens, continuing ({})", continuation);
+
+            let mut body = anthropic_body(model, &continued, params);
+            body["stream"] = json!(true);
+            completion.extend(read_stream("Anthropic", self.post(&url), &body, AnthropicStream::default(), on_delta).await?);
+        }
+
+        if completion.is_truncated() {
+            log::warn!("Anthropic stream truncated at the max_tokens budget");
         }
+        log::info!("Anthropic stream finished");
+        Ok(completion)
     }
-}
 
-impl LLMClient for OpenRouterClient {
-    fn complete(&self, model: &str, prompt: &str) -> Result<String, Box<dyn Error>> {
-        log::info!("OpenRouter API call starting for model: {}", model);
-        let url = format!("{}/chat/completions", self.endpoint);
-        let body = json!({
-            "model": model,
-            "messages": [{"role": "user", "content": prompt}],
-            "temperature": 0.7
-        });
-
-        log::info!("Sending request to OpenRouter API...");
-        let response = ureq::post(&url)
-            .set("Authorization", &format!("Bearer {}", self.api_key))
-            .set("HTTP-Referer", "http://localhost")
-            .set("X-Title", "Sophia Desktop")
-            .timeout(std::time::Duration::from_secs(30))
-            .send_json(&body)
-            .map_err(|e| {
-                log::error!("OpenRouter API request failed: {}", e);
-                format!("OpenRouter API request failed: {}", e)
-            })?;
-
-        let status = response.status();
-        log::info!("Received response with status: {}", status);
-        
-        if status != 200 {
-            let error_text = response.into_string().unwrap_or_else(|_| "Unknown error".to_string());
-            log::error!("OpenRouter API error response: {}", error_text);
-            return Err(format!("OpenRouter API Error {}: {}", status, error_text).into());
-        }
-
-        let json: Value = response.into_json()?;
-        let response_text = json["choices"][0]["message"]["content"]
-            .as_str()
-            .ok_or("Invalid OpenRouter response format")?
-            .to_string();
-
-        log::info!("OpenRouter response received successfully");
-        Ok(response_text)
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, SophiaError> {
+        let url = format!("{}/models", self.endpoint);
+        let mut models = Vec::new();
+        let mut after_id: Option<String> = None;
+
+        loop {
+            let mut request = self.client.get(&url)
+                .header("x-api-key", self.api_key.expose_secret())
+                .header("anthropic-version", "2023-06-01")
+                .query(&[("limit", "1000")]);
+            if let Some(id) = &after_id {
+                request = request.query(&[("after_id", id)]);
+            }
+
+            let json = fetch_json("Anthropic", request).await?;
+            models.extend(parse_anthropic_models(&json)?);
+            after_id = json_string(&json["last_id"]).filter(|_| json["has_more"].as_bool().unwrap_or(false));
+            if after_id.is_none() {
+                return Ok(models);
+            }
+        }
     }
 }
 
@@ -327,8 +1080,313 @@ pub struct MockClient {
     pub response: String,
 }
 
+#[async_trait]
 impl LLMClient for MockClient {
-    fn complete(&self, _model: &str, _prompt: &str) -> Result<String, Box<dyn Error>> {
-        Ok(self.response.clone())
+    async fn chat(&self, _model: &str, _messages: &[ChatMessage], _params: &GenerationParams) -> Result<Completion, SophiaError> {
+        Ok(Completion::from_text(&self.response))
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, SophiaError> {
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds a whole body to `parser`, collecting the deltas it emits.
+    fn parse<P: StreamParser>(mut parser: P, body: &str, deltas: &mut Vec<String>) -> Result<Completion, SophiaError> {
+        for line in body.lines() {
+            if !parser.line(line, &mut |d| deltas.push(d.to_string()))? {
+                break;
+            }
+        }
+        Ok(parser.finish())
+    }
+
+    #[test]
+    fn test_openai_stream_parsing() {
+        let body = "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\n\
+                    data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n\
+                    : keep-alive\n\n\
+                    data: {\"id\":\"chatcmpl-1\",\"choices\":[{\"delta\":{\"content\":\"lo\"},\"finish_reason\":\"stop\"}]}\n\n\
+                    data: {\"id\":\"chatcmpl-1\",\"choices\":[],\"usage\":{\"prompt_tokens\":9,\"completion_tokens\":2,\"total_tokens\":11}}\n\n\
+                    data: [DONE]\n\n";
+        let mut deltas = Vec::new();
+        let completion = parse(OpenAIStream::default(), body, &mut deltas).unwrap();
+
+        assert_eq!(completion.text, "Hello");
+        assert_eq!(deltas, vec!["Hel", "lo"]);
+        assert_eq!(completion.finish_reason.as_deref(), Some("stop"));
+        assert_eq!(completion.request_id.as_deref(), Some("chatcmpl-1"));
+        assert_eq!(completion.usage, Some(TokenUsage::new(9, 2, Some(11))));
+    }
+
+    #[test]
+    fn test_anthropic_stream_parsing() {
+        let body = "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"usage\":{\"input_tokens\":15,\"output_tokens\":1}}}\n\n\
+                    event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"delta\":{\"type\":\"text_delta\",\"text\":\"Hi \"}}\n\n\
+                    event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"delta\":{\"type\":\"text_delta\",\"text\":\"there\"}}\n\n\
+                    event: message_delta\ndata: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\"},\"usage\":{\"output_tokens\":4}}\n\n\
+                    event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n";
+        let mut deltas = Vec::new();
+        let completion = parse(AnthropicStream::default(), body, &mut deltas).unwrap();
+
+        assert_eq!(completion.text, "Hi there");
+        assert_eq!(deltas.len(), 2);
+        assert_eq!(completion.request_id.as_deref(), Some("msg_1"));
+        assert_eq!(completion.finish_reason.as_deref(), Some("end_turn"));
+        assert_eq!(completion.usage, Some(TokenUsage::new(15, 4, None)));
+    }
+
+    #[test]
+    fn test_gemini_stream_parsing() {
+        let body = "data: {\"candidates\":[{\"content\":{\"parts\":[{\"text\":\"One \"}]}}]}\r\n\r\n\
+                    data: {\"candidates\":[{\"content\":{\"parts\":[{\"text\":\"two\"}]},\"finishReason\":\"STOP\"}],\"usageMetadata\":{\"promptTokenCount\":5,\"candidatesTokenCount\":2,\"totalTokenCount\":7},\"responseId\":\"r1\"}\r\n\r\n";
+        let mut deltas = Vec::new();
+        let completion = parse(GeminiStream::default(), body, &mut deltas).unwrap();
+
+        assert_eq!(completion.text, "One two");
+        assert_eq!(completion.finish_reason.as_deref(), Some("STOP"));
+        assert_eq!(completion.usage, Some(TokenUsage::new(5, 2, Some(7))));
+        assert_eq!(completion.request_id.as_deref(), Some("r1"));
+    }
+
+    #[test]
+    fn test_ollama_stream_parsing() {
+        let body = "{\"message\":{\"role\":\"assistant\",\"content\":\"a\"},\"done\":false}\n\
+                    {\"message\":{\"role\":\"assistant\",\"content\":\"b\"},\"done\":false}\n\
+                    {\"message\":{\"role\":\"assistant\",\"content\":\"\"},\"done\":true,\"done_reason\":\"stop\",\"prompt_eval_count\":26,\"eval_count\":2}\n";
+        let mut deltas = Vec::new();
+        let completion = parse(OllamaStream::default(), body, &mut deltas).unwrap();
+
+        assert_eq!(completion.text, "ab");
+        assert_eq!(deltas, vec!["a", "b"]);
+        assert_eq!(completion.usage, Some(TokenUsage::new(26, 2, None)));
+    }
+
+    #[test]
+    fn test_anthropic_message_mapping() {
+        let messages = vec![
+            ChatMessage::new(ChatRole::System, "Be brief."),
+            ChatMessage::user("Hi"),
+            ChatMessage::new(ChatRole::Assistant, "Hello!"),
+            ChatMessage::user("What's 2+2?"),
+            ChatMessage::user("Answer in words."),
+        ];
+        let (system, turns) = anthropic_messages(&messages);
+
+        assert_eq!(system.as_deref(), Some("Be brief."));
+        let turns = turns.as_array().unwrap();
+        assert_eq!(turns.len(), 3);
+        assert_eq!(turns[2]["role"], "user");
+        assert_eq!(turns[2]["content"], "What's 2+2?\n\nAnswer in words.");
+    }
+
+    #[test]
+    fn test_gemini_message_mapping() {
+        let messages = vec![
+            ChatMessage::new(ChatRole::System, "Be brief."),
+            ChatMessage::user("Hi"),
+            ChatMessage::new(ChatRole::Assistant, "Hello!"),
+        ];
+        let body = gemini_body(&messages, &GenerationParams::default());
+
+        assert_eq!(body["systemInstruction"]["parts"][0]["text"], "Be brief.");
+        assert_eq!(body["contents"].as_array().unwrap().len(), 2);
+        assert_eq!(body["contents"][1]["role"], "model");
+        assert!(body.get("generationConfig").is_none());
+    }
+
+    #[test]
+    fn test_generation_params_mapping() {
+        let params = GenerationParams {
+            system_prompt: Some("You are terse.".to_string()),
+            temperature: Some(0.2),
+            top_p: Some(0.9),
+            max_output_tokens: Some(256),
+            stop_sequences: vec!["END".to_string()],
+            seed: Some(42),
+            max_continuations: None,
+        };
+        let messages = vec![ChatMessage::new(ChatRole::System, "Use metric units."), ChatMessage::user("Hi")];
+
+        let body = openai_body("gpt-4o-mini", &messages, &params);
+        assert_eq!(body["messages"][0]["content"], "You are terse.");
+        assert_eq!(body["messages"].as_array().unwrap().len(), 3);
+        assert_eq!(body["max_tokens"], 256);
+        assert_eq!(body["stop"][0], "END");
+        assert_eq!(body["seed"], 42);
+
+        let body = anthropic_body("claude-3-5-haiku-20241022", &messages, &params);
+        assert_eq!(body["system"], "You are terse.\n\nUse metric units.");
+        assert_eq!(body["max_tokens"], 256);
+        assert_eq!(body["stop_sequences"][0], "END");
+        assert!(body.get("seed").is_none());
+
+        let body = gemini_body(&messages, &params);
+        assert_eq!(body["systemInstruction"]["parts"][0]["text"], "You are terse.");
+        assert_eq!(body["generationConfig"]["maxOutputTokens"], 256);
+        assert_eq!(body["generationConfig"]["topP"], 0.9);
+
+        let body = ollama_body("llama3.2:3b", &messages, &params);
+        assert_eq!(body["options"]["num_predict"], 256);
+        assert_eq!(body["options"]["temperature"], 0.2);
+
+        // Unset fields are left to the provider; Anthropic still needs a budget
+        let body = openai_body("gpt-4o-mini", &messages, &GenerationParams::default());
+        assert!(body.get("temperature").is_none());
+        let body = anthropic_body("claude-3-5-haiku-20241022", &messages, &GenerationParams::default());
+        assert_eq!(body["max_tokens"], ANTHROPIC_DEFAULT_MAX_TOKENS);
+
+        // Per-request overrides replace only the fields they set
+        let merged = params.merged(&GenerationParams { temperature: Some(1.0), ..GenerationParams::default() });
+        assert_eq!(merged.temperature, Some(1.0));
+        assert_eq!(merged.max_output_tokens, Some(256));
+        assert!(GenerationParams { top_p: Some(1.5), ..GenerationParams::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn test_response_parsing_without_usage() {
+        let json = serde_json::json!({"choices": [{"message": {"content": "hi"}}]});
+        let completion = parse_openai_response("DeepSeek", &json).unwrap();
+        assert_eq!(completion.text, "hi");
+        assert!(completion.usage.is_none());
+
+        let json = serde_json::json!({"id": "msg_2", "content": [{"type": "text", "text": "yo"}], "stop_reason": "max_tokens", "usage": {"input_tokens": 3, "output_tokens": 1024}});
+        let completion = parse_anthropic_response(&json).unwrap();
+        assert_eq!(completion.finish_reason.as_deref(), Some("max_tokens"));
+        assert_eq!(completion.usage.unwrap().total_tokens, 1027);
+    }
+
+    #[test]
+    fn test_anthropic_joins_text_blocks_and_continues() {
+        let json = serde_json::json!({"id": "msg_3", "content": [
+            {"type": "thinking", "thinking": "..."},
+            {"type": "text", "text": "Part one. "},
+            {"type": "text", "text": "Part two "}
+        ], "stop_reason": "max_tokens", "usage": {"input_tokens": 10, "output_tokens": 20}});
+        let mut completion = parse_anthropic_response(&json).unwrap();
+        assert_eq!(completion.text, "Part one. Part two ");
+        assert!(completion.is_truncated());
+
+        // The partial reply is resent, trimmed, as the turn to resume
+        let messages = vec![ChatMessage::user("Write a lot")];
+        let continued = anthropic_continuation(&messages, &mut completion).unwrap();
+        assert_eq!(continued.len(), 2);
+        assert_eq!(continued[1].role, ChatRole::Assistant);
+        assert_eq!(continued[1].content, "Part one. Part two");
+
+        let json = serde_json::json!({"id": "msg_4", "content": [{"type": "text", "text": " and the end."}], "stop_reason": "end_turn", "usage": {"input_tokens": 30, "output_tokens": 5}});
+        completion.extend(parse_anthropic_response(&json).unwrap());
+        assert_eq!(completion.text, "Part one. Part two and the end.");
+        assert!(!completion.is_truncated());
+        assert_eq!(completion.request_id.as_deref(), Some("msg_3"));
+        assert_eq!(completion.usage, Some(TokenUsage::new(40, 25, None)));
+
+        assert!(anthropic_continuation(&messages, &mut Completion::from_text("  ")).is_none());
+    }
+
+    #[test]
+    fn test_stream_error_is_surfaced() {
+        let body = "data: {\"error\":{\"message\":\"overloaded\"}}\n\n";
+        let result = parse(OpenAIStream::default(), body, &mut Vec::new());
+        assert_eq!(result.unwrap_err().kind(), "ProviderUnavailable");
+    }
+
+    #[test]
+    fn test_blocked_replies_are_content_filtered() {
+        let json = serde_json::json!({"promptFeedback": {"blockReason": "SAFETY"}});
+        assert_eq!(parse_gemini_response(&json).unwrap_err().kind(), "ContentFiltered");
+
+        let json = serde_json::json!({"choices": [{"message": {"content": null}, "finish_reason": "content_filter"}]});
+        assert_eq!(parse_openai_response("OpenAI", &json).unwrap_err().kind(), "ContentFiltered");
+
+        let json = serde_json::json!({"choices": [{"message": {}}]});
+        assert_eq!(parse_openai_response("OpenAI", &json).unwrap_err().kind(), "ProviderResponseInvalid");
+    }
+
+    #[test]
+    fn test_ollama_model_listing() {
+        let tags = serde_json::json!({"models": [
+            {"name": "llama3.1:8b", "size": 4920753328u64, "details": {"family": "llama", "parameter_size": "8.0B", "quantization_level": "Q4_K_M"}},
+            {"size": 1}
+        ]});
+        let models = parse_ollama_tags(&tags).unwrap();
+        assert_eq!(models.len(), 1);
+        assert_eq!(models[0].name, "llama3.1:8b");
+        assert_eq!(models[0].quantization.as_deref(), Some("Q4_K_M"));
+        assert_eq!(models[0].size_bytes, 4920753328);
+
+        let show = serde_json::json!({"model_info": {"general.architecture": "llama", "llama.context_length": 131072}});
+        assert_eq!(ollama_context_length(&show), Some(131072));
+        assert!(parse_ollama_tags(&serde_json::json!({})).is_err());
+    }
+
+    #[test]
+    fn test_model_list_parsing() {
+        let json = serde_json::json!({"data": [
+            {"id": "openai/gpt-4o", "name": "OpenAI: GPT-4o", "context_length": 128000},
+            {"id": "deepseek-chat", "object": "model"}
+        ]});
+        let models = parse_openai_models("OpenRouter", &json).unwrap();
+        assert_eq!(models[0].context_length, Some(128000));
+        assert_eq!(models[1].id, "deepseek-chat");
+
+        let json = serde_json::json!({"data": [{"id": "claude-3-5-haiku-20241022", "display_name": "Claude Haiku 3.5"}], "has_more": false});
+        assert_eq!(parse_anthropic_models(&json).unwrap()[0].display_name.as_deref(), Some("Claude Haiku 3.5"));
+
+        let json = serde_json::json!({"models": [
+            {"name": "models/gemini-2.5-flash", "inputTokenLimit": 1048576, "supportedGenerationMethods": ["generateContent", "countTokens"]},
+            {"name": "models/text-embedding-004", "supportedGenerationMethods": ["embedContent"]}
+        ]});
+        let models = parse_gemini_models(&json).unwrap();
+        assert_eq!(models.len(), 1);
+        assert_eq!(models[0].id, "gemini-2.5-flash");
+    }
+
+    #[test]
+    fn test_openai_compatible_auth_schemes() {
+        let client = OpenAICompatibleClient::new("Azure", "https://gateway.example/openai/", "secret")
+            .with_auth(AuthScheme::Header("api-key".to_string()))
+            .with_header("X-Tenant", "team-a");
+        let request = client.authorize(client.client.get("https://gateway.example/openai/models")).build().unwrap();
+        assert_eq!(request.headers()["api-key"], "secret");
+        assert_eq!(request.headers()["X-Tenant"], "team-a");
+        assert!(request.headers().get("authorization").is_none());
+        assert_eq!(client.endpoint, "https://gateway.example/openai");
+
+        let client = OpenAICompatibleClient::new("Groq", "https://api.groq.com/openai/v1", "gsk");
+        let request = client.authorize(client.client.get("https://api.groq.com/openai/v1/models")).build().unwrap();
+        assert_eq!(request.headers()["authorization"], "Bearer gsk");
+
+        let client = OpenAICompatibleClient::new("LM Studio", "http://localhost:1234/v1", "").with_auth(AuthScheme::None);
+        let request = client.authorize(client.client.get("http://localhost:1234/v1/models")).build().unwrap();
+        assert!(request.headers().is_empty());
+    }
+
+    #[test]
+    fn test_gemini_key_is_sent_as_header() {
+        let client = GeminiClient::new("https://generativelanguage.googleapis.com/v1beta", "AIzaSecret");
+        let url = "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.5-flash:generateContent";
+        let request = client.authorize(client.client.post(url)).build().unwrap();
+        assert_eq!(request.headers()["x-goog-api-key"], "AIzaSecret");
+        assert!(!request.url().as_str().contains("AIzaSecret"));
+    }
+
+    #[test]
+    fn test_line_buffer_joins_split_chunks() {
+        let mut lines = LineBuffer::default();
+        lines.push(b"data: {\"a\":");
+        assert!(lines.next_line().is_none());
+
+        // "é" split across two chunks
+        lines.push(b"1}\r\n\ndata: caf\xc3");
+        lines.push(b"\xa9");
+        assert_eq!(lines.next_line().as_deref(), Some("data: {\"a\":1}"));
+        assert_eq!(lines.next_line().as_deref(), Some(""));
+        assert!(lines.next_line().is_none());
+        assert_eq!(lines.finish().as_deref(), Some("data: café"));
     }
 }
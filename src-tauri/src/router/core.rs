@@ -1,140 +1,691 @@
-use crate::providers::{ProviderRegistry, ProviderType};
-use crate::router::types::{ModelConfig, TaskType};
+use crate::error::SophiaError;
+use crate::providers::{health, ModelCatalog, ProviderHealth, ProviderRegistry, ProviderType};
+use crate::redact::register_temporary_secret;
+use crate::router::classifier::{Classification, ClassificationRule, ClassifierKind, HeuristicClassifier, LlmClassifier, RuleClassifier, TaskClassifier};
+use crate::router::client::DeltaFn;
+use crate::router::egress::{EgressPolicy, LOCAL_ONLY_KEY, NETWORK_EGRESS_CONSENT_KEY};
+use crate::router::types::{ChatMessage, ChatRole, Completion, GenerationParams, ModelConfig, ModelInfo, RouteTarget, RoutingTable, TaskType};
+use crate::runtime::audit::AuditLogger;
 use crate::storage::StorageManager;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const ROUTING_TABLE_KEY: &str = "routing_table";
+const TASK_CLASSIFIER_KEY: &str = "task_classifier";
+const CLASSIFIER_RULES_KEY: &str = "classifier_rules";
 
 pub struct ModelRouter {
     storage: Arc<StorageManager>,
     provider_registry: Arc<Mutex<ProviderRegistry>>,
+    // Only set when the user saved a model_config; seeds the routing table
+    model_config: Option<ModelConfig>,
+    // Where egress policy changes and blocked attempts are reported
+    audit: Option<Arc<AuditLogger>>,
+}
+
+/// A provider to try, with the model to use and why it was picked.
+struct Candidate {
+    provider: ProviderType,
+    model: Option<String>,
+    source: &'static str,
+}
+
+/// How each attempt hands the reply back.
+enum Delivery<'a> {
+    Complete,
+    /// Forward text fragments to the callback as the provider streams them.
+    Stream(&'a mut DeltaFn<'a>),
+}
+
+/// What every attempt of one routed request shares in the decision log.
+struct AttemptLog<'a> {
+    routing_id: &'a str,
+    input_context: &'a serde_json::Value,
+    classification: &'a Classification,
 }
 
 impl ModelRouter {
     pub fn new(storage: Arc<StorageManager>, provider_registry: Arc<Mutex<ProviderRegistry>>) -> Self {
-        let _config = Self::get_config(&storage);
+        let model_config = Self::get_config(&storage);
         
         ModelRouter {
             storage,
             provider_registry,
+            model_config,
+            audit: None,
+        }
+    }
+
+    pub fn with_audit_logger(mut self, audit: Arc<AuditLogger>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    fn audit(&self, level: &str, event: &str, context: serde_json::Value) {
+        if let Some(audit) = &self.audit {
+            audit.log(level, "router", event, context);
+        }
+    }
+
+    pub fn egress_policy(&self) -> EgressPolicy {
+        EgressPolicy::load(&self.storage)
+    }
+
+    pub fn set_local_only(&self, enabled: bool) -> Result<EgressPolicy, SophiaError> {
+        self.set_egress_flag(LOCAL_ONLY_KEY, enabled)
+    }
+
+    pub fn set_network_egress_consent(&self, consent: bool) -> Result<EgressPolicy, SophiaError> {
+        self.set_egress_flag(NETWORK_EGRESS_CONSENT_KEY, consent)
+    }
+
+    fn set_egress_flag(&self, key: &str, value: bool) -> Result<EgressPolicy, SophiaError> {
+        self.storage.set_preference(key, serde_json::json!(value))?;
+        let policy = self.egress_policy();
+        self.audit("INFO", "egress_policy_changed", serde_json::json!({"setting": key, "value": value, "policy": policy}));
+        Ok(policy)
+    }
+
+    /// Whether the egress policy lets this provider be called. Providers
+    /// without known capabilities are treated as remote.
+    fn egress_allowed(&self, egress: &EgressPolicy, provider: &ProviderType) -> bool {
+        egress.allows_remote()
+            || self.provider_registry.lock().unwrap().capabilities(provider).is_some_and(|c| c.local)
+    }
+
+    /// Fails with `EgressBlocked` when the egress policy keeps `provider` from
+    /// being contacted at all. For requests made outside routing, such as
+    /// model listings and health checks.
+    pub fn check_egress(&self, provider: &ProviderType, purpose: &str) -> Result<(), SophiaError> {
+        let egress = self.egress_policy();
+        if self.egress_allowed(&egress, provider) {
+            return Ok(());
+        }
+        let reason = egress.block_reason().unwrap_or_default();
+        log::warn!("Blocking {} for provider {:?}: {}", purpose, provider, reason);
+        self.audit("WARN", "egress_blocked", serde_json::json!({
+            "provider": provider.as_str(),
+            "purpose": purpose,
+            "reason": reason,
+        }));
+        Err(SophiaError::EgressBlocked(format!("{}: {}", provider.as_str(), reason)))
+    }
+
+    /// The provider's model list, from the cache unless it has expired or
+    /// `refresh` is set. Fetching it contacts the provider, so the egress
+    /// policy applies.
+    pub async fn list_models(&self, provider: &ProviderType, refresh: bool) -> Result<Vec<ModelInfo>, SophiaError> {
+        if !refresh {
+            if let Some(catalog) = ModelCatalog::load(&self.storage, provider)?.filter(|c| c.is_fresh()) {
+                return Ok(catalog.models);
+            }
+        }
+
+        self.check_egress(provider, "model listing")?;
+        let client = self.provider_registry.lock().unwrap().get_client(provider)?;
+
+        let catalog = ModelCatalog::new(client.list_models().await?);
+        catalog.save(&self.storage, provider)?;
+        log::info!("Fetched {} models for {}", catalog.models.len(), provider.as_str());
+        Ok(catalog.models)
+    }
+
+    /// Checks a provider with a minimal authenticated call: with `api_key` if
+    /// given (a key not saved yet), otherwise with the stored key. A provider
+    /// the egress policy blocks is reported unavailable without being called.
+    pub async fn check_provider_health(&self, provider: &ProviderType, api_key: Option<&str>) -> Result<ProviderHealth, SophiaError> {
+        // A candidate key isn't stored, so it is redacted only until the check is done
+        let _candidate_key = api_key.map(register_temporary_secret);
+
+        let model = self.provider_registry.lock().unwrap()
+            .get_provider_config(provider)
+            .map(|c| c.model.clone())
+            .unwrap_or_default();
+        if let Err(blocked) = self.check_egress(provider, "health check") {
+            return Ok(ProviderHealth::unavailable(provider.as_str(), &model, &blocked));
         }
+
+        let client = match self.provider_registry.lock().unwrap().get_check_client(provider, api_key) {
+            Ok(client) => client,
+            Err(unavailable) => return Ok(ProviderHealth::unavailable(provider.as_str(), &model, &unavailable.into())),
+        };
+
+        let (health, models) = health::check_health(provider.as_str(), &model, client.as_ref()).await;
+        // The listing doubles as a catalog refresh, but only for the saved key
+        if let (Some(models), None) = (models, api_key) {
+            ModelCatalog::new(models).save(&self.storage, provider)?;
+        }
+        Ok(health)
     }
 
-    fn get_config(storage: &StorageManager) -> ModelConfig {
+    fn get_config(storage: &StorageManager) -> Option<ModelConfig> {
         match storage.get_preference("model_config") {
+            Ok(Some(val)) => Some(serde_json::from_value(val).unwrap_or_default()),
+            _ => None,
+        }
+    }
+
+    /// The saved routing table, or one seeded from `model_config` if the user
+    /// configured local models but never edited routes. Empty otherwise.
+    pub fn get_routing_table(&self) -> RoutingTable {
+        if let Ok(Some(val)) = self.storage.get_preference(ROUTING_TABLE_KEY) {
+            if let Ok(table) = serde_json::from_value(val) {
+                return table;
+            }
+            log::warn!("Ignoring unreadable routing table preference");
+        }
+
+        self.model_config.as_ref()
+            .map(RoutingTable::from_model_config)
+            .unwrap_or_default()
+    }
+
+    pub fn set_task_route(&self, task_type: TaskType, mut target: RouteTarget) -> Result<RoutingTable, SophiaError> {
+        target.model = target.model.trim().to_string();
+        if target.model.is_empty() {
+            return Err(SophiaError::InvalidInput("Model must not be empty".to_string()));
+        }
+        // A typo'd provider would parse as a custom one and fail every request
+        if self.provider_registry.lock().unwrap().get_provider_config(&target.provider).is_none() {
+            return Err(SophiaError::InvalidInput(format!("Unknown provider: {}", target.provider.as_str())));
+        }
+        ModelCatalog::validate_model(&self.storage, &target.provider, &target.model)?;
+
+        let mut table = self.get_routing_table();
+        table.routes.insert(task_type, target);
+        self.save_routing_table(&table)?;
+        Ok(table)
+    }
+
+    pub fn clear_task_route(&self, task_type: &TaskType) -> Result<RoutingTable, SophiaError> {
+        let mut table = self.get_routing_table();
+        table.routes.remove(task_type);
+        self.save_routing_table(&table)?;
+        Ok(table)
+    }
+
+    fn save_routing_table(&self, table: &RoutingTable) -> Result<(), SophiaError> {
+        let value = serde_json::to_value(table)?;
+        Ok(self.storage.set_preference(ROUTING_TABLE_KEY, value)?)
+    }
+
+    pub fn get_classifier_kind(&self) -> ClassifierKind {
+        match self.storage.get_preference(TASK_CLASSIFIER_KEY) {
             Ok(Some(val)) => serde_json::from_value(val).unwrap_or_default(),
-            _ => ModelConfig::default(),
+            _ => ClassifierKind::default(),
+        }
+    }
+
+    pub fn set_classifier_kind(&self, kind: ClassifierKind) -> Result<(), SophiaError> {
+        let value = serde_json::to_value(kind)?;
+        Ok(self.storage.set_preference(TASK_CLASSIFIER_KEY, value)?)
+    }
+
+    /// The saved rule set for the `rules` classifier, or the built-in defaults.
+    pub fn get_classifier_rules(&self) -> Vec<ClassificationRule> {
+        if let Ok(Some(val)) = self.storage.get_preference(CLASSIFIER_RULES_KEY) {
+            if let Ok(rules) = serde_json::from_value(val) {
+                return rules;
+            }
+            log::warn!("Ignoring unreadable classifier rules preference");
+        }
+        ClassificationRule::defaults()
+    }
+
+    pub fn set_classifier_rules(&self, rules: Vec<ClassificationRule>) -> Result<(), SophiaError> {
+        for rule in &rules {
+            rule.compile().map_err(SophiaError::InvalidInput)?;
         }
+        let value = serde_json::to_value(&rules)?;
+        Ok(self.storage.set_preference(CLASSIFIER_RULES_KEY, value)?)
     }
 
-    pub fn classify_task(&self, input: &str) -> TaskType {
-        // Simple heuristic for v1 (to avoid model dependency loop)
-        // In v2, this would be a "Fast" model call
-        if input.contains("code") || input.contains("function") {
-            TaskType::CodeAnalysis
-        } else if input.contains("plan") || input.contains("todo") {
-            TaskType::Planning
-        } else if input.contains("json") || input.contains("data") {
-            TaskType::DataProcessing
-        } else {
-            TaskType::GeneralChat
+    fn build_classifier(&self) -> Result<Box<dyn TaskClassifier>, SophiaError> {
+        match self.get_classifier_kind() {
+            ClassifierKind::Heuristic => Ok(Box::new(HeuristicClassifier)),
+            ClassifierKind::Rules => Ok(Box::new(RuleClassifier::new(&self.get_classifier_rules()))),
+            ClassifierKind::Llm => {
+                let (provider, model) = self.cheapest_available_model()
+                    .ok_or(SophiaError::NoProviderAvailable)?;
+                let client = self.provider_registry.lock().unwrap().get_client(&provider)?;
+                Ok(Box::new(LlmClassifier::new(client, provider, model, self.storage.clone())))
+            },
         }
     }
 
-    pub fn route_and_execute(&self, input: &str) -> Result<String, String> {
-        let task_type = self.classify_task(input);
+    /// The enabled, usable provider whose configured model costs least per token.
+    /// Local models (Ollama, custom localhost servers) are free, so they win
+    /// whenever one is enabled, and are the only choice when egress is blocked.
+    fn cheapest_available_model(&self) -> Option<(ProviderType, String)> {
+        let pricing = crate::storage::PricingCalculator::new();
+        let allows_remote = self.egress_policy().allows_remote();
+        let registry = self.provider_registry.lock().unwrap();
+
+        registry.get_active_provider_order()
+            .into_iter()
+            .filter(|provider| registry.unavailable_reason(provider).is_none())
+            .filter_map(|provider| {
+                let model = registry.get_provider_config(&provider)?.model.clone();
+                let local = registry.capabilities(&provider).is_some_and(|c| c.local);
+                if !local && !allows_remote {
+                    return None;
+                }
+                let cost = if local { 0.0 } else { pricing.calculate_cost(&model, 1_000_000, 1_000_000) };
+                Some((cost, provider, model))
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, provider, model)| (provider, model))
+    }
 
-        // Resolve provider (Gemini-first order, with primary override)
-        let provider = if let Ok(Some(primary)) = self.storage.get_preference("primary_provider") {
-            if let Some(p) = ProviderType::from_str(primary.as_str().unwrap_or("")) {
-                p
-            } else {
-                ProviderType::Ollama
+    /// Classifies with the configured classifier, falling back to the heuristic
+    /// if it can't be built or fails (e.g. the classifier model is offline).
+    pub async fn classify(&self, input: &str) -> Classification {
+        let result = match self.build_classifier() {
+            Ok(classifier) => classifier.classify(input).await,
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(classification) => classification,
+            Err(e) => {
+                log::warn!("Task classifier failed, using heuristic: {}", e);
+                HeuristicClassifier.classify(input).await
+                    .expect("heuristic classification is infallible")
+            },
+        }
+    }
+
+    pub async fn classify_task(&self, input: &str) -> TaskType {
+        self.classify(input).await.task_type
+    }
+
+    pub async fn route_and_execute(&self, input: &str) -> Result<String, SophiaError> {
+        let completion = self.route_and_execute_chat(&[ChatMessage::user(input)], &GenerationParams::default()).await?;
+        Ok(completion.text)
+    }
+
+    /// Same as `route_and_execute`, but forwards each text fragment to `on_delta`
+    /// as the provider streams it. Usage is recorded once the stream is complete.
+    pub async fn route_and_execute_stream(&self, input: &str, on_delta: &mut DeltaFn<'_>) -> Result<String, SophiaError> {
+        let completion = self.route_and_execute_chat_stream(&[ChatMessage::user(input)], &GenerationParams::default(), on_delta).await?;
+        Ok(completion.text)
+    }
+
+    /// Routes a multi-turn conversation. The task is classified from the latest
+    /// user turn, while the whole history is sent to the provider. Fields set
+    /// in `overrides` replace the chosen provider's configured generation params.
+    /// The completion's `finish_reason` tells whether the reply was cut short.
+    ///
+    /// Dropping the returned future aborts the provider request in flight.
+    pub async fn route_and_execute_chat(&self, messages: &[ChatMessage], overrides: &GenerationParams) -> Result<Completion, SophiaError> {
+        self.execute_with_fallback(messages, overrides, Delivery::Complete).await
+    }
+
+    pub async fn route_and_execute_chat_stream(&self, messages: &[ChatMessage], overrides: &GenerationParams, on_delta: &mut DeltaFn<'_>) -> Result<Completion, SophiaError> {
+        self.execute_with_fallback(messages, overrides, Delivery::Stream(on_delta)).await
+    }
+
+    /// The routing-table target for this task first (if any), then the primary
+    /// provider (if set), then the remaining enabled providers in order.
+    fn candidate_providers(&self, task_type: &TaskType) -> Vec<Candidate> {
+        let mut candidates: Vec<Candidate> = Vec::new();
+        let mut push = |provider: ProviderType, model: Option<String>, source| {
+            if !candidates.iter().any(|c| c.provider == provider) {
+                candidates.push(Candidate { provider, model, source });
             }
-        } else {
-            let provider_order = self.provider_registry.lock().unwrap().get_active_provider_order();
-            provider_order.first().cloned().unwrap_or(ProviderType::Ollama)
         };
-        
-        // Get the model from the provider config
-        let registry = self.provider_registry.lock().unwrap();
-        let model = registry.get_provider_config(&provider)
-            .map(|c| c.model.clone())
-            .unwrap_or_else(|| "gemini-1.5-flash".to_string());
-        
-        let client = registry.get_client(&provider);
-        drop(registry); // Release lock before making API call
 
-        // Estimate tokens (before API call)
-        let prompt_tokens = crate::storage::estimate_tokens(input);
+        if let Some(target) = self.get_routing_table().get(task_type) {
+            push(target.provider.clone(), Some(target.model.clone()), "routing_table");
+        }
+
+        if let Ok(Some(primary)) = self.storage.get_preference("primary_provider") {
+            push(ProviderType::from_str(primary.as_str().unwrap_or("")).unwrap_or(ProviderType::Ollama), None, "primary_provider");
+        }
+
+        for provider in self.provider_registry.lock().unwrap().get_active_provider_order() {
+            push(provider, None, "provider_order");
+        }
+
+        candidates
+    }
 
+    /// Tries each candidate provider in turn until one answers. Retryable errors
+    /// (network, rate limits, outages) are first retried per the provider's
+    /// `RetryPolicy`; those and auth/quota failures then move on to the next
+    /// provider, while anything else is returned immediately (see
+    /// `SophiaError::should_fail_over`). Providers the registry reports as
+    /// unavailable are skipped without a request, as are remote providers the
+    /// egress policy blocks; if nothing local could be called either, the
+    /// request fails with `SophiaError::EgressBlocked`. Every attempt and retry
+    /// is recorded as its own decision.
+    async fn execute_with_fallback(
+        &self,
+        messages: &[ChatMessage],
+        overrides: &GenerationParams,
+        mut delivery: Delivery<'_>,
+    ) -> Result<Completion, SophiaError> {
+        let input = messages.iter()
+            .rev()
+            .find(|m| m.role == ChatRole::User)
+            .map(|m| m.content.as_str())
+            .unwrap_or("");
+        let classification = self.classify(input).await;
+        let task_type = classification.task_type.clone();
+
+        // Estimate tokens (before API call); the whole history is billed as input
+        let prompt_tokens: i64 = messages.iter()
+            .map(|m| crate::storage::estimate_tokens(&m.content))
+            .sum();
+
+        // Attempts of one request share a task_id so the log can group them
+        let routing_id = uuid::Uuid::new_v4().to_string();
+        let input_context = serde_json::json!({"input_length": input.len(), "message_count": messages.len(), "estimated_tokens": prompt_tokens});
+        let attempt_log = AttemptLog { routing_id: &routing_id, input_context: &input_context, classification: &classification };
+
+        let candidates = self.candidate_providers(&task_type);
+        let egress = self.egress_policy();
+        let mut last_error: Option<SophiaError> = None;
+        let mut blocked: Vec<String> = Vec::new();
+        let mut called_any = false;
+
+        for (index, candidate) in candidates.iter().enumerate() {
+            let provider = &candidate.provider;
+            let attempt_number = index + 1;
+            let rationale = match &last_error {
+                None => "Routing decision".to_string(),
+                Some(error) => format!("Fallback after previous provider failed: {}", error),
+            };
+
+            // Get the model from the provider config. The lock is scoped so it
+            // is released before making the API call.
+            let (model, policy, params) = {
+                let registry = self.provider_registry.lock().unwrap();
+                let config = registry.get_provider_config(provider);
+                let model = candidate.model.clone()
+                    .or_else(|| config.map(|c| c.model.clone()))
+                    .unwrap_or_else(|| "gemini-1.5-flash".to_string());
+                let policy = config.map(|c| c.retry_policy.clone()).unwrap_or_default();
+                let params = config.map(|c| c.generation.merged(overrides)).unwrap_or_else(|| overrides.clone());
+
+                (model, policy, params)
+            };
+
+            if !self.egress_allowed(&egress, provider) {
+                let reason = egress.block_reason().unwrap_or_default();
+                log::warn!("Blocking provider {:?}: {}", provider, reason);
+                let error = SophiaError::EgressBlocked(format!("{}: {}", provider.as_str(), reason));
+                self.record_attempt(&attempt_log, candidate, &model, attempt_number, "blocked", Some(&error), None, rationale);
+                self.audit("WARN", "egress_blocked", serde_json::json!({
+                    "routing_id": &routing_id,
+                    "provider": provider.as_str(),
+                    "model": &model,
+                    "route_source": candidate.source,
+                    "reason": reason,
+                }));
+                blocked.push(provider.as_str().to_string());
+                continue;
+            }
+
+            // Resolved only once egress is allowed, so a blocked provider's key
+            // is never read from the secret store. Unavailable providers are
+            // never called, so nothing is billed for them.
+            let client = self.provider_registry.lock().unwrap().get_client(provider);
+            let client = match client {
+                Ok(client) => client,
+                Err(unavailable) => {
+                    log::warn!("Skipping provider {:?}: {}", provider, unavailable.reason);
+                    let error = SophiaError::from(unavailable);
+                    self.record_attempt(&attempt_log, candidate, &model, attempt_number, "skipped", Some(&error), None, rationale);
+                    last_error = Some(error);
+                    continue;
+                },
+            };
+
+            called_any = true;
+
+            // Once text has reached the UI, retrying or switching providers would
+            // splice two different answers together, so only do so before the first delta.
+            let mut streamed = false;
+            let mut retry = 0;
+            let result = loop {
+                let result = match &mut delivery {
+                    Delivery::Complete => client.chat(&model, messages, &params).await,
+                    Delivery::Stream(on_delta) => client.chat_stream(&model, messages, &params, &mut |delta| {
+                        streamed = true;
+                        on_delta(delta);
+                    }).await,
+                };
+
+                let error = match &result {
+                    Err(e) if !streamed && retry + 1 < policy.max_attempts && policy.should_retry(e) => e,
+                    _ => break result,
+                };
+                let delay = match policy.delay(retry + 1, error.retry_after()) {
+                    Some(delay) => delay,
+                    None => break result,
+                };
+
+                retry += 1;
+                log::warn!("Provider {:?} failed, retry {} in {:?}: {}", provider, retry, delay, error);
+                self.record_retry(&attempt_log, candidate, &model, attempt_number, retry, delay, error);
+                tokio::time::sleep(delay).await;
+            };
+
+            match result {
+                Ok(completion) => {
+                    if completion.is_truncated() {
+                        log::warn!("Reply from {:?} was cut off at the output token budget", provider);
+                    }
+                    self.record_attempt(&attempt_log, candidate, &model, attempt_number, "success", None, completion.finish_reason.as_deref(), rationale);
+                    self.record_completion(provider, &model, prompt_tokens, &completion);
+                    return Ok(completion);
+                },
+                Err(error) => {
+                    let fail_over = error.should_fail_over() && !streamed;
+                    log::warn!("Provider {:?} failed (fail over: {}): {}", provider, fail_over, error);
+                    self.record_attempt(&attempt_log, candidate, &model, attempt_number, "failed", Some(&error), None, rationale);
+
+                    if !fail_over {
+                        return Err(error);
+                    }
+                    last_error = Some(error);
+                },
+            }
+        }
+
+        // Nothing local could take it, so the policy is what stopped the request
+        if !called_any && !blocked.is_empty() {
+            return Err(egress.blocked_error(&blocked));
+        }
+        Err(last_error.unwrap_or(SophiaError::NoProviderAvailable))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn record_attempt(
+        &self,
+        log: &AttemptLog<'_>,
+        candidate: &Candidate,
+        model: &str,
+        attempt: usize,
+        outcome: &str,
+        error: Option<&SophiaError>,
+        finish_reason: Option<&str>,
+        rationale: String,
+    ) {
         // Log the routing decision (Audit)
         let _ = self.storage.record_decision(
-            None,
-            serde_json::json!({"input_length": input.len(), "estimated_tokens": prompt_tokens}),
-            serde_json::json!({"route": task_type, "model": &model, "provider": &provider}),
-            Some("Routing decision".to_string())
+            Some(log.routing_id.to_string()),
+            log.input_context.clone(),
+            serde_json::json!({
+                "route": &log.classification.task_type,
+                "classifier": &log.classification.classifier,
+                "confidence": log.classification.confidence,
+                "model": model,
+                "provider": &candidate.provider,
+                "route_source": candidate.source,
+                "attempt": attempt,
+                "outcome": outcome,
+                "error": error.map(|e| e.to_string()),
+                "error_kind": error.map(|e| e.kind()),
+                "finish_reason": finish_reason,
+            }),
+            Some(rationale)
         );
+    }
 
-        // Execute API call
-        let response = client.complete(&model, input)
-            .map_err(|e| e.to_string())?;
-
-        // Estimate completion tokens
-        let completion_tokens = crate::storage::estimate_tokens(&response);
-        let total_tokens = prompt_tokens + completion_tokens;
-
-        // Calculate cost
-        let pricing_calc = crate::storage::PricingCalculator::new();
-        let estimated_cost = pricing_calc.calculate_cost(&model, prompt_tokens, completion_tokens);
-
-        // Record usage
-        let usage_record = crate::storage::UsageRecord {
-            id: None,
-            timestamp: chrono::Utc::now().to_rfc3339(),
-            provider: provider.as_str().to_string(),
-            model: model.clone(),
-            prompt_tokens,
-            completion_tokens,
-            total_tokens,
-            estimated_cost_usd: estimated_cost,
-            request_id: None,
-        };
+    /// Records a retry against the same provider, so flaky providers show up
+    /// in the decision log and the audit trail even when a later attempt succeeds.
+    #[allow(clippy::too_many_arguments)]
+    fn record_retry(
+        &self,
+        log: &AttemptLog<'_>,
+        candidate: &Candidate,
+        model: &str,
+        attempt: usize,
+        retry: u32,
+        delay: Duration,
+        error: &SophiaError,
+    ) {
+        let _ = self.storage.record_decision(
+            Some(log.routing_id.to_string()),
+            log.input_context.clone(),
+            serde_json::json!({
+                "route": &log.classification.task_type,
+                "model": model,
+                "provider": &candidate.provider,
+                "attempt": attempt,
+                "retry": retry,
+                "delay_ms": delay.as_millis() as u64,
+                "outcome": "retrying",
+                "error": error.to_string(),
+                "error_kind": error.kind(),
+            }),
+            Some(format!("Retrying {} after transient failure", candidate.provider.as_str()))
+        );
+        self.audit("WARN", "provider_retry", serde_json::json!({
+            "routing_id": log.routing_id,
+            "provider": candidate.provider.as_str(),
+            "attempt": attempt,
+            "retry": retry,
+            "delay_ms": delay.as_millis() as u64,
+            "error_kind": error.kind(),
+        }));
+    }
 
-        if let Err(e) = self.storage.record_usage(&usage_record) {
-            log::warn!("Failed to record usage: {}", e);
-            // Don't fail the request if usage tracking fails
-        }
+    fn record_completion(&self, provider: &ProviderType, model: &str, estimated_prompt_tokens: i64, completion: &Completion) {
+        record_usage(&self.storage, provider, model, estimated_prompt_tokens, completion);
+    }
+}
+
+/// Records token usage and cost for one completed call. Shared with the LLM
+/// classifier, whose calls are billed like any other.
+pub(crate) fn record_usage(storage: &StorageManager, provider: &ProviderType, model: &str, estimated_prompt_tokens: i64, completion: &Completion) {
+    // Prefer the provider's own counts; estimate only when it didn't report any
+    let (prompt_tokens, completion_tokens, total_tokens) = match completion.usage {
+        Some(usage) => (usage.prompt_tokens, usage.completion_tokens, usage.total_tokens),
+        None => {
+            log::info!("Provider {:?} reported no usage, estimating tokens", provider);
+            let completion_tokens = crate::storage::estimate_tokens(&completion.text);
+            (estimated_prompt_tokens, completion_tokens, estimated_prompt_tokens + completion_tokens)
+        },
+    };
 
-        Ok(response)
+    // Calculate cost
+    let pricing_calc = crate::storage::PricingCalculator::new();
+    let estimated_cost = pricing_calc.calculate_cost(model, prompt_tokens, completion_tokens);
+
+    // Record usage
+    let usage_record = crate::storage::UsageRecord {
+        id: None,
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        provider: provider.as_str().to_string(),
+        model: model.to_string(),
+        prompt_tokens,
+        completion_tokens,
+        total_tokens,
+        estimated_cost_usd: estimated_cost,
+        request_id: completion.request_id.clone(),
+    };
+
+    if let Err(e) = storage.record_usage(&usage_record) {
+        log::warn!("Failed to record usage: {}", e);
+        // Don't fail the request if usage tracking fails
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tempfile::tempdir;
+    use crate::router::client::MockClient;
+    use crate::secret_store::SecretStore;
+    use tempfile::{tempdir, TempDir};
 
-    #[test]
-    fn test_classification_heuristics() {
+    /// A router over a fresh database and an in-memory secret store. Every
+    /// built-in provider answers from a `MockClient`, so no test reaches a
+    /// real endpoint.
+    struct Fixture {
+        /// Holds the database; keep it bound, dropping it deletes the files
+        dir: TempDir,
+        storage: Arc<StorageManager>,
+        secrets: Arc<SecretStore>,
+        registry: Arc<Mutex<ProviderRegistry>>,
+        router: ModelRouter,
+    }
+
+    fn fixture() -> Fixture {
         let dir = tempdir().unwrap();
-        let db_path = dir.path().join("test.db");
-        let storage = Arc::new(StorageManager::new_with_path(db_path));
-        let registry = Arc::new(std::sync::Mutex::new(ProviderRegistry::new(Arc::new(crate::secret_store::SecretStore::new("test")))));
-        let router = ModelRouter::new(storage, registry);
+        let storage = Arc::new(StorageManager::new_with_path(dir.path().join("test.db")));
+        let secrets = Arc::new(SecretStore::in_memory("test"));
+        let mut registry = ProviderRegistry::new(secrets.clone());
+        let providers: Vec<ProviderType> = registry.descriptors().iter().map(|d| d.provider.clone()).collect();
+        for provider in &providers {
+            registry.set_client_factory(provider, |_, _| Box::new(MockClient { response: "mock reply".to_string() }));
+        }
+        let registry = Arc::new(Mutex::new(registry));
+        let router = ModelRouter::new(storage.clone(), registry.clone());
+        Fixture { dir, storage, secrets, registry, router }
+    }
+
+    #[tokio::test]
+    async fn test_classification_heuristics() {
+        let Fixture { dir: _dir, router, .. } = fixture();
 
-        assert_eq!(router.classify_task("write a function to add numbers"), TaskType::CodeAnalysis);
-        assert_eq!(router.classify_task("create a plan for the project"), TaskType::Planning);
-        assert_eq!(router.classify_task("hello world"), TaskType::GeneralChat);
+        assert_eq!(router.classify_task("write a function to add numbers").await, TaskType::CodeAnalysis);
+        assert_eq!(router.classify_task("create a plan for the project").await, TaskType::Planning);
+        assert_eq!(router.classify_task("hello world").await, TaskType::GeneralChat);
     }
 
-    #[test]
-    fn test_routing_log() {
-        let dir = tempdir().unwrap();
-        let db_path = dir.path().join("test.db");
-        let storage = Arc::new(StorageManager::new_with_path(db_path));
-        let registry = Arc::new(std::sync::Mutex::new(ProviderRegistry::new(Arc::new(crate::secret_store::SecretStore::new("test")))));
-        let router = ModelRouter::new(storage.clone(), registry);
+    #[tokio::test]
+    async fn test_rule_classifier_recorded_in_decision() {
+        let Fixture { dir: _dir, router, storage, .. } = fixture();
+
+        // The heuristic reads "decode" as code; word-bounded rules don't
+        assert_eq!(router.classify_task("decode this data").await, TaskType::CodeAnalysis);
+        router.set_classifier_kind(ClassifierKind::Rules).unwrap();
+        assert_eq!(router.classify_task("decode this data").await, TaskType::DataProcessing);
+        assert_eq!(router.classify_task("Refactor this FUNCTION").await, TaskType::CodeAnalysis);
+
+        assert!(router.set_classifier_rules(vec![ClassificationRule {
+            task_type: TaskType::Planning,
+            pattern: "(".to_string(),
+            weight: 1.0,
+        }]).is_err());
+
+        let _ = router.route_and_execute("convert this csv").await;
+
+        let export = storage.export_all().unwrap();
+        let output = &export.decisions[0].decision_output;
+        assert_eq!(output["route"], "DataProcessing");
+        assert_eq!(output["classifier"], "rules");
+        assert_eq!(output["confidence"], 1.0);
+    }
 
-        let _ = router.route_and_execute("write code");
+    #[tokio::test]
+    async fn test_routing_log() {
+        let Fixture { dir: _dir, router, storage, .. } = fixture();
+
+        assert_eq!(router.route_and_execute("write code").await.unwrap(), "mock reply");
 
         // Check if decision was logged
         let export = storage.export_all().unwrap();
@@ -143,5 +694,184 @@ mod tests {
         let decision = &export.decisions[0];
         let output = &decision.decision_output;
         assert_eq!(output["route"].as_str().unwrap(), "CodeAnalysis");
+        assert_eq!(output["attempt"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_routing_table_drives_first_attempt() {
+        let Fixture { dir: _dir, router, storage, .. } = fixture();
+
+        assert!(router.get_routing_table().routes.is_empty());
+
+        router.set_task_route(TaskType::Planning, RouteTarget {
+            provider: ProviderType::Ollama,
+            model: "qwen2.5:14b".to_string(),
+        }).unwrap();
+        assert!(router.set_task_route(TaskType::Planning, RouteTarget {
+            provider: ProviderType::Ollama,
+            model: " ".to_string(),
+        }).is_err());
+
+        let _ = router.route_and_execute("make a plan for the week").await;
+
+        let export = storage.export_all().unwrap();
+        let output = &export.decisions[0].decision_output;
+        assert_eq!(output["route"], "Planning");
+        assert_eq!(output["model"], "qwen2.5:14b");
+        assert_eq!(output["route_source"], "routing_table");
+
+        // Unregistered providers and models missing from the catalog are refused
+        let unknown = RouteTarget { provider: ProviderType::from_str("gemni").unwrap(), model: "gemini-2.0-flash".to_string() };
+        assert_eq!(router.set_task_route(TaskType::Planning, unknown).unwrap_err().kind(), "InvalidInput");
+        ModelCatalog::new(vec![ModelInfo { id: "qwen2.5:14b".to_string(), display_name: None, context_length: None }])
+            .save(&storage, &ProviderType::Ollama).unwrap();
+        assert!(router.set_task_route(TaskType::Planning, RouteTarget {
+            provider: ProviderType::Ollama,
+            model: "qwen2.5:41b".to_string(),
+        }).is_err());
+        assert_eq!(router.get_routing_table().get(&TaskType::Planning).unwrap().model, "qwen2.5:14b");
+
+        let table = router.clear_task_route(&TaskType::Planning).unwrap();
+        assert!(table.get(&TaskType::Planning).is_none());
+    }
+
+    #[test]
+    fn test_model_config_seeds_routing_table() {
+        let Fixture { dir: _dir, storage, registry, .. } = fixture();
+        storage.set_preference("model_config", serde_json::to_value(ModelConfig::default()).unwrap()).unwrap();
+        // model_config is read when the router is built
+        let router = ModelRouter::new(storage, registry);
+
+        let table = router.get_routing_table();
+        assert_eq!(table.get(&TaskType::GeneralChat).unwrap().model, "llama3.2:3b");
+        assert_eq!(table.get(&TaskType::Planning).unwrap().model, "llama3.1:8b");
+    }
+
+    #[test]
+    fn test_usage_prefers_reported_tokens() {
+        let Fixture { dir, router, .. } = fixture();
+
+        let reported = Completion {
+            text: "four".to_string(),
+            usage: Some(crate::router::TokenUsage::new(12, 3, None)),
+            finish_reason: Some("stop".to_string()),
+            request_id: Some("chatcmpl-123".to_string()),
+        };
+        router.record_completion(&ProviderType::OpenAI, "gpt-4o-mini", 999, &reported);
+
+        // No usage reported: falls back to the estimate passed in
+        router.record_completion(&ProviderType::Ollama, "llama3.2:3b", 7, &Completion::from_text("abcdefgh"));
+
+        let tracker = crate::storage::UsageTracker::new(rusqlite::Connection::open(dir.path().join("test.db")).unwrap()).unwrap();
+        let records = tracker.get_recent_records(10).unwrap();
+        let openai = records.iter().find(|r| r.provider == "openai").unwrap();
+        assert_eq!((openai.prompt_tokens, openai.completion_tokens, openai.total_tokens), (12, 3, 15));
+        assert_eq!(openai.request_id.as_deref(), Some("chatcmpl-123"));
+
+        let ollama = records.iter().find(|r| r.provider == "ollama").unwrap();
+        assert_eq!((ollama.prompt_tokens, ollama.completion_tokens), (7, 2));
+        assert!(ollama.request_id.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_fallback_records_each_attempt() {
+        let Fixture { dir: _dir, router, storage, registry, .. } = fixture();
+        registry.lock().unwrap().set_provider_enabled(&ProviderType::Anthropic, true);
+        storage.set_preference("primary_provider", serde_json::json!("openrouter")).unwrap();
+
+        // OpenRouter is disabled and no Anthropic key is stored under this ID, so
+        // both are skipped without a request and it falls through to Ollama.
+        assert_eq!(router.route_and_execute("hello").await.unwrap(), "mock reply");
+
+        let export = storage.export_all().unwrap();
+        let attempts: Vec<_> = export.decisions.iter()
+            .map(|d| (d.decision_output["provider"].as_str().unwrap().to_string(), d.decision_output["outcome"].as_str().unwrap().to_string()))
+            .collect();
+        assert_eq!(attempts.len(), 3);
+        assert_eq!(attempts[0], ("OpenRouter".to_string(), "skipped".to_string()));
+        assert_eq!(attempts[1], ("Anthropic".to_string(), "skipped".to_string()));
+        assert_eq!(attempts[2], ("Ollama".to_string(), "success".to_string()));
+        assert_eq!(export.decisions[0].decision_output["error_kind"], "Auth");
+        assert_eq!(export.decisions[0].decision_output["error"], "openrouter: Provider is disabled");
+        assert_eq!(export.decisions[1].decision_output["error"], "anthropic: API key not found in keychain");
+
+        // All attempts belong to the same request
+        assert!(export.decisions.iter().all(|d| d.task_id == export.decisions[0].task_id));
+    }
+
+    #[tokio::test]
+    async fn test_local_only_blocks_remote_providers() {
+        let Fixture { dir, storage, secrets, registry, router } = fixture();
+        secrets.set_secret("anthropic_api_key", "sk-ant-egress-test").unwrap();
+        registry.lock().unwrap().set_provider_enabled(&ProviderType::Anthropic, true);
+        storage.set_preference("primary_provider", serde_json::json!("openrouter")).unwrap();
+        let audit_path = dir.path().join("audit.jsonl");
+        let router = router.with_audit_logger(Arc::new(AuditLogger::new(audit_path.clone())));
+
+        assert!(!router.set_local_only(true).unwrap().allows_remote());
+
+        // Both remote providers are blocked before any request; only Ollama is called
+        let _ = router.route_and_execute("hello").await;
+
+        let export = storage.export_all().unwrap();
+        let outcomes: Vec<_> = export.decisions.iter()
+            .map(|d| (d.decision_output["provider"].as_str().unwrap().to_string(), d.decision_output["outcome"].as_str().unwrap().to_string()))
+            .collect();
+        assert_eq!(outcomes[0], ("OpenRouter".to_string(), "blocked".to_string()));
+        assert_eq!(outcomes[1], ("Anthropic".to_string(), "blocked".to_string()));
+        assert_eq!(outcomes[2].0, "Ollama");
+        assert_eq!(export.decisions[0].decision_output["error_kind"], "EgressBlocked");
+        // A blocked provider's key is never read from the store
+        assert!(secrets.list_secrets().unwrap()[0].metadata.last_used_at.is_none());
+
+        let audit = std::fs::read_to_string(&audit_path).unwrap();
+        assert_eq!(audit.lines().filter(|line| line.contains("\"egress_blocked\"")).count(), 2);
+        assert!(audit.contains("\"egress_policy_changed\""));
+
+        // With nothing local to fall back to, the policy is the error
+        registry.lock().unwrap().set_provider_enabled(&ProviderType::Ollama, false);
+        let error = router.route_and_execute("hello").await.unwrap_err();
+        assert_eq!(error.kind(), "EgressBlocked");
+
+        // Consent alone also blocks
+        router.set_local_only(false).unwrap();
+        assert!(!router.set_network_egress_consent(false).unwrap().allows_remote());
+        assert_eq!(router.route_and_execute("hello").await.unwrap_err().kind(), "EgressBlocked");
+    }
+
+    #[tokio::test]
+    async fn test_blocked_provider_health_is_not_checked() {
+        let Fixture { dir: _dir, secrets, router, .. } = fixture();
+        secrets.set_secret("openai_api_key", "sk-openai-health-test").unwrap();
+        router.set_network_egress_consent(false).unwrap();
+
+        // Neither the stored key nor a candidate leaves the machine
+        for candidate in [None, Some("sk-openai-candidate-key")] {
+            let health = router.check_provider_health(&ProviderType::OpenAI, candidate).await.unwrap();
+            assert!(!health.reachable && !health.auth_ok);
+            assert_eq!(health.error_kind.as_deref(), Some("EgressBlocked"));
+        }
+        assert!(secrets.list_secrets().unwrap()[0].metadata.last_used_at.is_none());
+
+        let health = router.check_provider_health(&ProviderType::Ollama, None).await.unwrap();
+        assert!(health.reachable && health.auth_ok);
+    }
+
+    #[tokio::test]
+    async fn test_blocked_provider_models_are_not_fetched() {
+        let Fixture { dir: _dir, storage, secrets, registry, router } = fixture();
+        secrets.set_secret("openai_api_key", "sk-openai-listing-test").unwrap();
+        registry.lock().unwrap().set_provider_enabled(&ProviderType::OpenAI, true);
+        router.set_local_only(true).unwrap();
+
+        let error = router.list_models(&ProviderType::OpenAI, true).await.unwrap_err();
+        assert_eq!(error.kind(), "EgressBlocked");
+        // No request: the key was never read and no catalog was cached
+        assert!(secrets.list_secrets().unwrap()[0].metadata.last_used_at.is_none());
+        assert!(ModelCatalog::load(&storage, &ProviderType::OpenAI).unwrap().is_none());
+
+        // Local providers are still listed
+        assert!(router.list_models(&ProviderType::Ollama, true).await.is_ok());
+        assert!(ModelCatalog::load(&storage, &ProviderType::Ollama).unwrap().is_some());
     }
 }
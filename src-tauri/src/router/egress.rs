@@ -0,0 +1,88 @@
+use crate::error::SophiaError;
+use crate::storage::StorageManager;
+use serde::Serialize;
+
+/// Recorded at onboarding: whether prompts may be sent to cloud providers.
+pub const NETWORK_EGRESS_CONSENT_KEY: &str = "network_egress_consent";
+/// User toggle restricting routing to local providers even with consent.
+pub const LOCAL_ONLY_KEY: &str = "local_only";
+
+/// Whether the router may call providers off this machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct EgressPolicy {
+    pub network_egress_consent: bool,
+    pub local_only: bool,
+}
+
+impl EgressPolicy {
+    /// Reads both preferences. Only an explicit `false` withdraws consent:
+    /// a missing value predates onboarding recording it.
+    pub fn load(storage: &StorageManager) -> Self {
+        let flag = |key, default| match storage.get_preference(key) {
+            Ok(Some(val)) => val.as_bool().unwrap_or(default),
+            _ => default,
+        };
+        EgressPolicy {
+            network_egress_consent: flag(NETWORK_EGRESS_CONSENT_KEY, true),
+            local_only: flag(LOCAL_ONLY_KEY, false),
+        }
+    }
+
+    pub fn allows_remote(&self) -> bool {
+        self.block_reason().is_none()
+    }
+
+    /// Why remote providers are blocked, if they are.
+    pub fn block_reason(&self) -> Option<&'static str> {
+        if self.local_only {
+            Some("Local-only mode is on")
+        } else if !self.network_egress_consent {
+            Some("Network egress consent was not given")
+        } else {
+            None
+        }
+    }
+
+    /// The error for a request that had only remote providers to go to.
+    pub fn blocked_error(&self, blocked: &[String]) -> SophiaError {
+        SophiaError::EgressBlocked(format!(
+            "{}, so {} {} not called and no local provider (Ollama or a localhost endpoint) is available",
+            self.block_reason().unwrap_or("Network egress is blocked"),
+            blocked.join(", "),
+            if blocked.len() == 1 { "was" } else { "were" },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_policy_from_preferences() {
+        let dir = tempdir().unwrap();
+        let storage = StorageManager::new_with_path(dir.path().join("test.db"));
+
+        // Nothing recorded yet
+        assert!(EgressPolicy::load(&storage).allows_remote());
+
+        storage.set_preference(NETWORK_EGRESS_CONSENT_KEY, json!(false)).unwrap();
+        let policy = EgressPolicy::load(&storage);
+        assert!(!policy.allows_remote());
+        assert_eq!(policy.block_reason(), Some("Network egress consent was not given"));
+
+        storage.set_preference(NETWORK_EGRESS_CONSENT_KEY, json!(true)).unwrap();
+        storage.set_preference(LOCAL_ONLY_KEY, json!(true)).unwrap();
+        let policy = EgressPolicy::load(&storage);
+        assert!(!policy.allows_remote());
+
+        let error = policy.blocked_error(&["gemini".to_string(), "openai".to_string()]);
+        assert_eq!(error.kind(), "EgressBlocked");
+        assert_eq!(
+            error.to_string(),
+            "Local-only mode is on, so gemini, openai were not called and no local provider (Ollama or a localhost endpoint) is available"
+        );
+    }
+}
@@ -1,6 +1,12 @@
 pub mod types;
 pub mod client;
 pub mod core;
+pub mod retry;
+pub mod classifier;
+pub mod egress;
 
 pub use core::ModelRouter;
-pub use types::TaskType;
+pub use retry::RetryPolicy;
+pub use egress::EgressPolicy;
+pub use classifier::{Classification, ClassificationRule, ClassifierKind};
+pub use types::{AuthScheme, ChatMessage, ChatRole, Completion, GenerationParams, LocalModel, ModelInfo, RouteTarget, RoutingTable, StreamChunk, TaskType, TokenUsage};
@@ -0,0 +1,122 @@
+use crate::error::SophiaError;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// How often, and how patiently, a single provider is retried before the router
+/// fails over to the next one. Stored on each `ProviderConfig`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Total attempts against the provider, including the first. 1 disables retries.
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubled for each one after that.
+    pub initial_backoff_ms: u64,
+    /// Upper bound for the computed backoff. A `Retry-After` longer than this
+    /// is not waited out; the router fails over instead.
+    pub max_backoff_ms: u64,
+    /// Fraction of the backoff that is randomized (0.0 - 1.0), so clients that
+    /// failed together don't retry together.
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 10_000,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A single attempt, e.g. for a local server that is either up or not.
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Rate limits, outages and network failures (resets, timeouts) may succeed on retry.
+    pub fn should_retry(&self, err: &SophiaError) -> bool {
+        err.is_retryable()
+    }
+
+    /// How long to wait before retry number `retry` (1-based), or None if the
+    /// provider asked for a longer wait than the policy allows.
+    pub fn delay(&self, retry: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        self.delay_with(retry, retry_after, rand::thread_rng().gen::<f64>())
+    }
+
+    /// `delay` with the random draw (0.0 - 1.0) passed in.
+    fn delay_with(&self, retry: u32, retry_after: Option<Duration>, random: f64) -> Option<Duration> {
+        let max = Duration::from_millis(self.max_backoff_ms);
+        if let Some(wait) = retry_after {
+            return (wait <= max).then_some(wait);
+        }
+
+        let exponent = retry.saturating_sub(1).min(16);
+        let backoff = self.initial_backoff_ms.saturating_mul(1 << exponent).min(self.max_backoff_ms) as f64;
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        // Spread the backoff over [1 - jitter, 1] of its value
+        let millis = backoff * (1.0 - jitter * random);
+        Some(Duration::from_millis(millis as u64))
+    }
+}
+
+/// Parses a `Retry-After` header: either delay-seconds or an HTTP date.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let wait = date.with_timezone(&chrono::Utc) - chrono::Utc::now();
+    // A date in the past means "retry now"
+    Some(wait.to_std().unwrap_or(Duration::ZERO))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_and_is_capped() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 300,
+            jitter: 0.5,
+        };
+
+        assert_eq!(policy.delay_with(1, None, 0.0), Some(Duration::from_millis(100)));
+        assert_eq!(policy.delay_with(2, None, 0.0), Some(Duration::from_millis(200)));
+        assert_eq!(policy.delay_with(3, None, 0.0), Some(Duration::from_millis(300)));
+        // Full jitter draw takes off half the backoff
+        assert_eq!(policy.delay_with(2, None, 1.0), Some(Duration::from_millis(100)));
+
+        assert_eq!(policy.delay_with(1, Some(Duration::from_millis(250)), 0.5), Some(Duration::from_millis(250)));
+        assert_eq!(policy.delay_with(1, Some(Duration::from_secs(60)), 0.5), None);
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("7"), Some(Duration::from_secs(7)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn test_should_retry() {
+        let policy = RetryPolicy::default();
+        let limited = SophiaError::RateLimited { message: String::new(), retry_after: Some(Duration::from_secs(2)) };
+        assert!(policy.should_retry(&limited));
+        assert!(policy.should_retry(&SophiaError::Network("connection reset".into())));
+        assert!(!policy.should_retry(&SophiaError::Auth("invalid key".into())));
+        assert!(!policy.should_retry(&SophiaError::ProviderRejected("bad request".into())));
+    }
+}
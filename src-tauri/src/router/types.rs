@@ -1,6 +1,8 @@
+use crate::providers::ProviderType;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum TaskType {
     GeneralChat,
     CodeAnalysis,
@@ -8,6 +10,208 @@ pub enum TaskType {
     DataProcessing,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatRole {
+    System,
+    User,
+    Assistant,
+}
+
+impl ChatRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChatRole::System => "system",
+            ChatRole::User => "user",
+            ChatRole::Assistant => "assistant",
+        }
+    }
+
+    pub fn from_str(value: &str) -> Option<Self> {
+        match value {
+            "system" => Some(ChatRole::System),
+            "user" => Some(ChatRole::User),
+            "assistant" => Some(ChatRole::Assistant),
+            _ => None,
+        }
+    }
+}
+
+/// A single role-tagged turn in a conversation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: ChatRole,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn new(role: ChatRole, content: &str) -> Self {
+        ChatMessage {
+            role,
+            content: content.to_string(),
+        }
+    }
+
+    pub fn user(content: &str) -> Self {
+        Self::new(ChatRole::User, content)
+    }
+}
+
+/// Token counts as reported by the provider.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub total_tokens: i64,
+}
+
+impl TokenUsage {
+    /// Uses the provider's total when it reports one (it may include tokens,
+    /// like reasoning, that are in neither prompt nor completion).
+    pub fn new(prompt_tokens: i64, completion_tokens: i64, total_tokens: Option<i64>) -> Self {
+        TokenUsage {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: total_tokens.unwrap_or(prompt_tokens + completion_tokens),
+        }
+    }
+}
+
+/// Result of a completion call.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Completion {
+    pub text: String,
+    /// None when the provider did not report usage.
+    pub usage: Option<TokenUsage>,
+    pub finish_reason: Option<String>,
+    /// The provider's own ID for the response, for cross-referencing its dashboard.
+    pub request_id: Option<String>,
+}
+
+impl Completion {
+    pub fn from_text(text: &str) -> Self {
+        Completion {
+            text: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Whether the reply stopped at the output token budget rather than
+    /// finishing (Anthropic `max_tokens`, OpenAI `length`, Gemini `MAX_TOKENS`).
+    pub fn is_truncated(&self) -> bool {
+        matches!(self.finish_reason.as_deref(), Some("max_tokens" | "length" | "MAX_TOKENS"))
+    }
+
+    /// Appends a continuation of this reply, so both count as one completion.
+    /// Usage is summed; it becomes unknown if either part lacks it.
+    pub fn extend(&mut self, next: Completion) {
+        self.text.push_str(&next.text);
+        self.usage = match (self.usage, next.usage) {
+            (Some(a), Some(b)) => Some(TokenUsage::new(
+                a.prompt_tokens + b.prompt_tokens,
+                a.completion_tokens + b.completion_tokens,
+                Some(a.total_tokens + b.total_tokens),
+            )),
+            _ => None,
+        };
+        self.finish_reason = next.finish_reason;
+        if self.request_id.is_none() {
+            self.request_id = next.request_id;
+        }
+    }
+}
+
+/// A model offered by a provider's model-list endpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelInfo {
+    /// The identifier requests are sent with.
+    pub id: String,
+    pub display_name: Option<String>,
+    pub context_length: Option<u64>,
+}
+
+/// A model installed on the local Ollama server.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LocalModel {
+    pub name: String,
+    pub size_bytes: u64,
+    /// e.g. "8.0B"
+    pub parameter_size: Option<String>,
+    /// e.g. "Q4_K_M"
+    pub quantization: Option<String>,
+    pub family: Option<String>,
+    /// Maximum context window in tokens, from the model's metadata.
+    pub context_length: Option<u64>,
+}
+
+/// Sampling and output settings for a request. Unset fields are left out of
+/// the request so the provider's own default applies.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenerationParams {
+    /// Sent as each provider's native system instruction, ahead of any
+    /// system turns already in the conversation.
+    pub system_prompt: Option<String>,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub max_output_tokens: Option<u32>,
+    pub stop_sequences: Vec<String>,
+    /// Best-effort determinism; providers without a seed parameter ignore it.
+    pub seed: Option<u64>,
+    /// How many times a reply cut off by `max_output_tokens` is automatically
+    /// continued. Honored by Anthropic, which can resume a partial reply.
+    pub max_continuations: Option<u32>,
+}
+
+impl GenerationParams {
+    /// These params with every field set in `overrides` replaced.
+    pub fn merged(&self, overrides: &GenerationParams) -> GenerationParams {
+        GenerationParams {
+            system_prompt: overrides.system_prompt.clone().or_else(|| self.system_prompt.clone()),
+            temperature: overrides.temperature.or(self.temperature),
+            top_p: overrides.top_p.or(self.top_p),
+            max_output_tokens: overrides.max_output_tokens.or(self.max_output_tokens),
+            stop_sequences: if overrides.stop_sequences.is_empty() {
+                self.stop_sequences.clone()
+            } else {
+                overrides.stop_sequences.clone()
+            },
+            seed: overrides.seed.or(self.seed),
+            max_continuations: overrides.max_continuations.or(self.max_continuations),
+        }
+    }
+
+    /// Rejects values no provider accepts.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(t) = self.temperature {
+            if !(0.0..=2.0).contains(&t) {
+                return Err(format!("temperature must be between 0 and 2, got {}", t));
+            }
+        }
+        if let Some(p) = self.top_p {
+            if !(0.0..=1.0).contains(&p) {
+                return Err(format!("top_p must be between 0 and 1, got {}", p));
+            }
+        }
+        if self.max_output_tokens == Some(0) {
+            return Err("max_output_tokens must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// How an OpenAI-compatible endpoint expects the API key.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuthScheme {
+    /// `Authorization: Bearer <key>`
+    #[default]
+    Bearer,
+    /// The raw key in the named header, e.g. `api-key` for Azure-style gateways.
+    Header(String),
+    /// No key at all, e.g. LM Studio or a llama.cpp server on localhost.
+    None,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelConfig {
     pub fast_model: String,
@@ -24,3 +228,54 @@ impl Default for ModelConfig {
         }
     }
 }
+
+/// The provider and model a task type is sent to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteTarget {
+    pub provider: ProviderType,
+    pub model: String,
+}
+
+/// Task-bound routing: which provider/model handles each kind of task.
+/// Task types without an entry use the normal provider order.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoutingTable {
+    pub routes: HashMap<TaskType, RouteTarget>,
+}
+
+impl RoutingTable {
+    /// Seeds a table from the local `ModelConfig`: quick tasks go to the fast
+    /// model, reasoning-heavy ones to the complex model.
+    pub fn from_model_config(config: &ModelConfig) -> Self {
+        let target = |model: &str| RouteTarget {
+            provider: ProviderType::Ollama,
+            model: model.to_string(),
+        };
+
+        let mut routes = HashMap::new();
+        routes.insert(TaskType::GeneralChat, target(&config.fast_model));
+        routes.insert(TaskType::DataProcessing, target(&config.fast_model));
+        routes.insert(TaskType::CodeAnalysis, target(&config.complex_model));
+        routes.insert(TaskType::Planning, target(&config.complex_model));
+
+        RoutingTable { routes }
+    }
+
+    pub fn get(&self, task_type: &TaskType) -> Option<&RouteTarget> {
+        self.routes.get(task_type)
+    }
+}
+
+/// Payload of the `prompt-stream` event emitted while a streamed prompt runs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamChunk {
+    pub request_id: String,
+    pub delta: String,
+    pub done: bool,
+    /// Set on the final chunk: why the provider stopped, if it said.
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+    /// Set on the final chunk when the reply hit the output token budget.
+    #[serde(default)]
+    pub truncated: bool,
+}
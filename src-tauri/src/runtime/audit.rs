@@ -1,3 +1,4 @@
+use crate::redact::redact_value;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -37,13 +38,14 @@ impl AuditLogger {
         Self::new(log_path)
     }
 
+    /// Appends an entry. Known secrets are redacted from `context` first.
     pub fn log(&self, level: &str, component: &str, event: &str, context: Value) {
         let entry = AuditLogEntry {
             timestamp: Utc::now(),
             level: level.to_string(),
             component: component.to_string(),
             event: event.to_string(),
-            context,
+            context: redact_value(&context),
         };
 
         if let Ok(json) = serde_json::to_string(&entry) {
@@ -96,4 +98,18 @@ mod tests {
         assert!(content.contains("write_event"));
         assert!(content.contains("INFO"));
     }
+
+    #[test]
+    fn test_audit_log_redacts_secrets() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("audit_redact.jsonl");
+        let logger = AuditLogger::new(file_path.clone());
+
+        crate::redact::register_secret("sk-audit-test-secret");
+        logger.log("ERROR", "router", "provider_failed", serde_json::json!({"error": "bad key sk-audit-test-secret"}));
+
+        let content = fs::read_to_string(file_path).unwrap();
+        assert!(!content.contains("sk-audit-test-secret"));
+        assert!(content.contains("bad key [REDACTED]"));
+    }
 }
@@ -1,6 +1,9 @@
+use crate::error::SophiaError;
 use crate::runtime::audit::AuditLogger;
+use crate::runtime::requests::RequestTracker;
 use crate::runtime::state::RuntimeState;
 use serde_json::json;
+use std::future::Future;
 use std::sync::Mutex;
 use tauri::AppHandle;
 
@@ -11,6 +14,7 @@ use std::time::Duration;
 pub struct RuntimeManager {
     state: Arc<Mutex<RuntimeState>>,
     logger: Arc<AuditLogger>,
+    requests: RequestTracker,
 }
 
 impl RuntimeManager {
@@ -18,6 +22,7 @@ impl RuntimeManager {
         RuntimeManager {
             state: Arc::new(Mutex::new(RuntimeState::Stopped)),
             logger: Arc::new(AuditLogger::from_app(app_handle)),
+            requests: RequestTracker::new(),
         }
     }
 
@@ -25,6 +30,32 @@ impl RuntimeManager {
         *self.state.lock().unwrap()
     }
 
+    /// Runs a prompt under `request_id` so it can be cancelled. Rejected while
+    /// paused; the check and registration happen under the state lock, so a
+    /// concurrent pause either rejects the request or cancels it.
+    pub async fn run_request<F: Future>(&self, request_id: &str, future: F) -> Result<F::Output, SophiaError> {
+        let request = {
+            let state = self.state.lock().unwrap();
+            if *state == RuntimeState::Paused {
+                return Err(SophiaError::RuntimePaused);
+            }
+            self.requests.register(request_id)?
+        };
+
+        request.run(future).await
+    }
+
+    pub fn cancel_request(&self, request_id: &str) -> bool {
+        let cancelled = self.requests.cancel(request_id);
+        if cancelled {
+            self.logger.log("INFO", "runtime", "request_cancelled", json!({
+                "request_id": request_id,
+                "reason": "User Requested Cancel"
+            }));
+        }
+        cancelled
+    }
+
     fn spawn_runtime_loop(&self) {
         let state_clone = self.state.clone();
         let logger_clone = self.logger.clone();
@@ -110,8 +141,18 @@ impl RuntimeManager {
         self.transition_to(RuntimeState::Stopped, "System Shutdown")
     }
 
+    /// Pauses the runtime and cancels every prompt still in flight.
     pub fn pause(&self) -> Result<(), String> {
-        self.transition_to(RuntimeState::Paused, "User Requested Pause")
+        self.transition_to(RuntimeState::Paused, "User Requested Pause")?;
+
+        let cancelled = self.requests.cancel_all();
+        if !cancelled.is_empty() {
+            self.logger.log("INFO", "runtime", "request_cancelled", json!({
+                "request_ids": cancelled,
+                "reason": "User Requested Pause"
+            }));
+        }
+        Ok(())
     }
 
     pub fn resume(&self) -> Result<(), String> {
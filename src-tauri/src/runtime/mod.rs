@@ -1,6 +1,8 @@
 pub mod audit;
 pub mod manager;
+pub mod requests;
 pub mod state;
 
 pub use manager::RuntimeManager;
+pub use requests::RequestTracker;
 pub use state::RuntimeState;
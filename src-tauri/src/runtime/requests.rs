@@ -0,0 +1,116 @@
+use crate::error::SophiaError;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use tokio::sync::oneshot;
+
+/// Prompts currently being executed, keyed by request ID, so they can be
+/// cancelled one at a time or all together when the runtime pauses.
+#[derive(Default)]
+pub struct RequestTracker {
+    requests: Mutex<HashMap<String, oneshot::Sender<()>>>,
+}
+
+/// A tracked request. Removes itself from the tracker when dropped.
+pub struct InFlightRequest<'a> {
+    tracker: &'a RequestTracker,
+    request_id: String,
+    cancelled: oneshot::Receiver<()>,
+}
+
+impl RequestTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&self, request_id: &str) -> Result<InFlightRequest<'_>, SophiaError> {
+        let mut requests = self.requests.lock().unwrap();
+        if requests.contains_key(request_id) {
+            return Err(SophiaError::InvalidInput(format!("Request {} is already running", request_id)));
+        }
+
+        let (sender, cancelled) = oneshot::channel();
+        requests.insert(request_id.to_string(), sender);
+
+        Ok(InFlightRequest {
+            tracker: self,
+            request_id: request_id.to_string(),
+            cancelled,
+        })
+    }
+
+    /// Cancels one request. Returns false if it isn't (or is no longer) running.
+    pub fn cancel(&self, request_id: &str) -> bool {
+        match self.requests.lock().unwrap().remove(request_id) {
+            Some(sender) => {
+                let _ = sender.send(());
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Cancels every running request and returns their IDs.
+    pub fn cancel_all(&self) -> Vec<String> {
+        self.requests.lock().unwrap()
+            .drain()
+            .map(|(request_id, sender)| {
+                let _ = sender.send(());
+                request_id
+            })
+            .collect()
+    }
+
+    pub fn in_flight(&self) -> Vec<String> {
+        self.requests.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+impl InFlightRequest<'_> {
+    /// Drives `future` until it finishes or the request is cancelled. On cancel the
+    /// future is dropped, which aborts any provider call it was awaiting.
+    pub async fn run<F: Future>(mut self, future: F) -> Result<F::Output, SophiaError> {
+        tokio::select! {
+            output = future => Ok(output),
+            _ = &mut self.cancelled => Err(SophiaError::Cancelled(self.request_id.clone())),
+        }
+    }
+}
+
+impl Drop for InFlightRequest<'_> {
+    fn drop(&mut self) {
+        self.tracker.requests.lock().unwrap().remove(&self.request_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_cancel_aborts_running_request() {
+        let tracker = RequestTracker::new();
+        let request = tracker.register("req-1").unwrap();
+        assert!(tracker.register("req-1").is_err());
+        assert_eq!(tracker.in_flight(), vec!["req-1".to_string()]);
+
+        let (result, cancelled) = tokio::join!(
+            request.run(tokio::time::sleep(Duration::from_secs(60))),
+            async { tracker.cancel("req-1") },
+        );
+        assert!(cancelled);
+        assert_eq!(result, Err(SophiaError::Cancelled("req-1".to_string())));
+        assert!(tracker.in_flight().is_empty());
+        assert!(!tracker.cancel("req-1"));
+    }
+
+    #[tokio::test]
+    async fn test_finished_request_is_untracked() {
+        let tracker = RequestTracker::new();
+        let request = tracker.register("req-2").unwrap();
+        assert_eq!(request.run(async { 42 }).await, Ok(42));
+        assert!(tracker.in_flight().is_empty());
+        assert!(tracker.cancel_all().is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+use serde::{Deserialize, Serialize};
+use super::entry::Secrets;
+use super::file_backend::FileBackend;
+use super::keyring_backend::KeyringBackend;
+use super::memory_backend::MemoryBackend;
+
+const NO_PASSPHRASE: &str = "Only the encrypted file backend supports a master passphrase";
+
+/// Where the `SecretStore` keeps secrets between runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// The OS keyring: Secret Service on Linux, Keychain on macOS, Credential Manager on Windows
+    Keyring,
+    /// `~/.sophia/secrets.enc`, AES-GCM with a machine-ID or passphrase key
+    File,
+    /// Nothing is persisted; secrets last until the app exits
+    Memory,
+}
+
+impl BackendKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackendKind::Keyring => "keyring",
+            BackendKind::File => "file",
+            BackendKind::Memory => "memory",
+        }
+    }
+
+    pub fn from_str(value: &str) -> Option<Self> {
+        match value {
+            "keyring" => Some(BackendKind::Keyring),
+            "file" => Some(BackendKind::File),
+            "memory" => Some(BackendKind::Memory),
+            _ => None,
+        }
+    }
+
+    /// What to try, in order, when this backend is preferred: the preferred
+    /// one first, then whatever is less demanding of the platform.
+    pub fn fallback_chain(&self) -> &'static [BackendKind] {
+        match self {
+            BackendKind::Keyring => &[BackendKind::Keyring, BackendKind::File, BackendKind::Memory],
+            BackendKind::File => &[BackendKind::File, BackendKind::Memory],
+            BackendKind::Memory => &[BackendKind::Memory],
+        }
+    }
+}
+
+/// Persistent storage behind the `SecretStore`, which caches every secret in
+/// memory and hands the backend the whole set on each change.
+pub trait SecretBackend: Send {
+    fn kind(&self) -> BackendKind;
+
+    /// Every stored secret.
+    fn load(&self) -> Result<Secrets, String>;
+
+    /// Replaces the stored secrets with `secrets`.
+    fn save(&self, secrets: &Secrets) -> Result<(), String>;
+
+    /// A previous copy of the secrets, for when `load` fails.
+    fn load_from_backup(&self) -> Result<Secrets, String> {
+        Err("Backend keeps no backup".to_string())
+    }
+
+    /// Removes every stored secret, including any backup, after a migration.
+    fn clear(&self) -> Result<(), String> {
+        self.save(&Secrets::new())
+    }
+
+    // Master passphrase support; only the file backend has it
+
+    fn is_passphrase_protected(&self) -> bool {
+        false
+    }
+
+    fn is_locked(&self) -> bool {
+        false
+    }
+
+    fn unlock(&mut self, _passphrase: &str) -> Result<Secrets, String> {
+        Err(NO_PASSPHRASE.to_string())
+    }
+
+    fn lock(&mut self) {}
+
+    fn verify_passphrase(&self, _passphrase: &str) -> bool {
+        false
+    }
+
+    fn set_passphrase(&mut self, _passphrase: &str, _secrets: &Secrets) -> Result<(), String> {
+        Err(NO_PASSPHRASE.to_string())
+    }
+
+    fn remove_passphrase(&mut self, _secrets: &Secrets) -> Result<(), String> {
+        Err(NO_PASSPHRASE.to_string())
+    }
+
+    /// Re-encrypts `secrets` under a new master key; `passphrase` is the
+    /// current master passphrase, if there is one.
+    fn rotate_master_key(&mut self, _passphrase: Option<&str>, _secrets: &Secrets) -> Result<(), String> {
+        Err(format!("The {} backend has no master key to rotate", self.kind().as_str()))
+    }
+}
+
+/// Opens the backend of the given kind. Fails if the platform can't provide
+/// it, e.g. no Secret Service is running.
+pub fn open_backend(kind: BackendKind, service: &str) -> Result<Box<dyn SecretBackend>, String> {
+    Ok(match kind {
+        BackendKind::Keyring => Box::new(KeyringBackend::new(service)?),
+        BackendKind::File => Box::new(FileBackend::new()?),
+        BackendKind::Memory => Box::new(MemoryBackend::default()),
+    })
+}
+
+/// The first backend in `preferred`'s fallback chain that opens.
+pub fn open_preferred(preferred: BackendKind, service: &str) -> Box<dyn SecretBackend> {
+    for kind in preferred.fallback_chain() {
+        match open_backend(*kind, service) {
+            Ok(backend) => {
+                if *kind != preferred {
+                    log::warn!("Secret backend '{}' unavailable, using '{}'", preferred.as_str(), kind.as_str());
+                }
+                return backend;
+            },
+            Err(e) => log::warn!("Could not open '{}' secret backend: {}", kind.as_str(), e),
+        }
+    }
+    Box::new(MemoryBackend::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backend_kind_names() {
+        for kind in [BackendKind::Keyring, BackendKind::File, BackendKind::Memory] {
+            assert_eq!(BackendKind::from_str(kind.as_str()), Some(kind));
+            assert_eq!(kind.fallback_chain()[0], kind);
+            assert_eq!(*kind.fallback_chain().last().unwrap(), BackendKind::Memory);
+        }
+        assert_eq!(BackendKind::from_str("vault"), None);
+        assert_eq!(serde_json::to_value(BackendKind::Keyring).unwrap(), "keyring");
+    }
+
+    #[test]
+    fn test_passphrase_unsupported_by_default() {
+        let mut backend = MemoryBackend::default();
+        assert!(!backend.is_passphrase_protected() && !backend.is_locked());
+        assert_eq!(backend.set_passphrase("correct horse", &Secrets::new()).unwrap_err(), NO_PASSPHRASE);
+        assert!(backend.unlock("correct horse").is_err());
+    }
+}
@@ -2,10 +2,88 @@ use aes_gcm::{
     aead::{Aead, KeyInit, OsRng},
     Aes256Gcm, Nonce, Key
 };
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use rand::RngCore;
+use serde::{Deserialize, Serialize};
 use std::error::Error;
 
 const NONCE_SIZE: usize = 12; // 96 bits for GCM
+const SALT_SIZE: usize = 16;
+
+/// How a passphrase-derived master key was made. Stored in the clear next to
+/// the encrypted secrets, so the key can be derived again on unlock; the
+/// costs are kept per file so they can be raised without breaking old files.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub algorithm: String,
+    /// Random per-file salt, base64
+    pub salt: String,
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl KdfParams {
+    /// Argon2id with a fresh random salt, at the OWASP-recommended minimum
+    /// cost (19 MiB, 2 passes).
+    pub fn generate() -> Self {
+        KdfParams {
+            algorithm: "argon2id".to_string(),
+            salt: generate_key_salt(),
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+/// Derives a master encryption key from a passphrase with Argon2id
+pub fn derive_passphrase_key(passphrase: &str, kdf: &KdfParams) -> Result<[u8; 32], Box<dyn Error>> {
+    if kdf.algorithm != "argon2id" {
+        return Err(format!("Unsupported key derivation: {}", kdf.algorithm).into());
+    }
+    let salt = BASE64.decode(&kdf.salt)
+        .map_err(|e| format!("Invalid KDF salt: {}", e))?;
+    let params = Params::new(kdf.memory_kib, kdf.iterations, kdf.parallelism, Some(32))
+        .map_err(|e| format!("Invalid KDF parameters: {}", e))?;
+
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+        .map_err(|e| format!("Key derivation failed: {}", e))?;
+
+    log::info!("Master key derived from passphrase");
+    Ok(key)
+}
+
+/// A fresh random salt for `derive_rotated_master_key`, base64.
+pub fn generate_key_salt() -> String {
+    let mut salt = [0u8; SALT_SIZE];
+    OsRng.fill_bytes(&mut salt);
+    BASE64.encode(salt)
+}
+
+/// Derives a master encryption key from machine ID, application salt and a
+/// per-file `key_salt`, which is replaced on each key rotation. Without a
+/// salt this is the key used before rotation existed.
+pub fn derive_rotated_master_key(key_salt: Option<&str>) -> Result<[u8; 32], Box<dyn Error>> {
+    let key = derive_master_key()?;
+    let Some(key_salt) = key_salt else {
+        return Ok(key);
+    };
+    let salt = BASE64.decode(key_salt)
+        .map_err(|e| format!("Invalid key salt: {}", e))?;
+
+    use sha2::{Sha256, Digest};
+    let mut hasher = Sha256::new();
+    hasher.update(key);
+    hasher.update(&salt);
+
+    let mut rotated = [0u8; 32];
+    rotated.copy_from_slice(&hasher.finalize());
+    Ok(rotated)
+}
 
 /// Derives a master encryption key from machine ID and application salt
 pub fn derive_master_key() -> Result<[u8; 32], Box<dyn Error>> {
@@ -92,6 +170,35 @@ mod tests {
         assert_eq!(key1.len(), 32);
     }
 
+    #[test]
+    fn test_passphrase_key_derivation() {
+        let kdf = KdfParams::generate();
+        let key1 = derive_passphrase_key("correct horse battery staple", &kdf).unwrap();
+        let key2 = derive_passphrase_key("correct horse battery staple", &kdf).unwrap();
+        assert_eq!(key1, key2);
+
+        // Wrong passphrase, or the same one with another file's salt
+        assert_ne!(derive_passphrase_key("wrong horse", &kdf).unwrap(), key1);
+        assert_ne!(derive_passphrase_key("correct horse battery staple", &KdfParams::generate()).unwrap(), key1);
+        assert_ne!(key1, derive_master_key().unwrap());
+
+        let unknown = KdfParams { algorithm: "scrypt".to_string(), ..kdf };
+        assert!(derive_passphrase_key("correct horse battery staple", &unknown).is_err());
+    }
+
+    #[test]
+    fn test_rotated_master_key_derivation() {
+        let master_key = derive_master_key().unwrap();
+        assert_eq!(derive_rotated_master_key(None).unwrap(), master_key);
+
+        let salt = generate_key_salt();
+        let rotated = derive_rotated_master_key(Some(&salt)).unwrap();
+        assert_eq!(derive_rotated_master_key(Some(&salt)).unwrap(), rotated);
+        assert_ne!(rotated, master_key);
+        assert_ne!(derive_rotated_master_key(Some(&generate_key_salt())).unwrap(), rotated);
+        assert!(derive_rotated_master_key(Some("not base64!")).is_err());
+    }
+
     #[test]
     fn test_encrypt_decrypt_roundtrip() {
         let master_key = derive_master_key().unwrap();
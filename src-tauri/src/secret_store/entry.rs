@@ -0,0 +1,156 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use super::secret::SecretString;
+
+/// A secret that has gone this long without being used (or, if never used,
+/// since it was stored) is flagged as stale.
+pub const STALE_AFTER_DAYS: i64 = 90;
+
+/// Every stored secret, by key.
+pub type Secrets = HashMap<String, SecretEntry>;
+
+/// A stored secret and what is known about it. Only `value` is secret.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "StoredSecret")]
+pub struct SecretEntry {
+    pub value: SecretString,
+    #[serde(flatten)]
+    pub metadata: SecretMetadata,
+}
+
+/// Timestamps are None when unknown, e.g. for secrets stored before
+/// metadata was kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SecretMetadata {
+    pub created_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    /// When the value was last replaced
+    pub last_rotated_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// The provider the secret is an API key for
+    pub provider: Option<String>,
+}
+
+/// A secret's metadata as `list_secrets` shows it, without the value.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SecretInfo {
+    pub key: String,
+    #[serde(flatten)]
+    pub metadata: SecretMetadata,
+    pub expired: bool,
+    pub stale: bool,
+}
+
+/// How a secret is read back: with metadata, or as the bare value stored
+/// before metadata was kept.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredSecret {
+    Entry {
+        value: SecretString,
+        #[serde(flatten)]
+        metadata: SecretMetadata,
+    },
+    Plain(SecretString),
+}
+
+impl From<StoredSecret> for SecretEntry {
+    fn from(stored: StoredSecret) -> Self {
+        match stored {
+            StoredSecret::Entry { value, metadata } => SecretEntry { value, metadata },
+            StoredSecret::Plain(value) => SecretEntry { value, metadata: SecretMetadata::default() },
+        }
+    }
+}
+
+/// A bare value, with no metadata known.
+impl From<String> for SecretEntry {
+    fn from(value: String) -> Self {
+        SecretEntry { value: value.into(), metadata: SecretMetadata::default() }
+    }
+}
+
+impl SecretEntry {
+    /// A newly stored secret.
+    pub fn new(value: impl Into<SecretString>, provider: Option<&str>) -> Self {
+        SecretEntry {
+            value: value.into(),
+            metadata: SecretMetadata {
+                created_at: Some(Utc::now()),
+                provider: provider.map(str::to_string),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Replaces the value, if it changed. An expiry belonged to the old value,
+    /// so it is dropped.
+    pub fn rotate(&mut self, value: SecretString) {
+        if self.value != value {
+            self.value = value;
+            self.metadata.last_rotated_at = Some(Utc::now());
+            self.metadata.expires_at = None;
+        }
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.metadata.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Unused for `STALE_AFTER_DAYS`. A secret with no known dates isn't.
+    pub fn is_stale(&self, now: DateTime<Utc>) -> bool {
+        let metadata = &self.metadata;
+        metadata.last_used_at
+            .or(metadata.last_rotated_at.max(metadata.created_at))
+            .is_some_and(|last_active| now - last_active >= Duration::days(STALE_AFTER_DAYS))
+    }
+
+    pub fn info(&self, key: &str, now: DateTime<Utc>) -> SecretInfo {
+        SecretInfo {
+            key: key.to_string(),
+            metadata: self.metadata.clone(),
+            expired: self.is_expired(now),
+            stale: self.is_stale(now),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bare_values_are_read_as_entries() {
+        let secrets: Secrets = serde_json::from_str(r#"{"openai_api_key": "sk-legacy-value"}"#).unwrap();
+        let entry = &secrets["openai_api_key"];
+        assert_eq!(entry.value.expose_secret(), "sk-legacy-value");
+        assert_eq!(entry.metadata, SecretMetadata::default());
+
+        let entry = SecretEntry::new("sk-new-value", Some("openai"));
+        let json = serde_json::to_string(&entry).unwrap();
+        assert_eq!(serde_json::from_str::<SecretEntry>(&json).unwrap(), entry);
+    }
+
+    #[test]
+    fn test_expiry_staleness_and_rotation() {
+        let now = Utc::now();
+        let mut entry = SecretEntry::new("sk-first-value", Some("openai"));
+        assert!(!entry.is_expired(now) && !entry.is_stale(now));
+        assert!(!SecretEntry::from("sk-bare-value".to_string()).is_stale(now));
+
+        entry.metadata.expires_at = Some(now - Duration::days(1));
+        assert!(entry.info("openai_api_key", now).expired);
+        entry.metadata.created_at = Some(now - Duration::days(STALE_AFTER_DAYS));
+        assert!(entry.is_stale(now));
+
+        entry.metadata.last_used_at = Some(now);
+        assert!(!entry.is_stale(now));
+
+        entry.rotate("sk-first-value".into());
+        assert!(entry.metadata.last_rotated_at.is_none());
+        entry.rotate("sk-second-value".into());
+        assert!(entry.metadata.last_rotated_at.is_some() && !entry.is_expired(now));
+        assert_eq!(entry.metadata.provider.as_deref(), Some("openai"));
+    }
+}
@@ -1,10 +1,16 @@
 use std::collections::HashMap;
 use std::fs;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use serde::{Deserialize, Serialize};
-use super::crypto;
+use zeroize::Zeroizing;
+use super::backend::{BackendKind, SecretBackend};
+use super::crypto::{self, KdfParams};
+use super::entry::{SecretEntry, Secrets};
 
+/// Format 1: this whole struct, serialized and encrypted with the machine key.
+/// Still read so older files keep working; saving rewrites them as format 2.
 #[derive(Debug, Serialize, Deserialize)]
 struct SecretsFile {
     version: String,
@@ -12,10 +18,28 @@ struct SecretsFile {
     metadata: Metadata,
 }
 
+/// Format 2: metadata in the clear, so the KDF salt can be read before the
+/// key exists, and only the secrets map encrypted (base64 nonce || ciphertext).
+/// The map's values are `SecretEntry`s, or bare values if saved before
+/// secrets carried metadata.
+#[derive(Debug, Serialize, Deserialize)]
+struct SealedSecretsFile {
+    version: String,
+    metadata: Metadata,
+    secrets: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct Metadata {
     created_at: String,
     updated_at: String,
+    /// Set when the key is derived from a master passphrase rather than the machine ID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kdf: Option<KdfParams>,
+    /// Mixed into the machine key and replaced on each key rotation; absent
+    /// in files that were never rotated (base64)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_salt: Option<String>,
 }
 
 impl Default for SecretsFile {
@@ -27,6 +51,8 @@ impl Default for SecretsFile {
             metadata: Metadata {
                 created_at: now.clone(),
                 updated_at: now,
+                kdf: None,
+                key_salt: None,
             },
         }
     }
@@ -35,11 +61,17 @@ impl Default for SecretsFile {
 pub struct FileBackend {
     file_path: PathBuf,
     backup_path: PathBuf,
-    master_key: [u8; 32],
+    /// None while a passphrase-protected store is locked
+    master_key: Option<[u8; 32]>,
+    /// How `master_key` is derived from the passphrase; None in machine-ID mode
+    kdf: Option<KdfParams>,
+    /// Machine-ID mode's current key salt, if the key was ever rotated
+    key_salt: Option<String>,
 }
 
 impl FileBackend {
-    /// Creates a new FileBackend with the secrets file at ~/.sophia/secrets.enc
+    /// Creates a new FileBackend with the secrets file at ~/.sophia/secrets.enc.
+    /// A passphrase-protected file starts out locked.
     pub fn new() -> Result<Self, String> {
         // Get home directory
         let home = dirs::home_dir()
@@ -69,68 +101,107 @@ impl FileBackend {
         let file_path = sophia_dir.join("secrets.enc");
         let backup_path = sophia_dir.join("secrets.enc.bak");
         
-        // Derive master key
-        let master_key = crypto::derive_master_key()
-            .map_err(|e| format!("Failed to derive master key: {}", e))?;
-        
-        log::info!("FileBackend initialized with path: {:?}", file_path);
-        
+        let backend = Self::open(file_path, backup_path)?;
+        log::info!("FileBackend initialized with path: {:?}", backend.file_path);
+        Ok(backend)
+    }
+
+    /// Opens the secrets file at `file_path`, in whichever mode it was saved.
+    pub(super) fn open(file_path: PathBuf, backup_path: PathBuf) -> Result<Self, String> {
+        let (kdf, key_salt) = read_sealed(&file_path)
+            .map(|sealed| (sealed.metadata.kdf, sealed.metadata.key_salt))
+            .unwrap_or_default();
+        let master_key = if kdf.is_some() {
+            log::info!("Secrets file is passphrase-protected; starting locked");
+            None
+        } else {
+            Some(machine_key(key_salt.as_deref())?)
+        };
+
         Ok(FileBackend {
             file_path,
             backup_path,
             master_key,
+            kdf,
+            key_salt,
         })
     }
-    
+
+    /// Re-encrypts `secrets` under a new key. The file is replaced by the
+    /// same atomic rename as any save, so it is never half re-encrypted.
+    fn rekey(
+        &mut self,
+        master_key: Option<[u8; 32]>,
+        kdf: Option<KdfParams>,
+        key_salt: Option<String>,
+        secrets: &Secrets,
+    ) -> Result<(), String> {
+        let previous = (self.master_key.take(), self.kdf.take(), self.key_salt.take());
+        self.master_key = master_key;
+        self.kdf = kdf;
+        self.key_salt = key_salt;
+        if let Err(e) = self.save(secrets) {
+            (self.master_key, self.kdf, self.key_salt) = previous;
+            return Err(e);
+        }
+
+        // The backup still holds the old encryption; replace it so the old key
+        // can't open a copy of the secrets. The live file is already re-keyed,
+        // so a failure here only costs the backup, never the rekey itself
+        if let Err(e) = fs::copy(&self.file_path, &self.backup_path) {
+            log::warn!("Failed to replace backup after rekey: {}", e);
+            if let Err(e) = fs::remove_file(&self.backup_path) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    log::warn!("Failed to remove stale backup: {}", e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn key(&self) -> Result<&[u8; 32], String> {
+        self.master_key.as_ref().ok_or_else(|| "Secret store is locked".to_string())
+    }
+}
+
+impl SecretBackend for FileBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::File
+    }
+
     /// Loads secrets from encrypted file
-    pub fn load(&self) -> Result<HashMap<String, String>, String> {
+    fn load(&self) -> Result<Secrets, String> {
         if !self.file_path.exists() {
             log::info!("Secrets file does not exist, returning empty map");
-            return Ok(HashMap::new());
+            return Ok(Secrets::new());
         }
         
-        // Read encrypted file
-        let encrypted_data = fs::read(&self.file_path)
-            .map_err(|e| format!("Failed to read secrets file: {}", e))?;
+        let secrets = read_secrets(&self.file_path, self.key()?)
+            .inspect_err(|_| log::error!("Decryption failed, attempting backup..."))?;
         
-        // Decrypt
-        let plaintext = crypto::decrypt(&encrypted_data, &self.master_key)
-            .map_err(|e| {
-                log::error!("Decryption failed, attempting backup...");
-                format!("Failed to decrypt secrets: {}", e)
-            })?;
-        
-        // Deserialize JSON
-        let secrets_file: SecretsFile = serde_json::from_slice(&plaintext)
-            .map_err(|e| format!("Failed to parse secrets file: {}", e))?;
-        
-        log::info!("Loaded {} secrets from file", secrets_file.secrets.len());
-        Ok(secrets_file.secrets)
+        log::info!("Loaded {} secrets from file", secrets.len());
+        Ok(secrets)
     }
     
     /// Loads secrets from backup file (if main file is corrupted)
-    pub fn load_from_backup(&self) -> Result<HashMap<String, String>, String> {
+    fn load_from_backup(&self) -> Result<Secrets, String> {
         if !self.backup_path.exists() {
             return Err("Backup file does not exist".to_string());
         }
         
         log::warn!("Loading from backup file: {:?}", self.backup_path);
         
-        let encrypted_data = fs::read(&self.backup_path)
-            .map_err(|e| format!("Failed to read backup file: {}", e))?;
-        
-        let plaintext = crypto::decrypt(&encrypted_data, &self.master_key)
-            .map_err(|e| format!("Failed to decrypt backup: {}", e))?;
+        let secrets = read_secrets(&self.backup_path, self.key()?)
+            .map_err(|e| format!("Failed to load backup: {}", e))?;
         
-        let secrets_file: SecretsFile = serde_json::from_slice(&plaintext)
-            .map_err(|e| format!("Failed to parse backup file: {}", e))?;
-        
-        log::info!("Loaded {} secrets from backup", secrets_file.secrets.len());
-        Ok(secrets_file.secrets)
+        log::info!("Loaded {} secrets from backup", secrets.len());
+        Ok(secrets)
     }
     
     /// Saves secrets to encrypted file (atomic write)
-    pub fn save(&self, secrets: &HashMap<String, String>) -> Result<(), String> {
+    fn save(&self, secrets: &Secrets) -> Result<(), String> {
+        let master_key = self.key()?;
+
         // Create backup of existing file
         if self.file_path.exists() {
             fs::copy(&self.file_path, &self.backup_path)
@@ -138,51 +209,227 @@ impl FileBackend {
             log::debug!("Created backup at: {:?}", self.backup_path);
         }
         
-        // Create secrets file structure
-        let mut secrets_file = SecretsFile::default();
-        secrets_file.secrets = secrets.clone();
-        secrets_file.metadata.updated_at = chrono::Utc::now().to_rfc3339();
+        let mut metadata = SecretsFile::default().metadata;
+        metadata.kdf = self.kdf.clone();
+        metadata.key_salt = self.key_salt.clone();
+        write_sealed(&self.file_path, metadata, master_key, secrets)?;
         
-        // Serialize to JSON
-        let plaintext = serde_json::to_vec_pretty(&secrets_file)
-            .map_err(|e| format!("Failed to serialize secrets: {}", e))?;
-        
-        // Encrypt
-        let encrypted_data = crypto::encrypt(&plaintext, &self.master_key)
-            .map_err(|e| format!("Failed to encrypt secrets: {}", e))?;
-        
-        // Atomic write: write to temp file, then rename
-        let temp_path = self.file_path.with_extension("tmp");
-        
-        {
-            let mut file = fs::File::create(&temp_path)
-                .map_err(|e| format!("Failed to create temp file: {}", e))?;
-            
-            file.write_all(&encrypted_data)
-                .map_err(|e| format!("Failed to write temp file: {}", e))?;
-            
-            file.sync_all()
-                .map_err(|e| format!("Failed to sync temp file: {}", e))?;
+        log::info!("Saved {} secrets to encrypted file", secrets.len());
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<(), String> {
+        for path in [&self.file_path, &self.backup_path] {
+            if path.exists() {
+                fs::remove_file(path)
+                    .map_err(|e| format!("Failed to remove {:?}: {}", path, e))?;
+            }
         }
-        
-        // Set restrictive permissions before renaming
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
-            let mut perms = fs::metadata(&temp_path)
-                .map_err(|e| e.to_string())?
-                .permissions();
-            perms.set_mode(0o600); // Owner read/write only
-            fs::set_permissions(&temp_path, perms)
-                .map_err(|e| e.to_string())?;
+        log::info!("Removed the secrets file and its backup");
+        Ok(())
+    }
+
+    fn is_passphrase_protected(&self) -> bool {
+        self.kdf.is_some()
+    }
+
+    fn is_locked(&self) -> bool {
+        self.master_key.is_none()
+    }
+
+    /// Derives the key from `passphrase` and loads the secrets with it. The
+    /// key is only kept if it actually decrypts the file.
+    fn unlock(&mut self, passphrase: &str) -> Result<Secrets, String> {
+        let kdf = self.kdf.as_ref().ok_or("Secret store has no master passphrase")?;
+        let key = crypto::derive_passphrase_key(passphrase, kdf)
+            .map_err(|e| e.to_string())?;
+
+        let secrets = read_secrets(&self.file_path, &key)
+            .map_err(|e| wrong_key_as(e, "Incorrect master passphrase"))?;
+        self.master_key = Some(key);
+        log::info!("Secret store unlocked");
+        Ok(secrets)
+    }
+
+    /// Forgets the key until the next `unlock`. Machine-ID mode can't be locked.
+    fn lock(&mut self) {
+        if self.kdf.is_some() {
+            self.master_key = None;
+            log::info!("Secret store locked");
         }
+    }
+
+    /// Whether `passphrase` is the current master passphrase.
+    fn verify_passphrase(&self, passphrase: &str) -> bool {
+        self.kdf.as_ref()
+            .and_then(|kdf| crypto::derive_passphrase_key(passphrase, kdf).ok())
+            .is_some_and(|key| read_secrets(&self.file_path, &key).is_ok())
+    }
+
+    /// Re-encrypts `secrets` under a key derived from `passphrase` with a fresh
+    /// salt. Also how a machine-ID file is migrated to passphrase mode.
+    fn set_passphrase(&mut self, passphrase: &str, secrets: &Secrets) -> Result<(), String> {
+        let kdf = KdfParams::generate();
+        let key = crypto::derive_passphrase_key(passphrase, &kdf)
+            .map_err(|e| e.to_string())?;
+        self.rekey(Some(key), Some(kdf), None, secrets)?;
+        log::info!("Secrets re-encrypted with the master passphrase");
+        Ok(())
+    }
+
+    /// Goes back to a machine-ID key, with a fresh key salt.
+    fn remove_passphrase(&mut self, secrets: &Secrets) -> Result<(), String> {
+        let key_salt = crypto::generate_key_salt();
+        self.rekey(Some(machine_key(Some(&key_salt))?), None, Some(key_salt), secrets)?;
+        log::info!("Master passphrase removed; secrets encrypted with the machine key");
+        Ok(())
+    }
+
+    /// Re-encrypts `secrets` under a new key: a new salt for the passphrase,
+    /// or a new key salt mixed into the machine key.
+    fn rotate_master_key(&mut self, passphrase: Option<&str>, secrets: &Secrets) -> Result<(), String> {
+        if self.kdf.is_some() {
+            let passphrase = passphrase.ok_or("The master passphrase is needed to rotate its key")?;
+            return self.set_passphrase(passphrase, secrets);
+        }
+
+        let key_salt = crypto::generate_key_salt();
+        self.rekey(Some(machine_key(Some(&key_salt))?), None, Some(key_salt), secrets)?;
+        log::info!("Master key rotated; {} secrets re-encrypted", secrets.len());
+        Ok(())
+    }
+}
+
+fn machine_key(key_salt: Option<&str>) -> Result<[u8; 32], String> {
+    crypto::derive_rotated_master_key(key_salt)
+        .map_err(|e| format!("Failed to derive master key: {}", e))
+}
+
+/// Writes `secrets` to `path` as a recovery file: a format 2 secrets file
+/// keyed only by `passphrase`, so it opens on any machine, including one
+/// whose machine ID no longer matches the secrets file.
+pub fn export_recovery(path: &Path, passphrase: &str, secrets: &Secrets) -> Result<(), String> {
+    let kdf = KdfParams::generate();
+    let key = crypto::derive_passphrase_key(passphrase, &kdf)
+        .map_err(|e| e.to_string())?;
+
+    let mut metadata = SecretsFile::default().metadata;
+    metadata.kdf = Some(kdf);
+    write_sealed(path, metadata, &key, secrets)?;
+    log::info!("Exported {} secrets to a recovery file", secrets.len());
+    Ok(())
+}
+
+/// Reads the secrets from a recovery file written by `export_recovery`.
+pub fn import_recovery(path: &Path, passphrase: &str) -> Result<Secrets, String> {
+    if !path.exists() {
+        return Err(format!("Recovery file not found: {:?}", path));
+    }
+    let kdf = read_sealed(path)
+        .and_then(|sealed| sealed.metadata.kdf)
+        .ok_or("Not a secrets recovery file")?;
+    let key = crypto::derive_passphrase_key(passphrase, &kdf)
+        .map_err(|e| e.to_string())?;
+
+    read_secrets(path, &key)
+        .map_err(|e| wrong_key_as(e, "Incorrect recovery passphrase"))
+}
+
+/// Encrypts `secrets` and writes them to `path` as format 2 (atomic write).
+fn write_sealed(path: &Path, metadata: Metadata, master_key: &[u8; 32], secrets: &Secrets) -> Result<(), String> {
+    // Serialize the secrets to JSON
+    let plaintext = Zeroizing::new(serde_json::to_vec(secrets)
+        .map_err(|e| format!("Failed to serialize secrets: {}", e))?);
+    
+    // Encrypt
+    let encrypted_data = crypto::encrypt(&plaintext, master_key)
+        .map_err(|e| format!("Failed to encrypt secrets: {}", e))?;
+    
+    // Create secrets file structure
+    let sealed = SealedSecretsFile {
+        version: "2".to_string(),
+        metadata,
+        secrets: BASE64.encode(&encrypted_data),
+    };
+    let contents = serde_json::to_vec_pretty(&sealed)
+        .map_err(|e| format!("Failed to serialize secrets file: {}", e))?;
+    
+    // Atomic write: write to temp file, then rename
+    let temp_path = path.with_extension("tmp");
+    
+    {
+        let mut file = fs::File::create(&temp_path)
+            .map_err(|e| format!("Failed to create temp file: {}", e))?;
         
-        // Atomic rename
-        fs::rename(&temp_path, &self.file_path)
-            .map_err(|e| format!("Failed to rename temp file: {}", e))?;
+        file.write_all(&contents)
+            .map_err(|e| format!("Failed to write temp file: {}", e))?;
         
-        log::info!("Saved {} secrets to encrypted file", secrets.len());
-        Ok(())
+        file.sync_all()
+            .map_err(|e| format!("Failed to sync temp file: {}", e))?;
+    }
+    
+    // Set restrictive permissions before renaming
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut perms = fs::metadata(&temp_path)
+            .map_err(|e| e.to_string())?
+            .permissions();
+        perms.set_mode(0o600); // Owner read/write only
+        fs::set_permissions(&temp_path, perms)
+            .map_err(|e| e.to_string())?;
+    }
+    
+    // Atomic rename
+    fs::rename(&temp_path, path)
+        .map_err(|e| format!("Failed to rename temp file: {}", e))?;
+    
+    Ok(())
+}
+
+/// The file as format 2, or None if it is missing or in format 1.
+fn read_sealed(path: &Path) -> Option<SealedSecretsFile> {
+    fs::read(path).ok()
+        .and_then(|data| serde_json::from_slice(&data).ok())
+}
+
+/// Prefix of the error `read_secrets` returns when the key doesn't decrypt the file.
+const DECRYPT_FAILED: &str = "Failed to decrypt secrets";
+
+/// Replaces a `read_secrets` decryption failure, which is what a wrong key
+/// causes, with `message`. Read and parse errors are kept as they are.
+fn wrong_key_as(error: String, message: &str) -> String {
+    if error.starts_with(DECRYPT_FAILED) {
+        message.to_string()
+    } else {
+        error
+    }
+}
+
+/// Decrypts a secrets file in either format.
+fn read_secrets(path: &Path, master_key: &[u8; 32]) -> Result<Secrets, String> {
+    let data = fs::read(path)
+        .map_err(|e| format!("Failed to read secrets file: {}", e))?;
+
+    match serde_json::from_slice::<SealedSecretsFile>(&data) {
+        Ok(sealed) => {
+            let encrypted_data = BASE64.decode(&sealed.secrets)
+                .map_err(|e| format!("Failed to decode secrets: {}", e))?;
+            let plaintext = Zeroizing::new(crypto::decrypt(&encrypted_data, master_key)
+                .map_err(|e| format!("{}: {}", DECRYPT_FAILED, e))?);
+            serde_json::from_slice(&plaintext)
+                .map_err(|e| format!("Failed to parse secrets: {}", e))
+        },
+        // Format 1: the whole file is ciphertext
+        Err(_) => {
+            let plaintext = Zeroizing::new(crypto::decrypt(&data, master_key)
+                .map_err(|e| format!("{}: {}", DECRYPT_FAILED, e))?);
+            let secrets_file: SecretsFile = serde_json::from_slice(&plaintext)
+                .map_err(|e| format!("Failed to parse secrets file: {}", e))?;
+            Ok(secrets_file.secrets.into_iter()
+                .map(|(key, value)| (key, SecretEntry::from(value)))
+                .collect())
+        },
     }
 }
 
@@ -201,13 +448,15 @@ mod tests {
         let backend = FileBackend {
             file_path,
             backup_path,
-            master_key,
+            master_key: Some(master_key),
+            kdf: None,
+            key_salt: None,
         };
         
         // Create test secrets
-        let mut secrets = HashMap::new();
-        secrets.insert("key1".to_string(), "value1".to_string());
-        secrets.insert("key2".to_string(), "value2".to_string());
+        let mut secrets = Secrets::new();
+        secrets.insert("key1".to_string(), SecretEntry::new("value1", None));
+        secrets.insert("key2".to_string(), SecretEntry::new("value2", None));
         
         // Save
         backend.save(&secrets).unwrap();
@@ -216,8 +465,8 @@ mod tests {
         let loaded = backend.load().unwrap();
         
         assert_eq!(loaded.len(), 2);
-        assert_eq!(loaded.get("key1").unwrap(), "value1");
-        assert_eq!(loaded.get("key2").unwrap(), "value2");
+        assert_eq!(loaded.get("key1").unwrap().value.expose_secret(), "value1");
+        assert_eq!(loaded.get("key2").unwrap().value.expose_secret(), "value2");
     }
 
     #[test]
@@ -230,7 +479,9 @@ mod tests {
         let backend = FileBackend {
             file_path,
             backup_path,
-            master_key,
+            master_key: Some(master_key),
+            kdf: None,
+            key_salt: None,
         };
         
         // Should return empty map, not error
@@ -248,17 +499,19 @@ mod tests {
         let backend = FileBackend {
             file_path: file_path.clone(),
             backup_path: backup_path.clone(),
-            master_key,
+            master_key: Some(master_key),
+            kdf: None,
+            key_salt: None,
         };
         
         // Save first version
-        let mut secrets1 = HashMap::new();
-        secrets1.insert("key1".to_string(), "value1".to_string());
+        let mut secrets1 = Secrets::new();
+        secrets1.insert("key1".to_string(), SecretEntry::new("value1", None));
         backend.save(&secrets1).unwrap();
         
         // Save second version (should create backup)
-        let mut secrets2 = HashMap::new();
-        secrets2.insert("key2".to_string(), "value2".to_string());
+        let mut secrets2 = Secrets::new();
+        secrets2.insert("key2".to_string(), SecretEntry::new("value2", None));
         backend.save(&secrets2).unwrap();
         
         // Backup should exist
@@ -266,7 +519,7 @@ mod tests {
         
         // Load from backup should give first version
         let backup_secrets = backend.load_from_backup().unwrap();
-        assert_eq!(backup_secrets.get("key1").unwrap(), "value1");
+        assert_eq!(backup_secrets.get("key1").unwrap().value.expose_secret(), "value1");
     }
 
     #[test]
@@ -283,11 +536,13 @@ mod tests {
             let backend = FileBackend {
                 file_path: file_path.clone(),
                 backup_path,
-                master_key,
+                master_key: Some(master_key),
+                kdf: None,
+                key_salt: None,
             };
             
-            let mut secrets = HashMap::new();
-            secrets.insert("key1".to_string(), "value1".to_string());
+            let mut secrets = Secrets::new();
+            secrets.insert("key1".to_string(), SecretEntry::new("value1", None));
             backend.save(&secrets).unwrap();
             
             // Check permissions
@@ -299,4 +554,141 @@ mod tests {
             assert_eq!(mode & 0o777, 0o600);
         }
     }
+
+    #[test]
+    fn test_format_1_is_read_and_migrated() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("secrets.enc");
+        let master_key = crypto::derive_master_key().unwrap();
+
+        // As written before the metadata moved out of the ciphertext
+        let mut legacy = SecretsFile::default();
+        legacy.secrets.insert("key1".to_string(), "value1".to_string());
+        let plaintext = serde_json::to_vec_pretty(&legacy).unwrap();
+        fs::write(&file_path, crypto::encrypt(&plaintext, &master_key).unwrap()).unwrap();
+
+        let backend = FileBackend::open(file_path.clone(), dir.path().join("secrets.enc.bak")).unwrap();
+        assert!(!backend.is_passphrase_protected());
+        let loaded = backend.load().unwrap();
+        assert_eq!(loaded.get("key1").unwrap().value.expose_secret(), "value1");
+
+        backend.save(&loaded).unwrap();
+        let sealed = read_sealed(&file_path).unwrap();
+        assert_eq!(sealed.version, "2");
+        assert!(sealed.metadata.kdf.is_none());
+        assert_eq!(backend.load().unwrap(), loaded);
+    }
+
+    #[test]
+    fn test_format_2_without_metadata_is_read() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("secrets.enc");
+        let master_key = crypto::derive_master_key().unwrap();
+
+        // As written before secrets carried metadata
+        let encrypted = crypto::encrypt(br#"{"key1":"value1"}"#, &master_key).unwrap();
+        let sealed = SealedSecretsFile {
+            version: "2".to_string(),
+            metadata: SecretsFile::default().metadata,
+            secrets: BASE64.encode(encrypted),
+        };
+        fs::write(&file_path, serde_json::to_vec(&sealed).unwrap()).unwrap();
+
+        let backend = FileBackend::open(file_path, dir.path().join("secrets.enc.bak")).unwrap();
+        assert_eq!(backend.load().unwrap()["key1"], SecretEntry::from("value1".to_string()));
+    }
+
+    #[test]
+    fn test_passphrase_mode() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("secrets.enc");
+        let backup_path = dir.path().join("secrets.enc.bak");
+        let mut backend = FileBackend::open(file_path.clone(), backup_path.clone()).unwrap();
+
+        let mut secrets = Secrets::new();
+        secrets.insert("key1".to_string(), SecretEntry::new("value1", None));
+        backend.save(&secrets).unwrap();
+        backend.set_passphrase("correct horse battery staple", &secrets).unwrap();
+
+        // The salt is readable without the key; the secrets are not
+        let kdf = read_sealed(&file_path).unwrap().metadata.kdf.unwrap();
+        assert_eq!(kdf.algorithm, "argon2id");
+        let machine_key = crypto::derive_master_key().unwrap();
+        assert!(read_secrets(&file_path, &machine_key).is_err());
+        assert!(read_secrets(&backup_path, &machine_key).is_err());
+
+        let mut reopened = FileBackend::open(file_path.clone(), backup_path.clone()).unwrap();
+        assert!(reopened.is_passphrase_protected() && reopened.is_locked());
+        assert_eq!(reopened.load().unwrap_err(), "Secret store is locked");
+        assert!(reopened.save(&secrets).is_err());
+
+        assert_eq!(reopened.unlock("wrong horse").unwrap_err(), "Incorrect master passphrase");
+        assert!(reopened.is_locked());
+        assert_eq!(reopened.unlock("correct horse battery staple").unwrap(), secrets);
+        assert!(reopened.verify_passphrase("correct horse battery staple"));
+
+        reopened.lock();
+        assert!(reopened.is_locked());
+
+        // A file that can't be read is not a wrong passphrase
+        let contents = fs::read(&file_path).unwrap();
+        fs::remove_file(&file_path).unwrap();
+        assert!(reopened.unlock("correct horse battery staple").unwrap_err().starts_with("Failed to read secrets file"));
+        fs::write(&file_path, contents).unwrap();
+
+        reopened.unlock("correct horse battery staple").unwrap();
+        reopened.remove_passphrase(&secrets).unwrap();
+        let reopened = FileBackend::open(file_path, backup_path).unwrap();
+        assert!(!reopened.is_passphrase_protected() && !reopened.is_locked());
+        assert_eq!(reopened.load().unwrap(), secrets);
+    }
+
+    #[test]
+    fn test_rotate_master_key() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("secrets.enc");
+        let backup_path = dir.path().join("secrets.enc.bak");
+        let mut backend = FileBackend::open(file_path.clone(), backup_path.clone()).unwrap();
+
+        let mut secrets = Secrets::new();
+        secrets.insert("key1".to_string(), SecretEntry::new("value1", None));
+        backend.save(&secrets).unwrap();
+        backend.save(&secrets).unwrap();
+        let old_key = crypto::derive_master_key().unwrap();
+
+        backend.rotate_master_key(None, &secrets).unwrap();
+        let key_salt = read_sealed(&file_path).unwrap().metadata.key_salt.unwrap();
+        assert_eq!(backend.load().unwrap(), secrets);
+
+        // Neither the file nor its backup opens with the old key
+        assert!(read_secrets(&file_path, &old_key).is_err());
+        assert!(read_secrets(&backup_path, &old_key).is_err());
+
+        backend.rotate_master_key(None, &secrets).unwrap();
+        let reopened = FileBackend::open(file_path.clone(), backup_path).unwrap();
+        assert_ne!(reopened.key_salt.as_deref(), Some(key_salt.as_str()));
+        assert_eq!(reopened.load().unwrap(), secrets);
+        assert!(!dir.path().join("secrets.tmp").exists());
+    }
+
+    #[test]
+    fn test_recovery_export_and_import() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("sophia-recovery.json");
+
+        let mut secrets = Secrets::new();
+        secrets.insert("key1".to_string(), SecretEntry::new("value1", None));
+        export_recovery(&path, "recovery passphrase", &secrets).unwrap();
+
+        // Keyed by the passphrase alone, not this machine
+        let machine_key = crypto::derive_master_key().unwrap();
+        assert!(read_secrets(&path, &machine_key).is_err());
+        assert_eq!(import_recovery(&path, "recovery passphrase").unwrap(), secrets);
+        assert_eq!(import_recovery(&path, "wrong passphrase").unwrap_err(), "Incorrect recovery passphrase");
+
+        // A machine-ID secrets file is not a recovery file
+        let backend = FileBackend::open(dir.path().join("secrets.enc"), dir.path().join("secrets.enc.bak")).unwrap();
+        backend.save(&secrets).unwrap();
+        assert_eq!(import_recovery(&dir.path().join("secrets.enc"), "recovery passphrase").unwrap_err(), "Not a secrets recovery file");
+    }
 }
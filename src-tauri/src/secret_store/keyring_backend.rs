@@ -0,0 +1,232 @@
+use std::collections::BTreeSet;
+use keyring::credential::{CredentialBuilder, CredentialPersistence};
+use keyring::Entry;
+use zeroize::Zeroizing;
+use super::backend::{BackendKind, SecretBackend};
+use super::entry::{SecretEntry, Secrets};
+
+/// Keyring user under which the list of stored secret names is kept, since
+/// keyrings can't be enumerated portably.
+const INDEX_USER: &str = "__sophia_secret_index__";
+
+/// Stores each secret as its own OS keyring item, under the app's service
+/// name with the secret's key as the user. The item holds the `SecretEntry`
+/// as JSON; items saved before metadata was kept hold the bare value.
+pub struct KeyringBackend {
+    service: String,
+    credentials: Box<CredentialBuilder>,
+}
+
+impl KeyringBackend {
+    /// The platform keyring: Secret Service on Linux, Keychain on macOS,
+    /// Credential Manager on Windows.
+    pub fn new(service: &str) -> Result<Self, String> {
+        Self::with_credentials(service, keyring::default::default_credential_builder())
+    }
+
+    /// A keyring backed by `credentials`. Fails unless the store persists
+    /// across runs and answers a read, so a missing or locked keyring falls
+    /// back to another backend up front rather than on the first save.
+    pub fn with_credentials(service: &str, credentials: Box<CredentialBuilder>) -> Result<Self, String> {
+        if matches!(credentials.persistence(), CredentialPersistence::EntryOnly | CredentialPersistence::ProcessOnly) {
+            return Err("No persistent OS keyring on this platform".to_string());
+        }
+
+        let backend = KeyringBackend { service: service.to_string(), credentials };
+        backend.index()?;
+        log::info!("Using the OS keyring for secrets");
+        Ok(backend)
+    }
+
+    fn entry(&self, user: &str) -> Result<Entry, String> {
+        self.credentials.build(None, &self.service, user)
+            .map(Entry::new_with_credential)
+            .map_err(|e| format!("Keyring error: {}", e))
+    }
+
+    /// `user`'s item, or None if it doesn't exist.
+    fn get(&self, user: &str) -> Result<Option<String>, String> {
+        match self.entry(user)?.get_password() {
+            Ok(value) => Ok(Some(value)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(format!("Keyring error: {}", e)),
+        }
+    }
+
+    fn set(&self, user: &str, value: &str) -> Result<(), String> {
+        self.entry(user)?.set_password(value)
+            .map_err(|e| format!("Keyring error: {}", e))
+    }
+
+    fn delete(&self, user: &str) -> Result<(), String> {
+        match self.entry(user)?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(format!("Keyring error: {}", e)),
+        }
+    }
+
+    fn index(&self) -> Result<BTreeSet<String>, String> {
+        match self.get(INDEX_USER)? {
+            Some(index) => serde_json::from_str(&index).map_err(|e| format!("Unreadable keyring index: {}", e)),
+            None => Ok(BTreeSet::new()),
+        }
+    }
+}
+
+impl SecretBackend for KeyringBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Keyring
+    }
+
+    fn load(&self) -> Result<Secrets, String> {
+        let mut secrets = Secrets::new();
+        for key in self.index()? {
+            // An item deleted outside the app is simply gone
+            if let Some(item) = self.get(&key)? {
+                let item = Zeroizing::new(item);
+                let entry = serde_json::from_str(&item).unwrap_or_else(|_| SecretEntry::from(item.to_string()));
+                secrets.insert(key, entry);
+            }
+        }
+        log::info!("Loaded {} secrets from the OS keyring", secrets.len());
+        Ok(secrets)
+    }
+
+    fn save(&self, secrets: &Secrets) -> Result<(), String> {
+        let previous = self.index()?;
+        for (key, entry) in secrets {
+            let item = Zeroizing::new(serde_json::to_string(entry).map_err(|e| e.to_string())?);
+            self.set(key, &item)?;
+        }
+
+        // Write the index before deleting, so a failure leaves items orphaned
+        // rather than listed but missing
+        let index: BTreeSet<&String> = secrets.keys().collect();
+        self.set(INDEX_USER, &serde_json::to_string(&index).map_err(|e| e.to_string())?)?;
+        for key in previous.iter().filter(|key| !secrets.contains_key(*key)) {
+            self.delete(key)?;
+        }
+
+        log::info!("Saved {} secrets to the OS keyring", secrets.len());
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<(), String> {
+        for key in self.index()? {
+            self.delete(&key)?;
+        }
+        self.delete(INDEX_USER)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use keyring::credential::{Credential, CredentialApi, CredentialBuilderApi};
+    use std::any::Any;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    type Items = Arc<Mutex<HashMap<(String, String), Vec<u8>>>>;
+
+    /// A Secret Service stand-in: items persist across entries, keyed by
+    /// service and user, and the whole service can be made unreachable.
+    #[derive(Debug, Default, Clone)]
+    pub(crate) struct MockSecretService {
+        pub(crate) items: Items,
+        pub(crate) unavailable: bool,
+    }
+
+    #[derive(Debug)]
+    struct MockItem {
+        items: Items,
+        id: (String, String),
+        unavailable: bool,
+    }
+
+    impl MockItem {
+        fn check(&self) -> keyring::Result<()> {
+            if self.unavailable {
+                return Err(keyring::Error::NoStorageAccess("org.freedesktop.secrets not provided".into()));
+            }
+            Ok(())
+        }
+    }
+
+    impl CredentialApi for MockItem {
+        fn set_secret(&self, secret: &[u8]) -> keyring::Result<()> {
+            self.check()?;
+            self.items.lock().unwrap().insert(self.id.clone(), secret.to_vec());
+            Ok(())
+        }
+
+        fn get_secret(&self) -> keyring::Result<Vec<u8>> {
+            self.check()?;
+            self.items.lock().unwrap().get(&self.id).cloned().ok_or(keyring::Error::NoEntry)
+        }
+
+        fn delete_credential(&self) -> keyring::Result<()> {
+            self.check()?;
+            self.items.lock().unwrap().remove(&self.id).map(|_| ()).ok_or(keyring::Error::NoEntry)
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    impl CredentialBuilderApi for MockSecretService {
+        fn build(&self, _target: Option<&str>, service: &str, user: &str) -> keyring::Result<Box<Credential>> {
+            Ok(Box::new(MockItem {
+                items: self.items.clone(),
+                id: (service.to_string(), user.to_string()),
+                unavailable: self.unavailable,
+            }))
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let service = MockSecretService::default();
+        let backend = KeyringBackend::with_credentials("sophia", Box::new(service.clone())).unwrap();
+        assert!(backend.load().unwrap().is_empty());
+
+        let mut secrets = Secrets::new();
+        secrets.insert("openai_api_key".to_string(), SecretEntry::new("sk-keyring-test", Some("openai")));
+        secrets.insert("gemini_api_key".to_string(), SecretEntry::new("AIza-keyring-test", Some("gemini")));
+        backend.save(&secrets).unwrap();
+
+        // A second backend on the same service sees the same items
+        let reopened = KeyringBackend::with_credentials("sophia", Box::new(service.clone())).unwrap();
+        assert_eq!(reopened.load().unwrap(), secrets);
+
+        secrets.remove("gemini_api_key");
+        reopened.save(&secrets).unwrap();
+        assert_eq!(backend.load().unwrap(), secrets);
+        assert!(!service.items.lock().unwrap().contains_key(&("sophia".to_string(), "gemini_api_key".to_string())));
+
+        backend.clear().unwrap();
+        assert!(service.items.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_items_saved_before_metadata_are_read() {
+        let backend = KeyringBackend::with_credentials("sophia", Box::new(MockSecretService::default())).unwrap();
+        backend.set("openai_api_key", "sk-bare-keyring-value").unwrap();
+        backend.set(INDEX_USER, r#"["openai_api_key"]"#).unwrap();
+        assert_eq!(backend.load().unwrap()["openai_api_key"], SecretEntry::from("sk-bare-keyring-value".to_string()));
+    }
+
+    #[test]
+    fn test_unusable_keyrings_are_rejected() {
+        let unavailable = MockSecretService { unavailable: true, ..Default::default() };
+        assert!(KeyringBackend::with_credentials("sophia", Box::new(unavailable)).is_err());
+
+        // keyring's own mock forgets everything once the entry is dropped
+        assert!(KeyringBackend::with_credentials("sophia", keyring::mock::default_credential_builder()).is_err());
+    }
+}
@@ -0,0 +1,45 @@
+use std::sync::Mutex;
+use super::backend::{BackendKind, SecretBackend};
+use super::entry::Secrets;
+
+/// Keeps secrets only for the life of the process. The last resort when
+/// nothing persistent is available, and the backend tests run against.
+#[derive(Default)]
+pub struct MemoryBackend {
+    secrets: Mutex<Secrets>,
+}
+
+impl SecretBackend for MemoryBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Memory
+    }
+
+    fn load(&self) -> Result<Secrets, String> {
+        Ok(self.secrets.lock().map_err(|e| e.to_string())?.clone())
+    }
+
+    fn save(&self, secrets: &Secrets) -> Result<(), String> {
+        *self.secrets.lock().map_err(|e| e.to_string())? = secrets.clone();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret_store::entry::SecretEntry;
+
+    #[test]
+    fn test_save_and_load() {
+        let backend = MemoryBackend::default();
+        assert!(backend.load().unwrap().is_empty());
+
+        let mut secrets = Secrets::new();
+        secrets.insert("key1".to_string(), SecretEntry::new("value1", None));
+        backend.save(&secrets).unwrap();
+        assert_eq!(backend.load().unwrap(), secrets);
+
+        backend.clear().unwrap();
+        assert!(backend.load().unwrap().is_empty());
+    }
+}
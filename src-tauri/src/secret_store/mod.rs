@@ -1,5 +1,13 @@
 pub mod store;
+pub mod backend;
 pub mod crypto;
+pub mod entry;
 pub mod file_backend;
+pub mod keyring_backend;
+pub mod memory_backend;
+pub mod secret;
 
-pub use store::SecretStore;
+pub use backend::{open_backend, BackendKind, SecretBackend};
+pub use entry::{SecretEntry, SecretInfo, SecretMetadata};
+pub use secret::SecretString;
+pub use store::{SecretStore, SecretStoreStatus};
@@ -0,0 +1,85 @@
+use crate::redact::REDACTED;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::sync::Arc;
+use zeroize::Zeroizing;
+
+/// A secret value such as an API key. Clones share one buffer instead of
+/// copying the secret, and the buffer is zeroized once the last clone drops.
+/// `Debug` never shows the value; `expose_secret` is only for the moment
+/// the value is actually needed, e.g. signing a request.
+#[derive(Clone, Default)]
+pub struct SecretString(Arc<Zeroizing<String>>);
+
+impl SecretString {
+    pub fn new(value: String) -> Self {
+        SecretString(Arc::new(Zeroizing::new(value)))
+    }
+
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(value: String) -> Self {
+        SecretString::new(value)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(value: &str) -> Self {
+        SecretString::new(value.to_string())
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SecretString").field(&REDACTED).finish()
+    }
+}
+
+impl PartialEq for SecretString {
+    fn eq(&self, other: &Self) -> bool {
+        self.expose_secret() == other.expose_secret()
+    }
+}
+
+impl Eq for SecretString {}
+
+/// Written out only by secret backends, which store the value itself.
+impl Serialize for SecretString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.expose_secret())
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(SecretString::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_is_redacted_and_clones_share() {
+        let secret = SecretString::from("sk-wrapper-test-value");
+        assert_eq!(format!("{:?}", secret), "SecretString(\"[REDACTED]\")");
+        assert_eq!(format!("{:?}", Some(secret.clone())), "Some(SecretString(\"[REDACTED]\"))");
+
+        let clone = secret.clone();
+        assert!(std::ptr::eq(clone.expose_secret(), secret.expose_secret()));
+        assert_eq!(clone, secret);
+
+        let json = serde_json::to_string(&secret).unwrap();
+        assert_eq!(serde_json::from_str::<SecretString>(&json).unwrap(), secret);
+        assert!(SecretString::default().is_empty());
+    }
+}
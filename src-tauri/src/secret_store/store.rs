@@ -1,99 +1,520 @@
-use std::collections::HashMap;
-use std::sync::Mutex;
-use super::file_backend::FileBackend;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use super::backend::{open_preferred, BackendKind, SecretBackend};
+use super::entry::{SecretEntry, SecretInfo, Secrets};
+use super::file_backend;
+use super::secret::SecretString;
+use crate::redact::register_secret;
+use crate::runtime::audit::AuditLogger;
+
+/// Idle time after which a passphrase-protected store locks itself.
+pub const DEFAULT_AUTO_LOCK: Duration = Duration::from_secs(15 * 60);
+
+/// `last_used_at` is only persisted when it moves by at least this much, so
+/// reading a key doesn't rewrite the backend on every request.
+const LAST_USED_RESOLUTION_MINUTES: i64 = 60;
 
 pub struct SecretStore {
     _service: String,
-    // In-memory cache for fast access
-    cache: Mutex<HashMap<String, String>>,
-    // Persistent file backend
-    backend: FileBackend,
+    // In-memory cache for fast access; empty while locked
+    cache: Mutex<Secrets>,
+    // Persistent backend
+    backend: Mutex<Box<dyn SecretBackend>>,
+    session: Mutex<Session>,
+    audit: Option<Arc<AuditLogger>>,
+}
+
+/// What the settings panel shows about the store.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SecretStoreStatus {
+    pub backend: BackendKind,
+    pub passphrase_protected: bool,
+    pub locked: bool,
+    pub auto_lock_minutes: Option<u64>,
+}
+
+/// Auto-lock bookkeeping.
+struct Session {
+    auto_lock_after: Option<Duration>,
+    last_used: Instant,
 }
 
 impl SecretStore {
+    /// A store on the encrypted file backend.
     pub fn new(service: &str) -> Self {
-        log::info!("Initializing SecretStore with persistent file storage");
-        
-        // Initialize file backend
-        let backend = match FileBackend::new() {
-            Ok(b) => b,
-            Err(e) => {
-                log::error!("Failed to initialize file backend: {}", e);
-                panic!("Cannot initialize SecretStore: {}", e);
-            }
-        };
-        
-        // Load existing secrets from file
-        let cache = match backend.load() {
+        Self::open(service, BackendKind::File)
+    }
+
+    /// A store on the `preferred` backend, or the next one in its fallback
+    /// chain if the platform can't provide it.
+    pub fn open(service: &str, preferred: BackendKind) -> Self {
+        log::info!("Initializing SecretStore with persistent storage");
+        Self::from_backend(service, open_preferred(preferred, service))
+    }
+
+    /// A store on a fresh memory backend, so tests never touch the real
+    /// secrets file.
+    #[cfg(test)]
+    pub(crate) fn in_memory(service: &str) -> Self {
+        Self::from_backend(service, Box::new(super::memory_backend::MemoryBackend::default()))
+    }
+
+    fn load_secrets(backend: &dyn SecretBackend) -> Secrets {
+        match backend.load() {
             Ok(secrets) => {
                 log::info!("Loaded {} secrets from persistent storage", secrets.len());
-                Mutex::new(secrets)
+                secrets
             },
             Err(e) => {
-                log::warn!("Failed to load secrets from file: {}", e);
+                log::warn!("Failed to load secrets from {} backend: {}", backend.kind().as_str(), e);
                 log::info!("Attempting to load from backup...");
                 
                 // Try backup
                 match backend.load_from_backup() {
                     Ok(secrets) => {
                         log::info!("Loaded {} secrets from backup", secrets.len());
-                        Mutex::new(secrets)
+                        secrets
                     },
                     Err(backup_err) => {
                         log::warn!("Failed to load from backup: {}", backup_err);
                         log::info!("Starting with empty secret store");
-                        Mutex::new(HashMap::new())
+                        Secrets::new()
                     }
                 }
             }
+        }
+    }
+
+    fn from_backend(service: &str, backend: Box<dyn SecretBackend>) -> Self {
+        // Load existing secrets; a locked store has none until unlocked
+        let secrets = if backend.is_locked() {
+            log::info!("Secret store is locked until the master passphrase is entered");
+            Secrets::new()
+        } else {
+            Self::load_secrets(backend.as_ref())
         };
-        
+
+        // Scrub stored keys from logs and errors from the start
+        secrets.values().for_each(|entry| register_secret(entry.value.expose_secret()));
+
         SecretStore {
             _service: service.to_string(),
-            cache,
-            backend,
+            cache: Mutex::new(secrets),
+            backend: Mutex::new(backend),
+            session: Mutex::new(Session { auto_lock_after: Some(DEFAULT_AUTO_LOCK), last_used: Instant::now() }),
+            audit: None,
+        }
+    }
+
+    /// Records secret accesses (never values) to `audit`.
+    pub fn with_audit_logger(mut self, audit: Arc<AuditLogger>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    fn audit(&self, event: &str, context: serde_json::Value) {
+        if let Some(audit) = &self.audit {
+            audit.log("INFO", "secret_store", event, context);
+        }
+    }
+
+    pub fn backend_kind(&self) -> Option<BackendKind> {
+        self.backend.lock().ok().map(|b| b.kind())
+    }
+
+    /// Moves every secret into `target`, which then replaces the current
+    /// backend. The old backend is only cleared once `target` reads the
+    /// secrets back, so a failed move loses nothing. Returns how many moved.
+    pub fn migrate_to(&self, target: Box<dyn SecretBackend>) -> Result<usize, String> {
+        let cache = self.unlocked_cache()?;
+        let mut backend = self.backend.lock().map_err(|e| e.to_string())?;
+        let (from, to) = (backend.kind(), target.kind());
+        if from == to {
+            return Err(format!("Secrets are already in the {} backend", to.as_str()));
+        }
+
+        target.save(&cache)?;
+        if target.load()? != *cache {
+            return Err(format!("Secrets did not read back from the {} backend; nothing was moved", to.as_str()));
+        }
+        if let Err(e) = backend.clear() {
+            log::warn!("Secrets copied, but the {} backend could not be cleared: {}", from.as_str(), e);
+        }
+        *backend = target;
+
+        log::info!("Moved {} secrets from the {} backend to {}", cache.len(), from.as_str(), to.as_str());
+        Ok(cache.len())
+    }
+
+    pub fn status(&self) -> SecretStoreStatus {
+        SecretStoreStatus {
+            backend: self.backend_kind().unwrap_or(BackendKind::Memory),
+            locked: self.is_locked(),
+            passphrase_protected: self.is_passphrase_protected(),
+            auto_lock_minutes: self.auto_lock_after().map(|d| d.as_secs() / 60),
+        }
+    }
+
+    pub fn is_passphrase_protected(&self) -> bool {
+        self.backend.lock().map(|b| b.is_passphrase_protected()).unwrap_or(false)
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.lock_if_idle();
+        self.backend.lock().map(|b| b.is_locked()).unwrap_or(true)
+    }
+
+    pub fn unlock(&self, passphrase: &str) -> Result<(), String> {
+        let mut cache = self.cache.lock().map_err(|e| e.to_string())?;
+        let mut backend = self.backend.lock().map_err(|e| e.to_string())?;
+        let secrets = backend.unlock(passphrase)?;
+
+        secrets.values().for_each(|entry| register_secret(entry.value.expose_secret()));
+        *cache = secrets;
+        self.touch();
+        Ok(())
+    }
+
+    /// Drops the key and every cached secret. No-op without a master passphrase.
+    pub fn lock(&self) -> Result<(), String> {
+        let mut cache = self.cache.lock().map_err(|e| e.to_string())?;
+        let mut backend = self.backend.lock().map_err(|e| e.to_string())?;
+        if backend.is_passphrase_protected() {
+            backend.lock();
+            cache.clear();
+        }
+        Ok(())
+    }
+
+    /// Sets or changes the master passphrase. Changing it requires the current one.
+    pub fn set_passphrase(&self, current: Option<&str>, passphrase: &str) -> Result<(), String> {
+        let cache = self.unlocked_cache()?;
+        let mut backend = self.backend.lock().map_err(|e| e.to_string())?;
+        if backend.is_passphrase_protected() && !current.is_some_and(|c| backend.verify_passphrase(c)) {
+            return Err("Incorrect master passphrase".to_string());
+        }
+        backend.set_passphrase(passphrase, &cache)
+    }
+
+    /// Goes back to machine-ID encryption.
+    pub fn remove_passphrase(&self, current: &str) -> Result<(), String> {
+        let cache = self.unlocked_cache()?;
+        let mut backend = self.backend.lock().map_err(|e| e.to_string())?;
+        if !backend.verify_passphrase(current) {
+            return Err("Incorrect master passphrase".to_string());
+        }
+        backend.remove_passphrase(&cache)
+    }
+
+    /// Re-encrypts every secret under a new master key. A passphrase-protected
+    /// store needs the current passphrase, which stays the same.
+    pub fn rotate_master_key(&self, passphrase: Option<&str>) -> Result<(), String> {
+        let cache = self.unlocked_cache()?;
+        let mut backend = self.backend.lock().map_err(|e| e.to_string())?;
+        if backend.is_passphrase_protected() && !passphrase.is_some_and(|p| backend.verify_passphrase(p)) {
+            return Err("Incorrect master passphrase".to_string());
+        }
+        backend.rotate_master_key(passphrase, &cache)
+    }
+
+    /// Writes every secret to a recovery file at `path`, encrypted with
+    /// `passphrase` only, for import on another machine. Returns how many.
+    pub fn export_recovery(&self, path: &Path, passphrase: &str) -> Result<usize, String> {
+        let cache = self.unlocked_cache()?;
+        file_backend::export_recovery(path, passphrase, &cache)?;
+        Ok(cache.len())
+    }
+
+    /// Adds the secrets from a recovery file, replacing any stored under the
+    /// same keys. Returns how many were imported.
+    pub fn import_recovery(&self, path: &Path, passphrase: &str) -> Result<usize, String> {
+        let secrets = file_backend::import_recovery(path, passphrase)?;
+        secrets.values().for_each(|entry| register_secret(entry.value.expose_secret()));
+
+        let mut cache = self.unlocked_cache()?;
+        let count = secrets.len();
+        cache.extend(secrets);
+        self.backend.lock().map_err(|e| e.to_string())?.save(&cache)?;
+
+        log::info!("Imported {} secrets from a recovery file", count);
+        Ok(count)
+    }
+
+    /// How long the store may sit unused before it locks itself. None disables auto-lock.
+    pub fn auto_lock_after(&self) -> Option<Duration> {
+        self.session.lock().ok().and_then(|s| s.auto_lock_after)
+    }
+
+    pub fn set_auto_lock_after(&self, timeout: Option<Duration>) {
+        if let Ok(mut session) = self.session.lock() {
+            session.auto_lock_after = timeout;
         }
     }
 
-    pub fn set_secret(&self, key: &str, value: &str) -> Result<(), String> {
+    /// Locks the store if it has been idle past the auto-lock timeout. Called
+    /// on every access, and periodically so idle secrets leave memory on time.
+    pub fn lock_if_idle(&self) {
+        let idle = self.session.lock()
+            .map(|s| s.auto_lock_after.is_some_and(|timeout| s.last_used.elapsed() >= timeout))
+            .unwrap_or(false);
+        let unlocked = self.backend.lock()
+            .map(|b| b.is_passphrase_protected() && !b.is_locked())
+            .unwrap_or(false);
+        if idle && unlocked {
+            log::info!("Secret store idle, locking");
+            let _ = self.lock();
+        }
+    }
+
+    fn touch(&self) {
+        if let Ok(mut session) = self.session.lock() {
+            session.last_used = Instant::now();
+        }
+    }
+
+    /// The cache, once the store is known to be unlocked.
+    fn unlocked_cache(&self) -> Result<MutexGuard<'_, Secrets>, String> {
+        self.lock_if_idle();
+        if self.backend.lock().map_err(|e| e.to_string())?.is_locked() {
+            return Err("Secret store is locked".to_string());
+        }
+        self.touch();
+        self.cache.lock().map_err(|e| e.to_string())
+    }
+
+    pub fn set_secret(&self, key: &str, value: impl Into<SecretString>) -> Result<(), String> {
+        self.set_secret_for(key, value.into(), None)
+    }
+
+    /// Stores `value` under `key`, noting the provider it is a key for.
+    /// Replacing an existing value counts as a rotation.
+    pub fn set_secret_for(&self, key: &str, value: SecretString, provider: Option<&str>) -> Result<(), String> {
         log::info!("Storing secret for key: {}", key);
         
+        register_secret(value.expose_secret());
+
         // Update cache
-        let mut cache = self.cache.lock().map_err(|e| e.to_string())?;
-        cache.insert(key.to_string(), value.to_string());
+        let mut cache = self.unlocked_cache()?;
+        match cache.get_mut(key) {
+            Some(entry) => {
+                entry.rotate(value);
+                if let Some(provider) = provider {
+                    entry.metadata.provider = Some(provider.to_string());
+                }
+            },
+            None => {
+                cache.insert(key.to_string(), SecretEntry::new(value, provider));
+            },
+        }
         
         // Persist to file
-        self.backend.save(&cache)?;
+        self.backend.lock().map_err(|e| e.to_string())?.save(&cache)?;
         
         log::info!("Secret stored and persisted for key: {}", key);
         Ok(())
     }
 
-    pub fn get_secret(&self, key: &str) -> Result<Option<String>, String> {
+    /// The secret stored under `key`, sharing the cached buffer rather than
+    /// copying it. Each access is audited and updates the secret's `last_used_at`.
+    pub fn get_secret(&self, key: &str) -> Result<Option<SecretString>, String> {
         log::debug!("Retrieving secret for key: {}", key);
-        let cache = self.cache.lock().map_err(|e| e.to_string())?;
-        let result = cache.get(key).cloned();
-        
-        if result.is_some() {
-            log::debug!("Secret found for key: {}", key);
-        } else {
+        let mut cache = self.unlocked_cache()?;
+        let now = Utc::now();
+
+        let Some(entry) = cache.get_mut(key) else {
             log::debug!("Secret not found for key: {}", key);
+            self.audit("secret_accessed", serde_json::json!({"key": key, "found": false}));
+            return Ok(None);
+        };
+        log::debug!("Secret found for key: {}", key);
+
+        let persist = !entry.metadata.last_used_at
+            .is_some_and(|last_used| (now - last_used).num_minutes() < LAST_USED_RESOLUTION_MINUTES);
+        entry.metadata.last_used_at = Some(now);
+        let expired = entry.is_expired(now);
+        if expired {
+            log::warn!("Secret for key {} expired at {:?}", key, entry.metadata.expires_at);
         }
-        
-        Ok(result)
+        self.audit("secret_accessed", serde_json::json!({
+            "key": key,
+            "found": true,
+            "provider": entry.metadata.provider,
+            "expired": expired,
+        }));
+        let value = entry.value.clone();
+
+        // Usage tracking must not make the key unavailable
+        if persist {
+            if let Err(e) = self.backend.lock().map_err(|e| e.to_string())?.save(&cache) {
+                log::warn!("Failed to record use of secret {}: {}", key, e);
+            }
+        }
+        Ok(Some(value))
+    }
+
+    /// Metadata for every stored secret, sorted by key. Values are left out.
+    pub fn list_secrets(&self) -> Result<Vec<SecretInfo>, String> {
+        let cache = self.unlocked_cache()?;
+        let now = Utc::now();
+        let mut secrets = cache.iter()
+            .map(|(key, entry)| entry.info(key, now))
+            .collect::<Vec<_>>();
+        secrets.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(secrets)
+    }
+
+    /// Sets or clears when the secret under `key` expires.
+    pub fn set_expiry(&self, key: &str, expires_at: Option<DateTime<Utc>>) -> Result<(), String> {
+        let mut cache = self.unlocked_cache()?;
+        let entry = cache.get_mut(key).ok_or_else(|| format!("No secret stored for key: {}", key))?;
+        entry.metadata.expires_at = expires_at;
+        self.backend.lock().map_err(|e| e.to_string())?.save(&cache)
     }
 
     pub fn delete_secret(&self, key: &str) -> Result<(), String> {
         log::info!("Deleting secret for key: {}", key);
         
         // Update cache
-        let mut cache = self.cache.lock().map_err(|e| e.to_string())?;
+        let mut cache = self.unlocked_cache()?;
         cache.remove(key);
         
         // Persist to file
-        self.backend.save(&cache)?;
+        self.backend.lock().map_err(|e| e.to_string())?.save(&cache)?;
         
         log::info!("Secret deleted and persisted for key: {}", key);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret_store::file_backend::FileBackend;
+    use crate::secret_store::keyring_backend::{tests::MockSecretService, KeyringBackend};
+    use crate::secret_store::memory_backend::MemoryBackend;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_lock_unlock_and_auto_lock() {
+        let dir = tempdir().unwrap();
+        let backend = FileBackend::open(dir.path().join("secrets.enc"), dir.path().join("secrets.enc.bak")).unwrap();
+        let store = SecretStore::from_backend("test", Box::new(backend));
+
+        store.set_secret("openai_api_key", "sk-store-test-value").unwrap();
+        assert!(!store.is_locked());
+        // Machine-ID mode can't be locked
+        store.lock().unwrap();
+        assert_eq!(store.get_secret("openai_api_key").unwrap().as_ref().map(SecretString::expose_secret), Some("sk-store-test-value"));
+
+        store.set_passphrase(None, "correct horse battery staple").unwrap();
+        store.lock().unwrap();
+        assert!(store.is_locked());
+        assert_eq!(store.get_secret("openai_api_key").unwrap_err(), "Secret store is locked");
+        assert!(store.unlock("wrong horse").is_err());
+
+        store.unlock("correct horse battery staple").unwrap();
+        assert_eq!(store.get_secret("openai_api_key").unwrap().as_ref().map(SecretString::expose_secret), Some("sk-store-test-value"));
+        assert!(store.set_passphrase(Some("wrong horse"), "another passphrase").is_err());
+
+        store.set_auto_lock_after(Some(Duration::ZERO));
+        assert!(store.is_locked());
+        store.set_auto_lock_after(None);
+        store.unlock("correct horse battery staple").unwrap();
+        assert!(!store.is_locked());
+
+        store.remove_passphrase("correct horse battery staple").unwrap();
+        assert!(!store.is_passphrase_protected());
+    }
+
+    #[test]
+    fn test_migrate_between_backends() {
+        let store = SecretStore::from_backend("test", Box::new(MemoryBackend::default()));
+        store.set_secret("anthropic_api_key", "sk-ant-migrate-test").unwrap();
+        assert_eq!(store.status().backend, BackendKind::Memory);
+
+        let service = MockSecretService::default();
+        let keyring = KeyringBackend::with_credentials("test", Box::new(service.clone())).unwrap();
+        assert_eq!(store.migrate_to(Box::new(keyring)).unwrap(), 1);
+        assert_eq!(store.status().backend, BackendKind::Keyring);
+        assert_eq!(store.get_secret("anthropic_api_key").unwrap().as_ref().map(SecretString::expose_secret), Some("sk-ant-migrate-test"));
+
+        // Later writes land in the keyring
+        store.set_secret("openai_api_key", "sk-migrate-test-2").unwrap();
+        let reopened = KeyringBackend::with_credentials("test", Box::new(service.clone())).unwrap();
+        assert_eq!(reopened.load().unwrap().len(), 2);
+
+        let again = KeyringBackend::with_credentials("test", Box::new(service)).unwrap();
+        assert!(store.migrate_to(Box::new(again)).is_err());
+
+        // Back to memory; the keyring is emptied
+        assert_eq!(store.migrate_to(Box::new(MemoryBackend::default())).unwrap(), 2);
+        assert!(reopened.load().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_metadata_and_access_audit() {
+        let dir = tempdir().unwrap();
+        let audit_path = dir.path().join("audit.jsonl");
+        let store = SecretStore::from_backend("test", Box::new(MemoryBackend::default()))
+            .with_audit_logger(Arc::new(AuditLogger::new(audit_path.clone())));
+
+        store.set_secret_for("openai_api_key", "sk-metadata-test-1".into(), Some("openai")).unwrap();
+        let info = &store.list_secrets().unwrap()[0];
+        assert_eq!(info.key, "openai_api_key");
+        assert_eq!(info.metadata.provider.as_deref(), Some("openai"));
+        assert!(info.metadata.created_at.is_some() && info.metadata.last_used_at.is_none());
+
+        assert_eq!(store.get_secret("openai_api_key").unwrap().as_ref().map(SecretString::expose_secret), Some("sk-metadata-test-1"));
+        assert!(store.get_secret("gemini_api_key").unwrap().is_none());
+        let info = &store.list_secrets().unwrap()[0];
+        assert!(info.metadata.last_used_at.is_some() && !info.stale);
+
+        // Replacing the value is a rotation and keeps the provider
+        store.set_secret("openai_api_key", "sk-metadata-test-2").unwrap();
+        store.set_expiry("openai_api_key", Some(Utc::now())).unwrap();
+        let info = &store.list_secrets().unwrap()[0];
+        assert!(info.metadata.last_rotated_at.is_some() && info.expired);
+        assert_eq!(info.metadata.provider.as_deref(), Some("openai"));
+        assert!(store.set_expiry("gemini_api_key", None).is_err());
+
+        // Accesses are audited, values never are
+        let audit = std::fs::read_to_string(&audit_path).unwrap();
+        assert_eq!(audit.lines().filter(|line| line.contains("\"secret_accessed\"")).count(), 2);
+        assert!(audit.contains("\"found\":false"));
+        assert!(!audit.contains("sk-metadata-test"));
+    }
+
+    #[test]
+    fn test_rotate_and_recover() {
+        let dir = tempdir().unwrap();
+        let (file_path, backup_path) = (dir.path().join("secrets.enc"), dir.path().join("secrets.enc.bak"));
+        let backend = FileBackend::open(file_path.clone(), backup_path.clone()).unwrap();
+        let store = SecretStore::from_backend("test", Box::new(backend));
+        store.set_secret("openai_api_key", "sk-rotate-test-value").unwrap();
+        let before = std::fs::read(&file_path).unwrap();
+
+        store.rotate_master_key(None).unwrap();
+        assert_ne!(std::fs::read(&file_path).unwrap(), before);
+        let reopened = FileBackend::open(file_path.clone(), backup_path.clone()).unwrap();
+        assert_eq!(reopened.load().unwrap()["openai_api_key"].value.expose_secret(), "sk-rotate-test-value");
+
+        store.set_passphrase(None, "correct horse battery staple").unwrap();
+        assert!(store.rotate_master_key(None).is_err());
+        assert!(store.rotate_master_key(Some("wrong horse")).is_err());
+        store.rotate_master_key(Some("correct horse battery staple")).unwrap();
+
+        // A recovery file opens elsewhere with its own passphrase
+        let recovery = dir.path().join("recovery.json");
+        assert_eq!(store.export_recovery(&recovery, "recovery passphrase").unwrap(), 1);
+        let elsewhere = SecretStore::from_backend("test", Box::new(MemoryBackend::default()));
+        elsewhere.set_secret("gemini_api_key", "AIza-rotate-test-value").unwrap();
+        assert_eq!(elsewhere.import_recovery(&recovery, "wrong passphrase").unwrap_err(), "Incorrect recovery passphrase");
+        assert_eq!(elsewhere.import_recovery(&recovery, "recovery passphrase").unwrap(), 1);
+        assert_eq!(elsewhere.get_secret("openai_api_key").unwrap().as_ref().map(SecretString::expose_secret), Some("sk-rotate-test-value"));
+        assert!(elsewhere.get_secret("gemini_api_key").unwrap().is_some());
+        assert!(elsewhere.import_recovery(&file_path.with_extension("missing"), "recovery passphrase").is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+use chrono::Utc;
+use uuid::Uuid;
+
+pub const DEFAULT_CONVERSATION_TITLE: &str = "New conversation";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Conversation {
+    pub id: String,
+    pub title: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredMessage {
+    pub id: String,
+    pub conversation_id: String,
+    pub role: String,
+    pub content: String,
+    pub created_at: String,
+}
+
+pub struct ConversationStore {
+    conn: Connection,
+}
+
+impl ConversationStore {
+    pub fn new(conn: Connection) -> Result<Self> {
+        let store = ConversationStore { conn };
+        store.init_schema()?;
+        Ok(store)
+    }
+
+    fn init_schema(&self) -> Result<()> {
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS conversations (
+                id TEXT PRIMARY KEY,
+                title TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS messages (
+                id TEXT PRIMARY KEY,
+                conversation_id TEXT NOT NULL,
+                role TEXT NOT NULL,
+                content TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        self.conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_messages_conversation ON messages(conversation_id, created_at)",
+            [],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn create(&self, title: Option<&str>) -> Result<Conversation> {
+        let now = Utc::now().to_rfc3339();
+        let conversation = Conversation {
+            id: Uuid::new_v4().to_string(),
+            title: title.unwrap_or(DEFAULT_CONVERSATION_TITLE).to_string(),
+            created_at: now.clone(),
+            updated_at: now,
+        };
+
+        self.conn.execute(
+            "INSERT INTO conversations (id, title, created_at, updated_at) VALUES (?1, ?2, ?3, ?4)",
+            params![conversation.id, conversation.title, conversation.created_at, conversation.updated_at],
+        )?;
+
+        Ok(conversation)
+    }
+
+    pub fn get(&self, id: &str) -> Result<Option<Conversation>> {
+        self.conn.query_row(
+            "SELECT id, title, created_at, updated_at FROM conversations WHERE id = ?1",
+            params![id],
+            |row| {
+                Ok(Conversation {
+                    id: row.get(0)?,
+                    title: row.get(1)?,
+                    created_at: row.get(2)?,
+                    updated_at: row.get(3)?,
+                })
+            },
+        ).optional()
+    }
+
+    /// Lists conversations, most recently active first.
+    pub fn list(&self) -> Result<Vec<Conversation>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, title, created_at, updated_at FROM conversations ORDER BY updated_at DESC"
+        )?;
+
+        let conversations = stmt.query_map([], |row| {
+            Ok(Conversation {
+                id: row.get(0)?,
+                title: row.get(1)?,
+                created_at: row.get(2)?,
+                updated_at: row.get(3)?,
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+        Ok(conversations)
+    }
+
+    pub fn rename(&self, id: &str, title: &str) -> Result<bool> {
+        let updated = self.conn.execute(
+            "UPDATE conversations SET title = ?1, updated_at = ?2 WHERE id = ?3",
+            params![title, Utc::now().to_rfc3339(), id],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// Deletes a conversation together with all of its messages.
+    pub fn delete(&mut self, id: &str) -> Result<bool> {
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM messages WHERE conversation_id = ?1", params![id])?;
+        let deleted = tx.execute("DELETE FROM conversations WHERE id = ?1", params![id])?;
+        tx.commit()?;
+        Ok(deleted > 0)
+    }
+
+    pub fn add_message(&self, conversation_id: &str, role: &str, content: &str) -> Result<StoredMessage> {
+        let message = StoredMessage {
+            id: Uuid::new_v4().to_string(),
+            conversation_id: conversation_id.to_string(),
+            role: role.to_string(),
+            content: content.to_string(),
+            created_at: Utc::now().to_rfc3339(),
+        };
+
+        self.conn.execute(
+            "INSERT INTO messages (id, conversation_id, role, content, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![message.id, message.conversation_id, message.role, message.content, message.created_at],
+        )?;
+
+        self.conn.execute(
+            "UPDATE conversations SET updated_at = ?1 WHERE id = ?2",
+            params![message.created_at, conversation_id],
+        )?;
+
+        Ok(message)
+    }
+
+    /// Returns the messages of a conversation in the order they were written.
+    pub fn get_messages(&self, conversation_id: &str) -> Result<Vec<StoredMessage>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, conversation_id, role, content, created_at
+             FROM messages
+             WHERE conversation_id = ?1
+             ORDER BY created_at ASC, rowid ASC"
+        )?;
+
+        let messages = stmt.query_map(params![conversation_id], |row| {
+            Ok(StoredMessage {
+                id: row.get(0)?,
+                conversation_id: row.get(1)?,
+                role: row.get(2)?,
+                content: row.get(3)?,
+                created_at: row.get(4)?,
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+        Ok(messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_conversation_lifecycle() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let mut store = ConversationStore::new(Connection::open(db_path).unwrap()).unwrap();
+
+        let conversation = store.create(None).unwrap();
+        assert_eq!(conversation.title, DEFAULT_CONVERSATION_TITLE);
+
+        store.add_message(&conversation.id, "user", "Hi").unwrap();
+        store.add_message(&conversation.id, "assistant", "Hello!").unwrap();
+        store.add_message(&conversation.id, "user", "How are you?").unwrap();
+
+        let messages = store.get_messages(&conversation.id).unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0].content, "Hi");
+        assert_eq!(messages[2].role, "user");
+
+        assert!(store.rename(&conversation.id, "Greetings").unwrap());
+        assert_eq!(store.get(&conversation.id).unwrap().unwrap().title, "Greetings");
+
+        assert!(store.delete(&conversation.id).unwrap());
+        assert!(store.get(&conversation.id).unwrap().is_none());
+        assert!(store.get_messages(&conversation.id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_list_orders_by_activity() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let store = ConversationStore::new(Connection::open(db_path).unwrap()).unwrap();
+
+        let first = store.create(Some("First")).unwrap();
+        let second = store.create(Some("Second")).unwrap();
+        store.add_message(&first.id, "user", "bump").unwrap();
+
+        let list = store.list().unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].id, first.id);
+        assert_eq!(list[1].id, second.id);
+    }
+}
@@ -6,6 +6,8 @@ use tauri::AppHandle;
 use tauri::Manager;
 use uuid::Uuid;
 use chrono::Utc;
+use crate::storage::conversations::{Conversation, ConversationStore, StoredMessage};
+use crate::redact::redact_value;
 
 #[derive(Clone)]
 pub struct StorageManager {
@@ -190,8 +192,8 @@ impl StorageManager {
             params![
                 id, 
                 task_id, 
-                input.to_string(), 
-                output.to_string(), 
+                redact_value(&input).to_string(),
+                redact_value(&output).to_string(),
                 rationale, 
                 timestamp
             ],
@@ -228,6 +230,12 @@ impl StorageManager {
         }
     }
 
+    pub fn delete_preference(&self, key: &str) -> Result<()> {
+        let conn = self.get_connection()?;
+        conn.execute("DELETE FROM preferences WHERE key = ?1", params![key])?;
+        Ok(())
+    }
+
     pub fn export_all(&self) -> Result<ExportData> {
         let conn = self.get_connection()?;
 
@@ -323,6 +331,39 @@ impl StorageManager {
         let tracker = crate::storage::usage::UsageTracker::new(conn)?;
         tracker.get_total_cost(days)
     }
+
+    // Conversation methods
+    fn conversations(&self) -> Result<ConversationStore> {
+        ConversationStore::new(self.get_connection()?)
+    }
+
+    pub fn create_conversation(&self, title: Option<&str>) -> Result<Conversation> {
+        self.conversations()?.create(title)
+    }
+
+    pub fn get_conversation(&self, id: &str) -> Result<Option<Conversation>> {
+        self.conversations()?.get(id)
+    }
+
+    pub fn list_conversations(&self) -> Result<Vec<Conversation>> {
+        self.conversations()?.list()
+    }
+
+    pub fn rename_conversation(&self, id: &str, title: &str) -> Result<bool> {
+        self.conversations()?.rename(id, title)
+    }
+
+    pub fn delete_conversation(&self, id: &str) -> Result<bool> {
+        self.conversations()?.delete(id)
+    }
+
+    pub fn add_message(&self, conversation_id: &str, role: &str, content: &str) -> Result<StoredMessage> {
+        self.conversations()?.add_message(conversation_id, role, content)
+    }
+
+    pub fn get_messages(&self, conversation_id: &str) -> Result<Vec<StoredMessage>> {
+        self.conversations()?.get_messages(conversation_id)
+    }
 }
 
 #[cfg(test)]
@@ -363,6 +404,9 @@ mod tests {
         
         let val = storage.get_preference("theme").unwrap().unwrap();
         assert_eq!(val, "dark");
+
+        storage.delete_preference("theme").unwrap();
+        assert!(storage.get_preference("theme").unwrap().is_none());
     }
 
     #[test]
@@ -382,4 +426,24 @@ mod tests {
         assert_eq!(export.decisions.len(), 1);
         assert_eq!(export.preferences["k"], "v");
     }
+
+    #[test]
+    fn test_decisions_are_redacted_on_disk() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let storage = StorageManager::new_with_path(db_path.clone());
+
+        crate::redact::register_secret("decision-test-secret-value");
+        storage.record_decision(
+            None,
+            serde_json::json!({"provider": "openai"}),
+            serde_json::json!({"error": "OpenAI API Error 401: decision-test-secret-value"}),
+            None,
+        ).unwrap();
+
+        let raw = std::fs::read(db_path).unwrap();
+        assert!(!String::from_utf8_lossy(&raw).contains("decision-test-secret-value"));
+        let export = storage.export_all().unwrap();
+        assert_eq!(export.decisions[0].decision_output["error"], "OpenAI API Error 401: [REDACTED]");
+    }
 }
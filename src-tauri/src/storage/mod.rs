@@ -1,7 +1,9 @@
 pub mod manager;
 pub mod usage;
 pub mod pricing;
+pub mod conversations;
 
 pub use manager::StorageManager;
 pub use usage::{UsageTracker, UsageRecord, UsageStats};
 pub use pricing::{PricingCalculator, estimate_tokens};
+pub use conversations::{Conversation, StoredMessage};
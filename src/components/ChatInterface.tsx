@@ -1,5 +1,15 @@
 import { useState } from "react";
 import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
+import { errorMessage } from "../errors";
+
+interface StreamChunk {
+  request_id: string;
+  delta: string;
+  done: boolean;
+  finish_reason: string | null;
+  truncated: boolean;
+}
 
 export function ChatInterface() {
   const [input, setInput] = useState("");
@@ -15,12 +25,34 @@ export function ChatInterface() {
     setLoading(true);
     setHistory(prev => [...prev, { role: "user", content: prompt }]);
 
+    const requestId = crypto.randomUUID();
+    let started = false;
+    const unlisten = await listen<StreamChunk>("prompt-stream", (event) => {
+      const chunk = event.payload;
+      if (chunk.request_id !== requestId) return;
+      if (chunk.done) {
+        if (chunk.truncated) {
+          setHistory(prev => [...prev, { role: "system", content: "Reply was cut off at the output token limit." }]);
+        }
+        return;
+      }
+      if (!started) {
+        started = true;
+        setHistory(prev => [...prev, { role: "assistant", content: chunk.delta }]);
+        return;
+      }
+      setHistory(prev => {
+        const last = prev[prev.length - 1];
+        return [...prev.slice(0, -1), { ...last, content: last.content + chunk.delta }];
+      });
+    });
+
     try {
-      const response = await invoke<string>("submit_prompt", { prompt });
-      setHistory(prev => [...prev, { role: "assistant", content: response }]);
+      await invoke<string>("submit_prompt_stream", { prompt, requestId });
     } catch (err) {
-      setHistory(prev => [...prev, { role: "error", content: String(err) }]);
+      setHistory(prev => [...prev, { role: "error", content: errorMessage(err) }]);
     } finally {
+      unlisten();
       setLoading(false);
     }
   };
@@ -1,18 +1,114 @@
-import { useState } from "react";
+import { useEffect, useState } from "react";
 import { invoke } from "@tauri-apps/api/core";
+import { errorMessage } from "../errors";
+import { ProviderHealth, healthSummary } from "../health";
+
+/** Whether prompts may leave this machine (serialized `EgressPolicy`). */
+interface EgressPolicy {
+  network_egress_consent: boolean;
+  local_only: boolean;
+}
+
+/** Serialized `SecretStoreStatus`. */
+interface SecretStoreStatus {
+  backend: "keyring" | "file" | "memory";
+  passphrase_protected: boolean;
+  locked: boolean;
+  auto_lock_minutes: number | null;
+}
+
+/** Serialized `SecretInfo`: a stored secret's metadata, never its value. */
+interface SecretInfo {
+  key: string;
+  provider: string | null;
+  created_at: string | null;
+  last_used_at: string | null;
+  last_rotated_at: string | null;
+  expires_at: string | null;
+  expired: boolean;
+  stale: boolean;
+}
+
+/** The fields of a custom `ProviderConfig` the settings panel shows. */
+interface CustomProvider {
+  provider: { Custom: string };
+  display_name: string | null;
+}
 
 export function Settings() {
   const [provider, setProvider] = useState("Gemini");
   const [key, setKey] = useState("");
   const [model, setModel] = useState("");
   const [message, setMessage] = useState("");
+  const [customProviders, setCustomProviders] = useState<CustomProvider[]>([]);
+  const [egress, setEgress] = useState<EgressPolicy | null>(null);
+  const [store, setStore] = useState<SecretStoreStatus | null>(null);
+  const [secrets, setSecrets] = useState<SecretInfo[]>([]);
+  const [passphrase, setPassphrase] = useState("");
+  const [recoveryPath, setRecoveryPath] = useState("");
+  const [recoveryPassphrase, setRecoveryPassphrase] = useState("");
+
+  /** Stored key metadata; unavailable while the store is locked. */
+  const loadSecrets = () => {
+    invoke<SecretInfo[]>("list_secrets")
+      .then(setSecrets)
+      .catch(() => setSecrets([]));
+  };
+
+  useEffect(() => {
+    invoke<CustomProvider[]>("list_custom_providers")
+      .then(setCustomProviders)
+      .catch((err) => console.error("Failed to load custom providers:", err));
+    invoke<EgressPolicy>("get_egress_policy")
+      .then(setEgress)
+      .catch((err) => console.error("Failed to load egress policy:", err));
+    invoke<SecretStoreStatus>("get_secret_store_status")
+      .then(setStore)
+      .catch((err) => console.error("Failed to load secret store status:", err));
+    loadSecrets();
+  }, []);
+
+  /** Runs a secret store command with the passphrase field, then clears it. */
+  const updateStore = async (command: string, args: Record<string, unknown>, done: string) => {
+    try {
+      setStore(await invoke<SecretStoreStatus>(command, args));
+      setMessage(done);
+      loadSecrets();
+    } catch (err) {
+      setMessage(errorMessage(err));
+    } finally {
+      setPassphrase("");
+    }
+  };
+
+  /** Exports or imports a recovery file, then clears its passphrase. */
+  const recover = async (command: "export_secrets_recovery" | "import_secrets_recovery") => {
+    try {
+      const count = await invoke<number>(command, { path: recoveryPath, passphrase: recoveryPassphrase });
+      setMessage(command === "export_secrets_recovery" ? `Exported ${count} API keys.` : `Imported ${count} API keys.`);
+    } catch (err) {
+      setMessage(errorMessage(err));
+    } finally {
+      setRecoveryPassphrase("");
+    }
+  };
+
+  const toggleLocalOnly = async (enabled: boolean) => {
+    try {
+      setEgress(await invoke<EgressPolicy>("set_local_only", { enabled }));
+      setMessage(enabled ? "Local-only mode on: only Ollama and localhost providers will be used." : "Local-only mode off.");
+    } catch (err) {
+      setMessage(errorMessage(err));
+    }
+  };
 
   const saveKey = async () => {
     try {
-      await invoke("save_provider_key", { provider, apiKey: key });
+      // A key the provider rejects is refused rather than stored and enabled
+      await invoke("save_provider_key", { provider, apiKey: key, verify: true });
       setMessage("Key saved successfully.");
     } catch (err) {
-      setMessage(String(err));
+      setMessage(errorMessage(err));
     }
   };
 
@@ -21,7 +117,20 @@ export function Settings() {
       await invoke("update_provider_model", { provider, model });
       setMessage("Model updated.");
     } catch (err) {
-      setMessage(String(err));
+      setMessage(errorMessage(err));
+    }
+  };
+
+  const checkHealth = async () => {
+    try {
+      // Checks the key in the field if one was entered, otherwise the saved one
+      const health = await invoke<ProviderHealth>("check_provider_health", {
+        provider,
+        apiKey: key.trim() ? key : null,
+      });
+      setMessage(healthSummary(health));
+    } catch (err) {
+      setMessage(`Health check failed: ${errorMessage(err)}`);
     }
   };
 
@@ -30,7 +139,7 @@ export function Settings() {
       const result = await invoke<string>("test_keychain");
       setMessage(`Keychain test: ${result}`);
     } catch (err) {
-      setMessage(`Keychain test failed: ${String(err)}`);
+      setMessage(`Keychain test failed: ${errorMessage(err)}`);
     }
   };
 
@@ -39,7 +148,7 @@ export function Settings() {
       const result = await invoke<string>("reset_provider_config", { provider });
       setMessage(result);
     } catch (err) {
-      setMessage(`Reset failed: ${String(err)}`);
+      setMessage(`Reset failed: ${errorMessage(err)}`);
     }
   };
 
@@ -52,6 +161,11 @@ export function Settings() {
         <option value="Anthropic">Anthropic</option>
         <option value="DeepSeek">DeepSeek</option>
         <option value="OpenRouter">OpenRouter</option>
+        {customProviders.map((p) => (
+          <option key={p.provider.Custom} value={p.provider.Custom}>
+            {p.display_name ?? p.provider.Custom}
+          </option>
+        ))}
       </select>
 
       <input
@@ -63,6 +177,8 @@ export function Settings() {
 
       <button onClick={saveKey}>Save Key</button>
 
+      <button onClick={checkHealth}>Check Connection</button>
+
       <input
         type="text"
         value={model}
@@ -76,6 +192,93 @@ export function Settings() {
 
       <button onClick={testKeychain}>Test Keychain</button>
 
+      <label>
+        <input
+          type="checkbox"
+          checked={egress?.local_only ?? false}
+          onChange={(e) => toggleLocalOnly(e.target.checked)}
+        />
+        Local only (never send prompts to cloud providers)
+      </label>
+      {egress && !egress.network_egress_consent && (
+        <div className="settings-msg">Network egress consent was not given, so cloud providers are blocked.</div>
+      )}
+
+      <h3>Secret Storage</h3>
+      <select
+        value={store?.backend ?? "file"}
+        onChange={(e) => updateStore("migrate_secrets", { backend: e.target.value }, `API keys moved to the ${e.target.value} backend.`)}
+      >
+        <option value="keyring">OS keyring</option>
+        <option value="file">Encrypted file</option>
+        <option value="memory">Memory only (forgotten on exit)</option>
+      </select>
+
+      <h3>Master Passphrase</h3>
+      {store && (
+        <div className="settings-msg">
+          {!store.passphrase_protected
+            ? "API keys are encrypted with a key tied to this machine."
+            : store.locked
+              ? "Secret store is locked."
+              : `Secret store is unlocked${store.auto_lock_minutes ? `; locks after ${store.auto_lock_minutes} min idle` : ""}.`}
+        </div>
+      )}
+      <input
+        type="password"
+        value={passphrase}
+        onChange={(e) => setPassphrase(e.target.value)}
+        placeholder="Master passphrase"
+      />
+      {store?.locked ? (
+        <button onClick={() => updateStore("unlock_secret_store", { passphrase }, "Secret store unlocked.")}>Unlock</button>
+      ) : store?.passphrase_protected ? (
+        <button onClick={() => updateStore("lock_secret_store", {}, "Secret store locked.")}>Lock Now</button>
+      ) : (
+        <button onClick={() => updateStore("set_master_passphrase", { current: null, passphrase }, "Master passphrase set.")}>
+          Set Passphrase
+        </button>
+      )}
+      {store?.backend === "file" && !store.locked && (
+        <button
+          onClick={() =>
+            updateStore("rotate_master_key", { passphrase: store.passphrase_protected ? passphrase : null }, "Master key rotated.")
+          }
+        >
+          Rotate Key
+        </button>
+      )}
+
+      <h3>Stored API Keys</h3>
+      {secrets.length === 0 ? (
+        <div className="settings-msg">{store?.locked ? "Unlock the secret store to see stored keys." : "No API keys stored."}</div>
+      ) : (
+        <ul className="secret-list">
+          {secrets.map((secret) => (
+            <li key={secret.key}>
+              {secret.provider ?? secret.key}: last used{" "}
+              {secret.last_used_at ? new Date(secret.last_used_at).toLocaleDateString() : "never"}
+              {secret.expired && " (expired)"}
+              {secret.stale && !secret.expired && " (unused for 90+ days)"}
+            </li>
+          ))}
+        </ul>
+      )}
+
+      <h3>Recovery File</h3>
+      <div className="settings-msg">
+        A recovery file holds your API keys encrypted with its own passphrase, so they can be imported on a new machine.
+      </div>
+      <input value={recoveryPath} onChange={(e) => setRecoveryPath(e.target.value)} placeholder="Path to recovery file" />
+      <input
+        type="password"
+        value={recoveryPassphrase}
+        onChange={(e) => setRecoveryPassphrase(e.target.value)}
+        placeholder="Recovery passphrase"
+      />
+      <button onClick={() => recover("export_secrets_recovery")}>Export</button>
+      <button onClick={() => recover("import_secrets_recovery")}>Import</button>
+
       {message && <div className="settings-msg">{message}</div>}
     </div>
   );
@@ -1,5 +1,7 @@
 import { useState } from 'react';
 import { invoke } from '@tauri-apps/api/core';
+import { errorMessage } from '../../errors';
+import { ProviderHealth, healthSummary } from '../../health';
 
 interface WizardProps {
   onComplete: () => void;
@@ -63,6 +65,20 @@ function ContractStep({ onComplete }: { onComplete: () => void }) {
       return;
     }
 
+    try {
+      // Catch a mistyped key before it is stored and enabled
+      const health = await invoke<ProviderHealth>('check_provider_health', {
+        provider: 'Gemini',
+        apiKey: geminiKey
+      });
+      if (health.reachable && !health.auth_ok) {
+        setError(healthSummary(health));
+        return;
+      }
+    } catch (e) {
+      console.warn('Gemini key check failed:', e);
+    }
+
     try {
       const payload = {
         contractVersion: 'v1.0',
@@ -77,7 +93,7 @@ function ContractStep({ onComplete }: { onComplete: () => void }) {
       onComplete();
     } catch (e) {
       console.error('Error details:', e);
-      setError(`Failed to sign contract: ${String(e)}`);
+      setError(`Failed to sign contract: ${errorMessage(e)}`);
     }
   };
 
@@ -0,0 +1,30 @@
+/** Error returned by every Tauri command (serialized `SophiaError`). */
+export interface SophiaError {
+  kind:
+    | "Auth"
+    | "RateLimited"
+    | "QuotaExceeded"
+    | "Network"
+    | "ProviderUnavailable"
+    | "ProviderRejected"
+    | "ProviderResponseInvalid"
+    | "ContentFiltered"
+    | "NoProviderAvailable"
+    | "EgressBlocked"
+    | "RuntimePaused"
+    | "Cancelled"
+    | "Runtime"
+    | "Storage"
+    | "InvalidInput";
+  message: string;
+  retry_after_ms: number | null;
+}
+
+export function isSophiaError(err: unknown): err is SophiaError {
+  return typeof err === "object" && err !== null && "kind" in err && "message" in err;
+}
+
+/** Human-readable text for anything thrown by `invoke`. */
+export function errorMessage(err: unknown): string {
+  return isSophiaError(err) ? err.message : String(err);
+}
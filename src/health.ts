@@ -0,0 +1,22 @@
+/** Result of `check_provider_health` (serialized `ProviderHealth`). */
+export interface ProviderHealth {
+  provider: string;
+  reachable: boolean;
+  auth_ok: boolean;
+  latency_ms: number | null;
+  model: string;
+  model_available: boolean | null;
+  error_kind: string | null;
+  error: string | null;
+}
+
+/** One-line summary of a health check for status messages. */
+export function healthSummary(health: ProviderHealth): string {
+  if (!health.reachable) return `${health.provider} unreachable: ${health.error}`;
+  if (!health.auth_ok) return `${health.provider} rejected the key: ${health.error}`;
+  const parts = [`${health.provider} OK`];
+  if (health.latency_ms !== null) parts.push(`${health.latency_ms} ms`);
+  if (health.model_available === false) parts.push(`model ${health.model} not offered`);
+  if (health.error) parts.push(health.error);
+  return parts.join(", ");
+}